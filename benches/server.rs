@@ -0,0 +1,182 @@
+//! Benchmarks for the hot paths of `InteractiveMarkerServer`.
+//!
+//! Run with a sourced ROS 2 environment:
+//! ```
+//! cargo bench --bench server
+//! ```
+//!
+//! Throughput targets (release build, one desktop core, 10k markers):
+//! - `insert`: at least 1M markers/s staged.
+//! - `apply_changes` with full updates: at least 200k markers/s published.
+//! - `apply_changes` with pose updates: at least 1M poses/s published.
+//! - `handle_feedback` with POSE_UPDATE events: at least 1M events/s.
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use r2r::geometry_msgs::msg::Vector3;
+use r2r::std_msgs::msg::ColorRGBA;
+use r2r::visualization_msgs::msg::{
+    InteractiveMarker, InteractiveMarkerControl, InteractiveMarkerFeedback, Marker,
+};
+use r2r::Context;
+use r2r_interactive_markers::InteractiveMarkerServer;
+use std::sync::{Arc, Mutex};
+
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+fn make_server(rt: &tokio::runtime::Runtime, namespace: &str) -> InteractiveMarkerServer {
+    let _guard = rt.enter();
+    let context = Context::create().expect("Failed to create context");
+    let node = r2r::Node::create(context, "bench_server", "").expect("Failed to create node");
+    InteractiveMarkerServer::new(namespace, Arc::new(Mutex::new(node)))
+        .expect("Failed to create server")
+}
+
+fn make_marker(i: usize) -> InteractiveMarker {
+    let cube = Marker {
+        type_: Marker::CUBE as i32,
+        scale: Vector3 {
+            x: 0.1,
+            y: 0.1,
+            z: 0.1,
+        },
+        color: ColorRGBA {
+            a: 1.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let control = InteractiveMarkerControl {
+        interaction_mode: InteractiveMarkerControl::MOVE_3D as u8,
+        always_visible: true,
+        markers: vec![cube],
+        ..Default::default()
+    };
+
+    let mut marker = InteractiveMarker {
+        name: i.to_string(),
+        scale: 0.1,
+        controls: vec![control],
+        ..Default::default()
+    };
+    marker.header.frame_id = "base_link".to_string();
+    marker.pose.position.x = i as f64;
+    marker.pose.orientation.w = 1.0;
+    marker
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let server = make_server(&rt, "bench_insert");
+    let mut group = c.benchmark_group("insert");
+    for n in SIZES {
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter_batched(
+                || (0..n).map(make_marker).collect::<Vec<_>>(),
+                |markers| {
+                    for marker in markers {
+                        server.insert(marker);
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_apply_full(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let server = make_server(&rt, "bench_apply_full");
+    let mut group = c.benchmark_group("apply_changes_full");
+    for n in SIZES {
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter_batched(
+                || {
+                    for i in 0..n {
+                        server.insert(make_marker(i));
+                    }
+                },
+                |()| server.apply_changes(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_apply_poses(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let server = make_server(&rt, "bench_apply_poses");
+    let mut group = c.benchmark_group("apply_changes_poses");
+    for n in SIZES {
+        server.clear();
+        for i in 0..n {
+            server.insert(make_marker(i));
+        }
+        server.apply_changes().expect("Failed to apply changes");
+
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter_batched(
+                || {
+                    for i in 0..n {
+                        let mut pose = make_marker(i).pose;
+                        pose.position.y += 1.0;
+                        server.set_pose(i.to_string(), pose, None);
+                    }
+                },
+                |()| server.apply_changes(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_feedback(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let server = make_server(&rt, "bench_feedback");
+    let mut group = c.benchmark_group("handle_feedback");
+    for n in SIZES {
+        server.clear();
+        for i in 0..n {
+            server.insert(make_marker(i));
+        }
+        server.apply_changes().expect("Failed to apply changes");
+
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter_batched(
+                || {
+                    (0..n)
+                        .map(|i| InteractiveMarkerFeedback {
+                            client_id: "bench".to_string(),
+                            marker_name: i.to_string(),
+                            event_type: InteractiveMarkerFeedback::POSE_UPDATE as u8,
+                            pose: make_marker(i).pose,
+                            ..Default::default()
+                        })
+                        .collect::<Vec<_>>()
+                },
+                |feedbacks| {
+                    for feedback in feedbacks {
+                        server.handle_feedback(feedback);
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_insert,
+    bench_apply_full,
+    bench_apply_poses,
+    bench_feedback
+);
+criterion_main!(benches);
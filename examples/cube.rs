@@ -44,13 +44,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     let node_clone = node.clone();
-    let server = Arc::new(InteractiveMarkerServer::new("cube", node_clone));
+    let server = Arc::new(InteractiveMarkerServer::new("cube", node_clone)?);
 
     let positions = Arc::new(Mutex::new(Vec::new()));
 
     make_cube(server.clone(), positions.clone());
 
-    server.apply_changes();
+    server.apply_changes()?;
 
     r2r::log_info!(NODE_ID, "Node started.");
 
@@ -66,7 +66,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 server.set_pose(&i.to_string(), pose.clone(), None);
             }
         }
-        server.apply_changes();
+        server.apply_changes()?;
 
         node.lock()
             .unwrap()
@@ -78,6 +78,7 @@ fn make_cube(server: Arc<InteractiveMarkerServer>, positions: Arc<Mutex<Vec<[f64
     let side_length = 10;
     let step = 1.0 / side_length as f64;
     let mut count = 0;
+    let mut markers = Vec::with_capacity(side_length * side_length * side_length);
 
     for i in 0..side_length {
         let x = -0.5 + step * i as f64;
@@ -100,21 +101,23 @@ fn make_cube(server: Arc<InteractiveMarkerServer>, positions: Arc<Mutex<Vec<[f64
                 marker.name = count.to_string();
 
                 make_box_control(&mut marker);
-
-                let positions_clone = positions.clone();
-
-                // Create the feedback callback without capturing server_clone
-                let feedback_cb = Arc::new(move |feedback: InteractiveMarkerFeedback| {
-                    process_feedback(feedback, positions_clone.clone());
-                });
-
-                server.insert(marker);
-                server.set_callback(&count.to_string(), Some(feedback_cb), DEFAULT_FEEDBACK_CB);
+                markers.push(marker);
 
                 count += 1;
             }
         }
     }
+
+    server.insert_many(markers);
+    for name in 0..count {
+        let positions_clone = positions.clone();
+
+        // Create the feedback callback without capturing server_clone
+        let feedback_cb = Arc::new(move |feedback: InteractiveMarkerFeedback| {
+            process_feedback(feedback, positions_clone.clone());
+        });
+        server.set_callback(&name.to_string(), Some(feedback_cb), DEFAULT_FEEDBACK_CB);
+    }
 }
 
 fn make_box_control(marker: &mut InteractiveMarker) {
@@ -0,0 +1,88 @@
+use r2r::geometry_msgs::msg::{Point, Pose, PoseStamped, Quaternion};
+use r2r::visualization_msgs::msg::{InteractiveMarker, InteractiveMarkerControl, Marker};
+use r2r::{Context, QosProfile};
+use r2r_interactive_markers::InteractiveMarkerServer;
+use std::sync::{Arc, Mutex};
+
+pub static NODE_ID: &'static str = "sim_bridge";
+
+// Drag-to-teleport of a simulated box. Released marker poses are published on
+// /sim/box/set_pose and the simulated pose is read back from /sim/box/pose. With Gazebo, bridge
+// these topics to the entity with ros_gz_bridge, or call the simulator's set pose service from
+// the closure instead.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let context = Context::create()?;
+    let node = r2r::Node::create(context, NODE_ID, "")?;
+    let arc_node = Arc::new(Mutex::new(node));
+
+    let server = InteractiveMarkerServer::new("sim_bridge", arc_node.clone())?;
+
+    let mut interactive_marker = InteractiveMarker::default();
+    interactive_marker.header.frame_id = "world".to_string();
+    interactive_marker.name = "box".to_string();
+    interactive_marker.description = "Drag to teleport".to_string();
+    interactive_marker.scale = 1.0;
+    interactive_marker.pose = Pose {
+        position: Point { x: 0.0, y: 0.0, z: 0.5 },
+        orientation: Quaternion { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
+    };
+
+    let mut box_marker = Marker::default();
+    box_marker.type_ = Marker::CUBE as i32;
+    box_marker.scale.x = 0.5;
+    box_marker.scale.y = 0.5;
+    box_marker.scale.z = 0.5;
+    box_marker.color.g = 0.5;
+    box_marker.color.a = 1.0;
+    box_marker.pose.orientation.w = 1.0;
+
+    // Dragging in the ground plane moves the box around, the box itself is always shown
+    let mut move_control = InteractiveMarkerControl::default();
+    move_control.name = "move_plane".to_string();
+    move_control.orientation = Quaternion { x: 0.0, y: 1.0, z: 0.0, w: 1.0 };
+    move_control.interaction_mode = InteractiveMarkerControl::MOVE_PLANE as u8;
+    move_control.always_visible = true;
+    move_control.markers.push(box_marker);
+    interactive_marker.controls.push(move_control);
+
+    server.insert(interactive_marker);
+    server.apply_changes()?;
+
+    let set_pose_pub = arc_node
+        .lock()
+        .unwrap()
+        .create_publisher::<PoseStamped>("/sim/box/set_pose", QosProfile::default())?;
+    let sim_poses = arc_node
+        .lock()
+        .unwrap()
+        .subscribe::<PoseStamped>("/sim/box/pose", QosProfile::default())?;
+
+    server.bridge_sim_pose(
+        "box",
+        move |pose: PoseStamped| {
+            let result = set_pose_pub.publish(&pose);
+            async move {
+                if let Err(e) = result {
+                    r2r::log_error!(NODE_ID, "Failed to teleport the box: '{}'.", e);
+                }
+            }
+        },
+        sim_poses,
+    );
+
+    // Keep the node alive
+    let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
+    let handle = std::thread::spawn(move || loop {
+        arc_node_clone
+            .lock()
+            .unwrap()
+            .spin_once(std::time::Duration::from_millis(100));
+    });
+
+    r2r::log_info!(NODE_ID, "Node started.");
+
+    handle.join().unwrap();
+
+    Ok(())
+}
@@ -1,12 +1,12 @@
-use r2r_interactive_markers::InteractiveMarkerServer;
+use r2r_interactive_markers::{ControlBuilder, InteractiveMarkerBuilder, InteractiveMarkerServer};
 use r2r::std_msgs::msg::Header;
 use r2r::tf2_msgs::msg::TFMessage;
 use r2r::Context;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use r2r::visualization_msgs::msg::{InteractiveMarker, InteractiveMarkerControl, InteractiveMarkerFeedback, Marker};
+use r2r::visualization_msgs::msg::{InteractiveMarkerFeedback, Marker};
 use r2r::QosProfile;
-use r2r::geometry_msgs::msg::{Point, Pose, Quaternion, Transform, TransformStamped};
+use r2r::geometry_msgs::msg::{Transform, TransformStamped};
 
 pub static NODE_ID: &'static str = "simple_marker";
 const DEFAULT_FEEDBACK_CB: u8 = 255;
@@ -79,67 +79,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let server = InteractiveMarkerServer::new(
         "simple_marker",
         arc_node_clone
-    );
-
-    // Create an interactive marker
-    let mut interactive_marker = InteractiveMarker::default();
-    interactive_marker.header.frame_id = "base_link".to_string();
-    interactive_marker.name = "my_marker".to_string();
-    interactive_marker.description = "Simple 1-DoF Control".to_string();
-    interactive_marker.pose = Pose {
-        position: Point {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        },
-        orientation: Quaternion {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-            w: 1.0,
-        },
-    };
+    )?;
 
     // Create a grey box marker
     let mut box_marker = Marker::default();
-    box_marker.action = 0 as i32;
     box_marker.type_ = Marker::CUBE as i32;
     box_marker.scale.x = 0.45;
     box_marker.scale.y = 0.45;
     box_marker.scale.z = 0.45;
-    box_marker.color.r = 0.0;
     box_marker.color.b = 0.5;
     box_marker.color.g = 0.5;
     box_marker.color.a = 1.0;
-    box_marker.pose = Pose {
-        position: Point {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        },
-        orientation: Quaternion {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-            w: 1.0,
-        },
-    };
-
-    // Create a non-interactive control which contains the box
-    let mut box_control = InteractiveMarkerControl::default();
-    box_control.always_visible = true;
-    box_control.markers.push(box_marker);
-
-    // Add the control to the interactive marker
-    interactive_marker.controls.push(box_control);
-
-    // Create a control which will move the box along the x-axis
-    let mut move_control = InteractiveMarkerControl::default();
-    move_control.name = "move_x".to_string();
-    move_control.interaction_mode = InteractiveMarkerControl::MOVE_AXIS as u8;
+    box_marker.pose.orientation.w = 1.0;
 
-    // Add the control to the interactive marker
-    interactive_marker.controls.push(move_control);
+    // Create an interactive marker with a non-interactive control which contains the box, and
+    // a control which will move the box along the x-axis
+    let interactive_marker = InteractiveMarkerBuilder::new("my_marker")
+        .frame("base_link")
+        .description("Simple 1-DoF Control")
+        .control(ControlBuilder::new().always_visible(true).marker(box_marker))
+        .control(ControlBuilder::move_axis_x())
+        .build();
 
     // Insert the marker into the server
     server.insert(interactive_marker);
@@ -158,7 +118,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     // Apply changes to publish updates
-    server.apply_changes();
+    server.apply_changes()?;
 
     // Keep the node alive
     let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
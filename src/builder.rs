@@ -0,0 +1,153 @@
+use crate::{clock, spawn, InteractiveMarkerServer, Result, ServerConfig};
+use r2r::QosProfile;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+
+/// Builds an `InteractiveMarkerServer` with its QoS profiles and topic layout set before the
+/// endpoints are created. Options not covered by a method can be set through `config`.
+pub struct ServerBuilder {
+    topic_namespace: String,
+    node: Arc<Mutex<r2r::Node>>,
+    config: ServerConfig,
+    runtime: Option<Handle>,
+    clock: clock::Clock,
+}
+
+impl ServerBuilder {
+    pub(crate) fn new(topic_namespace: &str, node: Arc<Mutex<r2r::Node>>) -> Self {
+        Self {
+            topic_namespace: topic_namespace.to_string(),
+            node,
+            config: ServerConfig::default(),
+            runtime: None,
+            clock: clock::Clock::System,
+        }
+    }
+
+    /// Replaces the whole configuration, including anything set before.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn update_qos(mut self, qos: QosProfile) -> Self {
+        self.config.update_qos = qos;
+        self
+    }
+
+    pub fn feedback_qos(mut self, qos: QosProfile) -> Self {
+        self.config.feedback_qos = Some(qos);
+        self
+    }
+
+    /// Sets the depth of the feedback subscription, also in a profile set with `feedback_qos`.
+    pub fn feedback_queue_depth(mut self, depth: usize) -> Self {
+        self.config.feedback_queue_depth = depth;
+        if let Some(qos) = self.config.feedback_qos.take() {
+            self.config.feedback_qos = Some(qos.keep_last(depth));
+        }
+        self
+    }
+
+    pub fn init_qos(mut self, qos: QosProfile) -> Self {
+        self.config.init_qos = qos;
+        self
+    }
+
+    pub fn service_qos(mut self, qos: QosProfile) -> Self {
+        self.config.service_qos = qos;
+        self
+    }
+
+    pub fn get_interactive_markers_service(mut self, enabled: bool) -> Self {
+        self.config.get_interactive_markers_service = enabled;
+        self
+    }
+
+    pub fn poses_service(mut self, enabled: bool) -> Self {
+        self.config.poses_service = enabled;
+        self
+    }
+
+    /// Publishes updates on `name`, relative to the topic namespace unless it starts with `/`.
+    pub fn update_topic(mut self, name: &str) -> Self {
+        self.config.update_topic = name.to_string();
+        self
+    }
+
+    /// Subscribes to feedback on `name`, relative to the topic namespace unless it starts with
+    /// `/`.
+    pub fn feedback_topic(mut self, name: &str) -> Self {
+        self.config.feedback_topic = name.to_string();
+        self
+    }
+
+    /// Serves `get_interactive_markers` as `name`, relative to the topic namespace unless it
+    /// starts with `/`.
+    pub fn service_name(mut self, name: &str) -> Self {
+        self.config.service_name = name.to_string();
+        self
+    }
+
+    pub fn update_shards(mut self, shards: usize) -> Self {
+        self.config.update_shards = shards;
+        self
+    }
+
+    pub fn legacy_init_topic(mut self, enabled: bool) -> Self {
+        self.config.legacy_init_topic = enabled;
+        self
+    }
+
+    pub fn snapshot_topic(mut self, enabled: bool) -> Self {
+        self.config.snapshot_topic = enabled;
+        self
+    }
+
+    /// Spawns the background work on `runtime`, see `InteractiveMarkerServer::new_with_handle`.
+    pub fn runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Takes times from `clock` instead of the system clock, e.g. the node's ROS clock, which
+    /// follows /clock under `use_sim_time`. Pose updates without a stamp are stamped with it when
+    /// published, and interaction times in `stats`, `client_activity` and the diagnostics are
+    /// its times since `UNIX_EPOCH`.
+    pub fn clock(mut self, clock: Arc<Mutex<r2r::Clock>>) -> Self {
+        self.clock = clock::Clock::Ros(clock);
+        self
+    }
+
+    pub fn build(self) -> Result<InteractiveMarkerServer> {
+        let spawner = match self.runtime {
+            Some(runtime) => spawn::Spawner::Handle(runtime),
+            None => spawn::Spawner::Ambient,
+        };
+        InteractiveMarkerServer::start(
+            &self.topic_namespace,
+            self.node,
+            self.config,
+            spawner,
+            self.clock,
+        )
+    }
+
+    /// Builds the server with `InteractiveMarkerServer::new_with_runner`, returning the future
+    /// that runs its background work instead of spawning it. A runtime set with `runtime` is
+    /// not used.
+    pub fn build_with_runner(
+        self,
+    ) -> Result<(InteractiveMarkerServer, impl Future<Output = ()> + Send + 'static)> {
+        let (spawner, run) = spawn::runner();
+        let server = InteractiveMarkerServer::start(
+            &self.topic_namespace,
+            self.node,
+            self.config,
+            spawner,
+            self.clock,
+        )?;
+        Ok((server, run))
+    }
+}
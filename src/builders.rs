@@ -0,0 +1,278 @@
+// Fluent builders for markers and controls.
+
+use crate::helpers::Axis;
+use r2r::geometry_msgs::msg::{Point, Pose, Quaternion};
+use r2r::visualization_msgs::msg::{
+    InteractiveMarker, InteractiveMarkerControl, Marker, MenuEntry,
+};
+
+fn identity() -> Quaternion {
+    Quaternion {
+        w: 1.0,
+        ..Default::default()
+    }
+}
+
+/// Builds an `InteractiveMarker`, starting at the identity pose with scale 1, e.g.
+/// `InteractiveMarkerBuilder::new("m1").frame("base_link").control(ControlBuilder::move_axis_x())`.
+#[derive(Clone, Debug)]
+pub struct InteractiveMarkerBuilder {
+    marker: InteractiveMarker,
+}
+
+impl InteractiveMarkerBuilder {
+    pub fn new(name: &str) -> Self {
+        Self {
+            marker: InteractiveMarker {
+                name: name.to_string(),
+                pose: Pose {
+                    position: Point::default(),
+                    orientation: identity(),
+                },
+                scale: 1.0,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.marker.name = name.to_string();
+        self
+    }
+
+    pub fn frame(mut self, frame_id: &str) -> Self {
+        self.marker.header.frame_id = frame_id.to_string();
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.marker.description = description.to_string();
+        self
+    }
+
+    pub fn pose(mut self, pose: Pose) -> Self {
+        self.marker.pose = pose;
+        self
+    }
+
+    pub fn position(mut self, x: f64, y: f64, z: f64) -> Self {
+        self.marker.pose.position = Point { x, y, z };
+        self
+    }
+
+    pub fn orientation(mut self, orientation: Quaternion) -> Self {
+        self.marker.pose.orientation = orientation;
+        self
+    }
+
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.marker.scale = scale;
+        self
+    }
+
+    pub fn control(mut self, control: impl Into<InteractiveMarkerControl>) -> Self {
+        self.marker.controls.push(control.into());
+        self
+    }
+
+    pub fn controls(
+        mut self,
+        controls: impl IntoIterator<Item = InteractiveMarkerControl>,
+    ) -> Self {
+        self.marker.controls.extend(controls);
+        self
+    }
+
+    /// Adds a menu entry that sends MENU_SELECT feedback, under `parent_id` or at the top level
+    /// with 0.
+    pub fn menu_entry(mut self, id: u32, parent_id: u32, title: &str) -> Self {
+        self.marker.menu_entries.push(MenuEntry {
+            id,
+            parent_id,
+            title: title.to_string(),
+            command_type: MenuEntry::FEEDBACK as u8,
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn build(self) -> InteractiveMarker {
+        self.marker
+    }
+}
+
+impl From<InteractiveMarkerBuilder> for InteractiveMarker {
+    fn from(builder: InteractiveMarkerBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// Builds an `InteractiveMarkerControl`. The constructors named after interaction modes set
+/// the mode, name and an orientation along the given axis of the marker.
+#[derive(Clone, Debug)]
+pub struct ControlBuilder {
+    control: InteractiveMarkerControl,
+}
+
+impl Default for ControlBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ControlBuilder {
+    /// A control without interaction, at the marker's orientation.
+    pub fn new() -> Self {
+        Self {
+            control: InteractiveMarkerControl {
+                orientation: identity(),
+                interaction_mode: InteractiveMarkerControl::NONE as u8,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn with_mode(name: &str, interaction_mode: u8, orientation: Quaternion) -> Self {
+        Self::new()
+            .name(name)
+            .interaction_mode(interaction_mode)
+            .orientation(orientation)
+    }
+
+    pub fn move_axis_x() -> Self {
+        Self::with_mode(
+            "move_x",
+            InteractiveMarkerControl::MOVE_AXIS as u8,
+            Axis::X.orientation(),
+        )
+    }
+
+    pub fn move_axis_y() -> Self {
+        Self::with_mode(
+            "move_y",
+            InteractiveMarkerControl::MOVE_AXIS as u8,
+            Axis::Y.orientation(),
+        )
+    }
+
+    pub fn move_axis_z() -> Self {
+        Self::with_mode(
+            "move_z",
+            InteractiveMarkerControl::MOVE_AXIS as u8,
+            Axis::Z.orientation(),
+        )
+    }
+
+    pub fn rotate_axis_x() -> Self {
+        Self::with_mode(
+            "rotate_x",
+            InteractiveMarkerControl::ROTATE_AXIS as u8,
+            Axis::X.orientation(),
+        )
+    }
+
+    pub fn rotate_axis_y() -> Self {
+        Self::with_mode(
+            "rotate_y",
+            InteractiveMarkerControl::ROTATE_AXIS as u8,
+            Axis::Y.orientation(),
+        )
+    }
+
+    pub fn rotate_axis_z() -> Self {
+        Self::with_mode(
+            "rotate_z",
+            InteractiveMarkerControl::ROTATE_AXIS as u8,
+            Axis::Z.orientation(),
+        )
+    }
+
+    /// Moves in the plane normal to the marker's z axis.
+    pub fn move_plane_xy() -> Self {
+        Self::with_mode(
+            "move_xy",
+            InteractiveMarkerControl::MOVE_PLANE as u8,
+            Axis::Z.orientation(),
+        )
+    }
+
+    pub fn move_3d() -> Self {
+        Self::with_mode(
+            "move_3d",
+            InteractiveMarkerControl::MOVE_3D as u8,
+            identity(),
+        )
+    }
+
+    pub fn rotate_3d() -> Self {
+        Self::with_mode(
+            "rotate_3d",
+            InteractiveMarkerControl::ROTATE_3D as u8,
+            identity(),
+        )
+    }
+
+    pub fn move_rotate_3d() -> Self {
+        Self::with_mode(
+            "move_rotate_3d",
+            InteractiveMarkerControl::MOVE_ROTATE_3D as u8,
+            identity(),
+        )
+    }
+
+    pub fn button() -> Self {
+        Self::with_mode("button", InteractiveMarkerControl::BUTTON as u8, identity())
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.control.name = name.to_string();
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.control.description = description.to_string();
+        self
+    }
+
+    pub fn orientation(mut self, orientation: Quaternion) -> Self {
+        self.control.orientation = orientation;
+        self
+    }
+
+    /// One of `InteractiveMarkerControl::INHERIT`, `FIXED` or `VIEW_FACING`.
+    pub fn orientation_mode(mut self, orientation_mode: u8) -> Self {
+        self.control.orientation_mode = orientation_mode;
+        self
+    }
+
+    pub fn interaction_mode(mut self, interaction_mode: u8) -> Self {
+        self.control.interaction_mode = interaction_mode;
+        self
+    }
+
+    pub fn always_visible(mut self, always_visible: bool) -> Self {
+        self.control.always_visible = always_visible;
+        self
+    }
+
+    pub fn independent_marker_orientation(mut self, independent: bool) -> Self {
+        self.control.independent_marker_orientation = independent;
+        self
+    }
+
+    /// Adds a marker shown as the control.
+    pub fn marker(mut self, marker: Marker) -> Self {
+        self.control.markers.push(marker);
+        self
+    }
+
+    pub fn build(self) -> InteractiveMarkerControl {
+        self.control
+    }
+}
+
+impl From<ControlBuilder> for InteractiveMarkerControl {
+    fn from(builder: ControlBuilder) -> Self {
+        builder.build()
+    }
+}
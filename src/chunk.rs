@@ -0,0 +1,191 @@
+// Splitting of large updates. A full update of thousands of markers becomes a single enormous
+// message, so with ServerConfig::max_update_bytes it is split into several updates that are
+// published with consecutive sequence numbers, which clients apply one after the other.
+
+use r2r::std_msgs::msg::Header;
+use r2r::visualization_msgs::msg::{
+    InteractiveMarker, InteractiveMarkerControl, InteractiveMarkerPose, InteractiveMarkerUpdate,
+    Marker, MenuEntry,
+};
+
+const POSE: usize = 7 * 8;
+
+// Splits `update` into updates whose estimated size stays below `max_bytes`, except for single
+// markers larger than that, which get an update of their own. The parts have no sequence
+// numbers yet.
+pub(crate) fn split(
+    update: InteractiveMarkerUpdate,
+    max_bytes: usize,
+) -> Vec<InteractiveMarkerUpdate> {
+    // The server id, sequence number, type and the lengths of the three sequences
+    let base = string(&update.server_id) + 8 + 1 + 3 * 4;
+    if update_size(&update, base) <= max_bytes {
+        return vec![update];
+    }
+
+    let mut parts = Parts {
+        empty: InteractiveMarkerUpdate {
+            server_id: update.server_id,
+            type_: update.type_,
+            ..Default::default()
+        },
+        parts: Vec::new(),
+        size: base,
+        base,
+        max_bytes,
+    };
+    for marker in update.markers {
+        parts.part(marker_size(&marker)).markers.push(marker);
+    }
+    for pose in update.poses {
+        parts.part(pose_size(&pose)).poses.push(pose);
+    }
+    for name in update.erases {
+        parts.part(string(&name)).erases.push(name);
+    }
+    parts.parts
+}
+
+struct Parts {
+    empty: InteractiveMarkerUpdate,
+    parts: Vec<InteractiveMarkerUpdate>,
+    // Estimated size of the last part
+    size: usize,
+    base: usize,
+    max_bytes: usize,
+}
+
+impl Parts {
+    // The part to add an item of `item_size` to, starting a new one when the last one is full
+    fn part(&mut self, item_size: usize) -> &mut InteractiveMarkerUpdate {
+        if self.parts.is_empty()
+            || (self.size + item_size > self.max_bytes && self.size > self.base)
+        {
+            self.parts.push(self.empty.clone());
+            self.size = self.base;
+        }
+        self.size += item_size;
+        self.parts.last_mut().unwrap()
+    }
+}
+
+fn update_size(update: &InteractiveMarkerUpdate, base: usize) -> usize {
+    base + update.markers.iter().map(marker_size).sum::<usize>()
+        + update.poses.iter().map(pose_size).sum::<usize>()
+        + update.erases.iter().map(|name| string(name)).sum::<usize>()
+}
+
+// CDR sizes, ignoring alignment padding like the feedback sizes in stats. Strings are a length,
+// the bytes and a terminating nul.
+fn string(s: &str) -> usize {
+    4 + s.len() + 1
+}
+
+fn header_size(header: &Header) -> usize {
+    8 + string(&header.frame_id)
+}
+
+fn pose_size(pose: &InteractiveMarkerPose) -> usize {
+    header_size(&pose.header) + POSE + string(&pose.name)
+}
+
+fn marker_size(marker: &InteractiveMarker) -> usize {
+    header_size(&marker.header)
+        + POSE
+        + string(&marker.name)
+        + string(&marker.description)
+        + 4
+        + 4
+        + marker
+            .menu_entries
+            .iter()
+            .map(menu_entry_size)
+            .sum::<usize>()
+        + 4
+        + marker.controls.iter().map(control_size).sum::<usize>()
+}
+
+fn menu_entry_size(entry: &MenuEntry) -> usize {
+    4 + 4 + string(&entry.title) + string(&entry.command) + 1
+}
+
+fn control_size(control: &InteractiveMarkerControl) -> usize {
+    // Orientation, orientation mode, interaction mode, always visible, independent orientation
+    const FIXED: usize = 4 * 8 + 4;
+    string(&control.name)
+        + FIXED
+        + 4
+        + control.markers.iter().map(visual_size).sum::<usize>()
+        + string(&control.description)
+}
+
+fn visual_size(marker: &Marker) -> usize {
+    // Id, type, action, pose, scale, color, lifetime, frame_locked and the embedded materials flag
+    const FIXED: usize = 3 * 4 + POSE + 3 * 8 + 4 * 4 + 8 + 1 + 1;
+    header_size(&marker.header)
+        + string(&marker.ns)
+        + FIXED
+        + 4
+        + marker.points.len() * 3 * 8
+        + 4
+        + marker.colors.len() * 4 * 4
+        + string(&marker.text)
+        + string(&marker.mesh_resource)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(markers: usize) -> InteractiveMarkerUpdate {
+        InteractiveMarkerUpdate {
+            server_id: "server".to_string(),
+            type_: InteractiveMarkerUpdate::UPDATE as u8,
+            markers: (0..markers)
+                .map(|i| InteractiveMarker {
+                    name: format!("marker_{}", i),
+                    ..Default::default()
+                })
+                .collect(),
+            erases: vec!["gone".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn small_updates_stay_whole() {
+        let parts = split(update(3), 1 << 20);
+        assert_eq!(parts, [update(3)]);
+    }
+
+    #[test]
+    fn parts_stay_below_the_limit_in_order() {
+        let max_bytes = 400;
+        let parts = split(update(20), max_bytes);
+        assert!(parts.len() > 1);
+        let base = string("server") + 8 + 1 + 3 * 4;
+        for part in &parts {
+            assert!(update_size(part, base) <= max_bytes);
+            assert_eq!(part.server_id, "server");
+            assert_eq!(part.type_, InteractiveMarkerUpdate::UPDATE as u8);
+        }
+
+        let names: Vec<String> = parts
+            .iter()
+            .flat_map(|part| part.markers.iter().map(|marker| marker.name.clone()))
+            .collect();
+        let expected: Vec<String> = (0..20).map(|i| format!("marker_{}", i)).collect();
+        assert_eq!(names, expected);
+        assert_eq!(parts.last().unwrap().erases, ["gone"]);
+    }
+
+    #[test]
+    fn oversized_markers_get_a_part_of_their_own() {
+        let mut update = update(2);
+        update.markers[0].description = "x".repeat(1000);
+        let parts = split(update, 300);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].markers.len(), 1);
+        assert_eq!(parts[1].markers[0].name, "marker_1");
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{sync, Result};
+use futures::{Stream, StreamExt};
+use r2r::visualization_msgs::msg::{InteractiveMarker, InteractiveMarkerUpdate};
+use r2r::visualization_msgs::srv::GetInteractiveMarkers;
+use r2r::QosProfile;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+// Wait before asking the server for the marker set again after a failed request
+const INIT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+type InitCallback = Arc<dyn Fn(GetInteractiveMarkers::Response) + Send + Sync + 'static>;
+type UpdateCallback = Arc<dyn Fn(InteractiveMarkerUpdate) + Send + Sync + 'static>;
+type ResetCallback = Arc<dyn Fn() + Send + Sync + 'static>;
+type StatusCallback = Arc<dyn Fn(ClientStatus, &str) + Send + Sync + 'static>;
+
+/// Where an `InteractiveMarkerClient` is in its connection to the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClientState {
+    #[default]
+    Idle,
+    /// Waiting for the `get_interactive_markers` response.
+    Initializing,
+    /// In sync with the server and applying updates.
+    Running,
+}
+
+/// Severity of a status message passed to the status callback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientStatus {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Default)]
+struct Callbacks {
+    init: Option<InitCallback>,
+    update: Option<UpdateCallback>,
+    reset: Option<ResetCallback>,
+    status: Option<StatusCallback>,
+}
+
+/// Client side of the interactive markers protocol, like
+/// `interactive_markers::InteractiveMarkerClient` of the C++ library. Initializes from the `get_interactive_markers` service of the server,
+/// applies the updates published on `<ns>/update` in sequence, and re-initializes when an update
+/// was missed. The current marker set is kept and can be read with `markers` and `get`.
+///
+/// Servers with `ServerConfig::update_shards` above one publish on per-shard topics and are not
+/// supported.
+#[derive(Clone)]
+pub struct InteractiveMarkerClient {
+    topic_namespace: String,
+    state: Arc<sync::Mutex<ClientState>>,
+    markers: Arc<sync::Mutex<HashMap<String, InteractiveMarker>>>,
+    callbacks: Arc<sync::Mutex<Callbacks>>,
+    task: Arc<sync::Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl InteractiveMarkerClient {
+    /// Connects to the server in `topic_namespace`. Callbacks set right after this returns see
+    /// the initialization, as it only starts once the node is spun. Runs on the ambient tokio
+    /// runtime, use `new_with_handle` to create a client from outside one.
+    pub fn new(topic_namespace: &str, node: Arc<Mutex<r2r::Node>>) -> Result<Self> {
+        Self::new_with_handle(topic_namespace, node, Handle::current())
+    }
+
+    /// Connects to the server in `topic_namespace`, running the client on `runtime`, like
+    /// `InteractiveMarkerServer::new_with_handle`.
+    pub fn new_with_handle(
+        topic_namespace: &str,
+        node: Arc<Mutex<r2r::Node>>,
+        runtime: Handle,
+    ) -> Result<Self> {
+        let update_topic = format!("{}/update", topic_namespace);
+        let service_name = format!("{}/get_interactive_markers", topic_namespace);
+
+        let updates = node.lock().unwrap().subscribe::<InteractiveMarkerUpdate>(
+            &update_topic,
+            QosProfile::default().keep_last(100),
+        )?;
+        let service = node
+            .lock()
+            .unwrap()
+            .create_client::<GetInteractiveMarkers::Service>(
+                &service_name,
+                QosProfile::default(),
+            )?;
+
+        let client = Self {
+            topic_namespace: topic_namespace.to_string(),
+            state: Arc::new(sync::Mutex::new(ClientState::Idle)),
+            markers: Arc::new(sync::Mutex::new(HashMap::new())),
+            callbacks: Arc::new(sync::Mutex::new(Callbacks::default())),
+            task: Arc::new(sync::Mutex::new(None)),
+        };
+        let client_clone = client.clone();
+        *client.task.lock() = Some(runtime.spawn(async move {
+            client_clone.run(updates, service).await;
+        }));
+        Ok(client)
+    }
+
+    pub fn topic_namespace(&self) -> &str {
+        &self.topic_namespace
+    }
+
+    pub fn state(&self) -> ClientState {
+        *self.state.lock()
+    }
+
+    /// The markers as last received from the server.
+    pub fn markers(&self) -> Vec<InteractiveMarker> {
+        self.markers.lock().values().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<InteractiveMarker> {
+        self.markers.lock().get(name).cloned()
+    }
+
+    /// Called with the full marker set whenever the client (re-)initializes.
+    pub fn set_init_callback(
+        &self,
+        callback: impl Fn(GetInteractiveMarkers::Response) + Send + Sync + 'static,
+    ) {
+        self.callbacks.lock().init = Some(Arc::new(callback));
+    }
+
+    /// Called with every update applied after initialization.
+    pub fn set_update_callback(
+        &self,
+        callback: impl Fn(InteractiveMarkerUpdate) + Send + Sync + 'static,
+    ) {
+        self.callbacks.lock().update = Some(Arc::new(callback));
+    }
+
+    /// Called when an update was missed and the marker set is dropped before re-initializing.
+    pub fn set_reset_callback(&self, callback: impl Fn() + Send + Sync + 'static) {
+        self.callbacks.lock().reset = Some(Arc::new(callback));
+    }
+
+    pub fn set_status_callback(
+        &self,
+        callback: impl Fn(ClientStatus, &str) + Send + Sync + 'static,
+    ) {
+        self.callbacks.lock().status = Some(Arc::new(callback));
+    }
+
+    /// Stops following the server. The marker set is kept.
+    pub fn shutdown(&self) {
+        if let Some(task) = self.task.lock().take() {
+            task.abort();
+        }
+        *self.state.lock() = ClientState::Idle;
+    }
+
+    async fn run(
+        &self,
+        mut updates: impl Stream<Item = InteractiveMarkerUpdate> + Unpin,
+        service: r2r::Client<GetInteractiveMarkers::Service>,
+    ) {
+        loop {
+            *self.state.lock() = ClientState::Initializing;
+
+            // Updates arriving while the request is in flight may be newer than the response
+            let mut buffered = Vec::new();
+            let response = {
+                let initialize = self.initialize(&service);
+                tokio::pin!(initialize);
+                loop {
+                    tokio::select! {
+                        response = &mut initialize => break response,
+                        update = updates.next() => match update {
+                            Some(update) => buffered.push(update),
+                            None => return,
+                        },
+                    }
+                }
+            };
+            let Some(response) = response else {
+                tokio::time::sleep(INIT_RETRY_DELAY).await;
+                continue;
+            };
+
+            let mut last_seq_num = response.sequence_number;
+            self.status(
+                ClientStatus::Info,
+                &format!("Initialized with sequence number {}.", last_seq_num),
+            );
+            *self.markers.lock() = response
+                .markers
+                .iter()
+                .map(|marker| (marker.name.clone(), marker.clone()))
+                .collect();
+            let init_callback = self.callbacks.lock().init.clone();
+            if let Some(callback) = init_callback {
+                callback(response);
+            }
+            *self.state.lock() = ClientState::Running;
+
+            let mut in_sync = buffered
+                .into_iter()
+                .all(|update| self.process_update(update, &mut last_seq_num));
+            while in_sync {
+                match updates.next().await {
+                    Some(update) => in_sync = self.process_update(update, &mut last_seq_num),
+                    None => return,
+                }
+            }
+
+            self.markers.lock().clear();
+            let reset_callback = self.callbacks.lock().reset.clone();
+            if let Some(callback) = reset_callback {
+                callback();
+            }
+        }
+    }
+
+    async fn initialize(
+        &self,
+        service: &r2r::Client<GetInteractiveMarkers::Service>,
+    ) -> Option<GetInteractiveMarkers::Response> {
+        self.status(ClientStatus::Debug, "Waiting for the server.");
+        let result = match r2r::Node::is_available(service) {
+            Ok(available) => available.await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            self.status(
+                ClientStatus::Error,
+                &format!("Waiting for the server failed with: {}.", e),
+            );
+            return None;
+        }
+
+        let result = match service.request(&GetInteractiveMarkers::Request::default()) {
+            Ok(response) => response.await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(response) => Some(response),
+            Err(e) => {
+                self.status(
+                    ClientStatus::Error,
+                    &format!("Requesting the markers failed with: {}.", e),
+                );
+                None
+            }
+        }
+    }
+
+    // Applies an update that follows the last one. Returns false if an update was missed.
+    fn process_update(&self, update: InteractiveMarkerUpdate, last_seq_num: &mut u64) -> bool {
+        // Older updates are already contained in the initialization
+        if update.seq_num <= *last_seq_num {
+            return true;
+        }
+        if update.seq_num != *last_seq_num + 1 {
+            self.status(
+                ClientStatus::Warn,
+                &format!(
+                    "Expected update {} but got {}, re-initializing.",
+                    *last_seq_num + 1,
+                    update.seq_num
+                ),
+            );
+            return false;
+        }
+        *last_seq_num = update.seq_num;
+        if update.type_ == InteractiveMarkerUpdate::KEEP_ALIVE as u8 {
+            return true;
+        }
+
+        let mut unknown = Vec::new();
+        {
+            let mut markers = self.markers.lock();
+            for marker in &update.markers {
+                markers.insert(marker.name.clone(), marker.clone());
+            }
+            for pose in &update.poses {
+                match markers.get_mut(&pose.name) {
+                    Some(marker) => {
+                        marker.header = pose.header.clone();
+                        marker.pose = pose.pose.clone();
+                    }
+                    None => unknown.push(pose.name.as_str()),
+                }
+            }
+            for name in &update.erases {
+                markers.remove(name);
+            }
+        }
+        for name in unknown {
+            self.status(
+                ClientStatus::Warn,
+                &format!("Pose update for unknown marker '{}'.", name),
+            );
+        }
+
+        let update_callback = self.callbacks.lock().update.clone();
+        if let Some(callback) = update_callback {
+            callback(update);
+        }
+        true
+    }
+
+    fn status(&self, status: ClientStatus, message: &str) {
+        let status_callback = self.callbacks.lock().status.clone();
+        if let Some(callback) = status_callback {
+            callback(status, message);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Time source of a server, the system clock unless a ROS clock is given with
+// ServerBuilder::clock. Times of a ROS clock are kept as SystemTime since UNIX_EPOCH, which for
+// simulation time is the time since the simulation started.
+
+use r2r::builtin_interfaces::msg::Time;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Default)]
+pub(crate) enum Clock {
+    #[default]
+    System,
+    Ros(Arc<Mutex<r2r::Clock>>),
+}
+
+impl Clock {
+    pub(crate) fn now(&self) -> SystemTime {
+        match self {
+            Clock::System => SystemTime::now(),
+            Clock::Ros(clock) => match clock.lock().unwrap().get_now() {
+                Ok(since_epoch) => UNIX_EPOCH + since_epoch,
+                Err(_) => SystemTime::now(),
+            },
+        }
+    }
+
+    // The stamp for outgoing headers, only with a ROS clock
+    pub(crate) fn stamp(&self) -> Option<Time> {
+        match self {
+            Clock::System => None,
+            Clock::Ros(clock) => clock
+                .lock()
+                .unwrap()
+                .get_now()
+                .ok()
+                .map(|now| r2r::Clock::to_builtin_time(&now)),
+        }
+    }
+}
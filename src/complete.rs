@@ -0,0 +1,132 @@
+// Port of autoComplete() of the C++ server. Fills in what a marker left at its defaults, so
+// markers built field by field behave the same in every client.
+
+use crate::math;
+use r2r::geometry_msgs::msg::Quaternion;
+use r2r::visualization_msgs::msg::{InteractiveMarker, InteractiveMarkerControl};
+use std::collections::HashSet;
+
+pub(crate) fn auto_complete(marker: &mut InteractiveMarker) {
+    if marker.scale == 0.0 {
+        marker.scale = 1.0;
+    }
+    complete_orientation(&mut marker.pose.orientation);
+
+    for control in &mut marker.controls {
+        complete_orientation(&mut control.orientation);
+        if control.name.is_empty() {
+            control.name = default_name(control.interaction_mode).to_string();
+        }
+        let scale = marker.scale as f64;
+        for sub_marker in &mut control.markers {
+            complete_orientation(&mut sub_marker.pose.orientation);
+            // A marker without any size is not drawn, so it gets the size of the marker
+            let size = &mut sub_marker.scale;
+            if size.x == 0.0 && size.y == 0.0 && size.z == 0.0 {
+                size.x = scale;
+                size.y = scale;
+                size.z = scale;
+            }
+        }
+    }
+    uniqueify_control_names(&mut marker.controls);
+}
+
+// Turns an all-zero quaternion into the identity and normalizes everything else. Clients
+// interpret unnormalized control orientations inconsistently.
+fn complete_orientation(q: &mut Quaternion) {
+    if q.x == 0.0 && q.y == 0.0 && q.z == 0.0 && q.w == 0.0 {
+        q.w = 1.0;
+    } else {
+        *q = math::normalize(q);
+    }
+}
+
+fn default_name(interaction_mode: u8) -> &'static str {
+    match interaction_mode {
+        m if m == InteractiveMarkerControl::MENU as u8 => "menu",
+        m if m == InteractiveMarkerControl::BUTTON as u8 => "button",
+        m if m == InteractiveMarkerControl::MOVE_AXIS as u8 => "move_axis",
+        m if m == InteractiveMarkerControl::MOVE_PLANE as u8 => "move_plane",
+        m if m == InteractiveMarkerControl::ROTATE_AXIS as u8 => "rotate_axis",
+        m if m == InteractiveMarkerControl::MOVE_ROTATE as u8 => "move_rotate",
+        m if m == InteractiveMarkerControl::MOVE_3D as u8 => "move_3d",
+        m if m == InteractiveMarkerControl::ROTATE_3D as u8 => "rotate_3d",
+        m if m == InteractiveMarkerControl::MOVE_ROTATE_3D as u8 => "move_rotate_3d",
+        _ => "none",
+    }
+}
+
+// Feedback names the control it came from, so names must be unique. Repeated names get a
+// "_u<N>" suffix like in the C++ library.
+fn uniqueify_control_names(controls: &mut [InteractiveMarkerControl]) {
+    let mut names = HashSet::new();
+    let mut suffix = 0;
+    for control in controls {
+        let mut name = control.name.clone();
+        while names.contains(&name) {
+            name = format!("{}_u{}", control.name, suffix);
+            suffix += 1;
+        }
+        names.insert(name.clone());
+        control.name = name;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r2r::visualization_msgs::msg::Marker;
+
+    fn control(name: &str, interaction_mode: u8) -> InteractiveMarkerControl {
+        InteractiveMarkerControl {
+            name: name.to_string(),
+            interaction_mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn defaults_are_filled_in() {
+        let mut marker = InteractiveMarker {
+            controls: vec![InteractiveMarkerControl {
+                markers: vec![Marker::default()],
+                ..control("", InteractiveMarkerControl::BUTTON as u8)
+            }],
+            ..Default::default()
+        };
+        auto_complete(&mut marker);
+        assert_eq!(marker.scale, 1.0);
+        assert_eq!(marker.pose.orientation.w, 1.0);
+        let control = &marker.controls[0];
+        assert_eq!(control.name, "button");
+        assert_eq!(control.orientation.w, 1.0);
+        assert_eq!(control.markers[0].scale.x, 1.0);
+    }
+
+    #[test]
+    fn orientations_are_normalized() {
+        let mut q = Quaternion {
+            z: 2.0,
+            w: 2.0,
+            ..Default::default()
+        };
+        complete_orientation(&mut q);
+        assert!((q.z - 0.5f64.sqrt()).abs() < 1e-12);
+        assert!((q.w - 0.5f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn repeated_control_names_get_suffixes() {
+        let mode = InteractiveMarkerControl::MOVE_AXIS as u8;
+        let mut controls = vec![
+            control("move", mode),
+            control("move", mode),
+            control("move", mode),
+            control("move_u0", mode),
+        ];
+        uniqueify_control_names(&mut controls);
+        let names: Vec<&str> = controls.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["move", "move_u0", "move_u1", "move_u0_u2"]);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::log::LogLevel;
+use r2r::QosProfile;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// What happens when a change would grow the pending updates beyond
+/// `ServerConfig::max_pending_updates`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the new change. `set_pose` and `erase` return false for it.
+    #[default]
+    Reject,
+    /// Publish everything that is pending with `apply_changes`, then queue the change.
+    ApplyChanges,
+}
+
+/// Who decides the pose of a marker that a client drags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PoseMode {
+    /// Feedback poses are taken over as the marker's pose right away and published with the
+    /// next `apply_changes`.
+    #[default]
+    Optimistic,
+    /// Feedback poses are only requests. The marker's pose changes only through `set_pose`,
+    /// e.g. called from the feedback callback to accept a pose, and otherwise the stored pose
+    /// is republished to correct the client's drag.
+    Authoritative,
+}
+
+/// What `set_pose` does to a marker a client is dragging.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DragProtection {
+    /// Set the pose anyway, making the marker jump under the user's cursor.
+    #[default]
+    Off,
+    /// Hold the latest pose until the last drag on the marker ends, then queue it for the next
+    /// apply.
+    Defer,
+    /// Ignore the pose. `set_pose` returns false for it.
+    Drop,
+}
+
+/// Configuration of an `InteractiveMarkerServer`.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Maximum number of markers with a pending change. Each marker has at most one pending
+    /// change, repeated changes to the same marker are merged into it.
+    pub max_pending_updates: usize,
+    pub overflow_policy: OverflowPolicy,
+    /// Depth of the feedback subscription. Fast bursts of MOUSE_DOWN/MENU_SELECT feedback are
+    /// lost in transport when this is too small.
+    pub feedback_queue_depth: usize,
+    /// QoS of the feedback subscription. `None` uses the default profile with a depth of
+    /// `feedback_queue_depth`.
+    pub feedback_qos: Option<QosProfile>,
+    /// QoS of the update publishers.
+    pub update_qos: QosProfile,
+    /// QoS of the `init` and `snapshot` publishers. Clients only get the marker set on joining
+    /// if this is transient local.
+    pub init_qos: QosProfile,
+    /// QoS of the services.
+    pub service_qos: QosProfile,
+    /// Serve `<ns>/get_interactive_markers`. RViz initializes from this service, so without it
+    /// clients only see markers once they are republished, e.g. with `republish_on_reconnect`.
+    pub get_interactive_markers_service: bool,
+    /// Names of the update topic, the feedback topic and the `get_interactive_markers` service.
+    /// Names starting with `/` are used as they are, others are taken relative to the topic
+    /// namespace, e.g. `update` becomes `<ns>/update`.
+    pub update_topic: String,
+    pub feedback_topic: String,
+    pub service_name: String,
+    /// Number of topics updates are spread over. With more than one shard, updates are published
+    /// on `<ns>/update_0` to `<ns>/update_<N-1>` instead of `<ns>/update`, with `update_topic`
+    /// in place of `<ns>/update`, and the shard count is served on `<ns>/get_update_shards`.
+    pub update_shards: usize,
+    /// Publish a re-inserted marker as a pose update when its controls, menu, description and
+    /// scale did not change, so large controls are not resent to every client. Off by default,
+    /// so re-inserts are published in full like with the C++ server.
+    pub delta_updates: bool,
+    /// Ignore inserts of a marker identical to the one clients already have, so idempotent
+    /// "sync" code does not cause flicker or traffic. Off by default, so every insert is
+    /// published like with the C++ server.
+    pub skip_duplicate_inserts: bool,
+    /// Complete inserted markers like the C++ server: a zero scale becomes 1, quaternions are
+    /// normalized (all-zero ones become the identity), empty and repeated control names are
+    /// filled in and made unique, and control markers without a size get the marker's scale.
+    pub auto_complete: bool,
+    /// Reject inserts of markers that fail `validate`, emitting a `ServerEvent::InsertRejected`,
+    /// instead of publishing markers that clients drop. Runs before `auto_complete`.
+    pub strict: bool,
+    /// Also publish the full marker set as an `InteractiveMarkerInit` on the transient local
+    /// topic `<ns>/init` after every apply. ROS 1 clients behind ros1_bridge initialize from this
+    /// topic since they do not use the `get_interactive_markers` service.
+    pub legacy_init_topic: bool,
+    /// Publish the full marker set as a single `InteractiveMarkerInit` on the transient local
+    /// topic `<ns>/snapshot` after every apply, so recorders and dashboards can grab the complete
+    /// state without calling the service.
+    pub snapshot_topic: bool,
+    /// Publish the poses of all markers, sorted by name, as a `PoseArray` on `<ns>/poses` after
+    /// every apply, a lightweight snapshot for debugging and simple consumers. The header has the
+    /// frame all markers share, or is empty when their frames differ.
+    pub pose_array_topic: bool,
+    /// Prefix applied to the frame ids of inserted markers and poses, e.g. `robot1` turns
+    /// `base_link` into `robot1/base_link`. Empty for no prefix. Frames that already carry the
+    /// prefix are left unchanged.
+    pub robot_prefix: String,
+    /// Also apply `robot_prefix` to marker names. The server API, callbacks and `get` keep using
+    /// the unprefixed names, only the published markers carry the prefix.
+    pub prefix_names: bool,
+    /// When `set_pose` gets a header in another frame than the marker's, transform the pose
+    /// into the marker's frame using tf2 (/tf and /tf_static) instead of moving the marker to
+    /// the new frame. The frame is switched as before when no transform is known.
+    pub transform_poses: bool,
+    /// Publish the full marker set when a client (re)subscribes to the update topic, so clients
+    /// that reconnect are resynced without toggling the display. Nothing is republished while
+    /// the server is inactive. Off by default.
+    pub republish_on_reconnect: bool,
+    /// Serve `<ns>/get_poses`, a `GetInteractiveMarkers` service answering with markers that
+    /// only carry their name, header and pose. Cheap enough for planners and loggers to poll.
+    pub poses_service: bool,
+    /// Move a marker back to its pose before a drag when the dragging client sent MOUSE_DOWN
+    /// but then neither MOUSE_UP nor any other feedback for this long, e.g. because it crashed
+    /// mid-drag. A `ServerEvent::DragReverted` is emitted for each such drag.
+    pub drag_timeout: Option<Duration>,
+    /// Keep `set_pose` and `set_pose_in_frame` from fighting a user who is dragging the marker,
+    /// like the C++ server, e.g. when a loop keeps setting poses as in the cube example.
+    pub drag_protection: DragProtection,
+    /// Only honor feedback from these client ids, e.g. so a second RViz cannot move
+    /// safety-critical markers. Other feedback is dropped with
+    /// `FeedbackDropReason::ClientNotAllowed`. Keep-alives are always accepted. `None` allows
+    /// every client. Markers can override this with `set_allowed_clients`.
+    pub allowed_clients: Option<HashSet<String>>,
+    /// Number of earlier poses kept per marker for `undo` and `redo`. Every drag and every
+    /// `set_pose` adds one. 0 disables the history.
+    pub pose_history_depth: usize,
+    /// Broadcast a frame at the pose of every marker on /tf every this often, so other nodes can
+    /// look up where a marker is instead of subscribing to feedback. The frames are named
+    /// `<tf_frame_prefix><marker name>` and parented to the marker's header frame.
+    pub tf_broadcast_period: Option<Duration>,
+    pub tf_frame_prefix: String,
+    /// Publish every `insert`, `set_pose` and `erase` right away, so `apply_changes` is not
+    /// needed. `insert_many`, `erase_many` and transactions still publish a single update.
+    pub auto_apply: bool,
+    /// Publish updates that only move markers at most this many times per second, e.g. 30.0
+    /// when a control loop calls `set_pose` and applies at 1 kHz. Applies within a window leave
+    /// the poses pending, where newer poses replace older ones, and the latest poses are
+    /// published when the window ends. Updates with inserts or erases are published right away,
+    /// together with any poses held back. Creating a server with a rate that is not positive and
+    /// finite fails with `Error::InvalidRate`.
+    pub max_pose_rate: Option<f64>,
+    /// Split updates larger than this many bytes, e.g. the full update of thousands of markers,
+    /// into several updates with consecutive sequence numbers. The size is estimated from the
+    /// message contents. A single marker larger than this is still sent in one update. None by
+    /// default, so every update is sent whole.
+    pub max_update_bytes: Option<usize>,
+    /// Publish the status of the server as a `DiagnosticArray` on /diagnostics every this often,
+    /// so it shows up in diagnostic aggregators: the marker, pending change and client counts,
+    /// the feedback rate, the time since the last feedback and failed publishes. The status
+    /// warns about failed publishes and is an error when a background task stopped.
+    pub diagnostics_period: Option<Duration>,
+    /// Publish an empty `KEEP_ALIVE` update with the current sequence number when no update was
+    /// published for this long, like the classic server, so clients can tell that the server is
+    /// alive and notice updates they missed. Paused and inactive servers send none.
+    pub keep_alive_period: Option<Duration>,
+    /// Sequence number of the server before its first update. A restarted server counting from
+    /// 0 again can confuse clients that cached its state, so seed this with e.g. the last
+    /// `sequence_number` of the previous run, or the current time in milliseconds, to keep the
+    /// numbers increasing across restarts.
+    pub initial_sequence_number: u64,
+    /// Name of the ROS logger the server logs to. `None` uses the node's logger with the topic
+    /// namespace as child, e.g. `my_node.robot1.markers` for the namespace `/robot1/markers`.
+    pub logger_name: Option<String>,
+    pub log_level: LogLevel,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_pending_updates: 100_000,
+            overflow_policy: OverflowPolicy::default(),
+            feedback_queue_depth: 100,
+            feedback_qos: None,
+            update_qos: QosProfile::default().keep_last(100),
+            init_qos: QosProfile::default().transient_local().keep_last(1),
+            service_qos: QosProfile::default(),
+            get_interactive_markers_service: true,
+            update_topic: "update".to_string(),
+            feedback_topic: "feedback".to_string(),
+            service_name: "get_interactive_markers".to_string(),
+            update_shards: 1,
+            delta_updates: false,
+            skip_duplicate_inserts: false,
+            auto_complete: true,
+            strict: false,
+            legacy_init_topic: false,
+            snapshot_topic: false,
+            pose_array_topic: false,
+            robot_prefix: String::new(),
+            prefix_names: false,
+            transform_poses: false,
+            republish_on_reconnect: false,
+            poses_service: false,
+            drag_timeout: None,
+            drag_protection: DragProtection::default(),
+            allowed_clients: None,
+            pose_history_depth: 0,
+            tf_broadcast_period: None,
+            tf_frame_prefix: String::new(),
+            auto_apply: false,
+            max_pose_rate: None,
+            max_update_bytes: None,
+            diagnostics_period: None,
+            keep_alive_period: None,
+            initial_sequence_number: 0,
+            logger_name: None,
+            log_level: LogLevel::default(),
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//! A declarative scene format, so markers, controls, shapes and menus can be described in a YAML
+//! file instead of in code, the way URDF describes robot geometry. For example:
+//!
+//! ```yaml
+//! markers:
+//!   - name: gripper
+//!     frame: base_link
+//!     position: [0.5, 0.0, 0.3]
+//!     scale: 0.3
+//!     six_dof: true
+//!     controls:
+//!       - interaction: move_3d
+//!         always_visible: true
+//!         shapes:
+//!           - type: cube
+//!             scale: [0.1, 0.1, 0.1]
+//!             color: [1.0, 0.5, 0.0, 1.0]
+//!     menu:
+//!       - title: Open
+//!       - title: Speed
+//!         entries:
+//!           - title: Slow
+//!           - title: Fast
+//! ```
+//!
+//! Menu entries get ids in the order they appear, starting at 1, and send MENU_SELECT feedback.
+//! The ids can be looked up with `MarkerDescription::menu_entry_id`.
+
+use crate::helpers::{self, Axis};
+use crate::InteractiveMarkerServer;
+use r2r::geometry_msgs::msg::{Point, Pose, Quaternion, Vector3};
+use r2r::std_msgs::msg::ColorRGBA;
+use r2r::visualization_msgs::msg::{
+    InteractiveMarker, InteractiveMarkerControl, Marker, MenuEntry,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A set of markers loaded from YAML, in the declarative format of this module. Unlike `Scene`,
+/// which saves the marker messages of a server as they are, it is meant to be written by hand.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub markers: Vec<MarkerDescription>,
+}
+
+/// An interactive marker. Everything except the name is optional.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkerDescription {
+    pub name: String,
+    pub frame: String,
+    pub description: String,
+    pub position: [f64; 3],
+    /// A quaternion as `[x, y, z, w]`.
+    pub orientation: [f64; 4],
+    pub scale: f32,
+    /// Add arrows and rings to move and rotate the marker along all axes.
+    pub six_dof: bool,
+    /// Keep the `six_dof` controls aligned with the marker's frame when it is rotated.
+    pub fixed: bool,
+    pub controls: Vec<ControlDescription>,
+    pub menu: Vec<MenuDescription>,
+}
+
+/// A control of a marker. The control acts along `axis` of the marker, or at the marker's
+/// orientation without one.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlDescription {
+    pub name: String,
+    pub description: String,
+    pub interaction: InteractionMode,
+    pub axis: Option<Axis>,
+    pub orientation_mode: OrientationMode,
+    pub always_visible: bool,
+    pub shapes: Vec<ShapeDescription>,
+}
+
+/// The `interaction_mode` of an `InteractiveMarkerControl`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionMode {
+    #[default]
+    None,
+    Menu,
+    Button,
+    MoveAxis,
+    MovePlane,
+    RotateAxis,
+    MoveRotate,
+    // Named after MOVE_3D and friends, which snake_case would turn into `move3d`
+    #[serde(rename = "move_3d")]
+    Move3d,
+    #[serde(rename = "rotate_3d")]
+    Rotate3d,
+    #[serde(rename = "move_rotate_3d")]
+    MoveRotate3d,
+}
+
+/// The `orientation_mode` of an `InteractiveMarkerControl`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrientationMode {
+    #[default]
+    Inherit,
+    Fixed,
+    ViewFacing,
+}
+
+/// A visual marker shown as part of a control, placed relative to the interactive marker.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShapeDescription {
+    #[serde(rename = "type")]
+    pub shape: ShapeType,
+    pub position: [f64; 3],
+    /// A quaternion as `[x, y, z, w]`.
+    pub orientation: [f64; 4],
+    pub scale: [f64; 3],
+    /// `[r, g, b, a]` in 0 to 1.
+    pub color: [f32; 4],
+    /// The text of `text` shapes.
+    pub text: String,
+    /// The resource of `mesh` shapes, e.g. `package://my_robot/meshes/gripper.dae`.
+    pub mesh: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShapeType {
+    Arrow,
+    #[default]
+    Cube,
+    Sphere,
+    Cylinder,
+    Text,
+    Mesh,
+}
+
+/// A menu entry, with its sub-entries.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MenuDescription {
+    pub title: String,
+    pub entries: Vec<MenuDescription>,
+}
+
+const IDENTITY: [f64; 4] = [0.0, 0.0, 0.0, 1.0];
+
+fn point([x, y, z]: [f64; 3]) -> Point {
+    Point { x, y, z }
+}
+
+fn quaternion([x, y, z, w]: [f64; 4]) -> Quaternion {
+    Quaternion { x, y, z, w }
+}
+
+impl Default for MarkerDescription {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            frame: String::new(),
+            description: String::new(),
+            position: [0.0; 3],
+            orientation: IDENTITY,
+            scale: 1.0,
+            six_dof: false,
+            fixed: false,
+            controls: Vec::new(),
+            menu: Vec::new(),
+        }
+    }
+}
+
+impl Default for ShapeDescription {
+    fn default() -> Self {
+        Self {
+            shape: ShapeType::default(),
+            position: [0.0; 3],
+            orientation: IDENTITY,
+            scale: [0.1; 3],
+            color: [0.5, 0.5, 0.5, 1.0],
+            text: String::new(),
+            mesh: String::new(),
+        }
+    }
+}
+
+impl SceneDescription {
+    pub fn from_yaml(yaml: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self::from_yaml(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn to_markers(&self) -> Vec<InteractiveMarker> {
+        self.markers
+            .iter()
+            .map(MarkerDescription::to_marker)
+            .collect()
+    }
+
+    /// Inserts the described markers into `server`, replacing markers with the same names, and
+    /// publishes them.
+    pub fn load(&self, server: &InteractiveMarkerServer) -> crate::Result<()> {
+        for marker in self.to_markers() {
+            server.insert(marker);
+        }
+        server.apply_changes()?;
+        Ok(())
+    }
+}
+
+impl MarkerDescription {
+    pub fn to_marker(&self) -> InteractiveMarker {
+        let mut controls: Vec<InteractiveMarkerControl> = self
+            .controls
+            .iter()
+            .map(ControlDescription::to_control)
+            .collect();
+        if self.six_dof {
+            controls.extend(helpers::make_6dof_controls(self.fixed));
+        }
+
+        let mut menu_entries = Vec::new();
+        add_menu_entries(&self.menu, 0, &mut menu_entries);
+
+        let mut marker = InteractiveMarker {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            pose: Pose {
+                position: point(self.position),
+                orientation: quaternion(self.orientation),
+            },
+            scale: self.scale,
+            controls,
+            menu_entries,
+            ..Default::default()
+        };
+        marker.header.frame_id = self.frame.clone();
+        marker
+    }
+
+    /// The id the menu entry at `path` gets, e.g. `["Speed", "Fast"]`, to match it against
+    /// MENU_SELECT feedback.
+    pub fn menu_entry_id(&self, path: &[&str]) -> Option<u32> {
+        let mut menu_entries = Vec::new();
+        add_menu_entries(&self.menu, 0, &mut menu_entries);
+        let mut parent_id = 0;
+        for title in path {
+            parent_id = menu_entries
+                .iter()
+                .find(|entry| entry.parent_id == parent_id && entry.title == *title)?
+                .id;
+        }
+        (parent_id != 0).then_some(parent_id)
+    }
+}
+
+// Entries are numbered depth first, in the order they are written
+fn add_menu_entries(menu: &[MenuDescription], parent_id: u32, entries: &mut Vec<MenuEntry>) {
+    for description in menu {
+        let id = entries.len() as u32 + 1;
+        entries.push(MenuEntry {
+            id,
+            parent_id,
+            title: description.title.clone(),
+            command_type: MenuEntry::FEEDBACK as u8,
+            ..Default::default()
+        });
+        add_menu_entries(&description.entries, id, entries);
+    }
+}
+
+impl ControlDescription {
+    pub fn to_control(&self) -> InteractiveMarkerControl {
+        InteractiveMarkerControl {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            orientation: self
+                .axis
+                .map_or_else(|| quaternion(IDENTITY), Axis::orientation),
+            orientation_mode: self.orientation_mode.to_msg(),
+            interaction_mode: self.interaction.to_msg(),
+            always_visible: self.always_visible,
+            markers: self
+                .shapes
+                .iter()
+                .map(ShapeDescription::to_marker)
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl InteractionMode {
+    pub fn to_msg(self) -> u8 {
+        (match self {
+            InteractionMode::None => InteractiveMarkerControl::NONE,
+            InteractionMode::Menu => InteractiveMarkerControl::MENU,
+            InteractionMode::Button => InteractiveMarkerControl::BUTTON,
+            InteractionMode::MoveAxis => InteractiveMarkerControl::MOVE_AXIS,
+            InteractionMode::MovePlane => InteractiveMarkerControl::MOVE_PLANE,
+            InteractionMode::RotateAxis => InteractiveMarkerControl::ROTATE_AXIS,
+            InteractionMode::MoveRotate => InteractiveMarkerControl::MOVE_ROTATE,
+            InteractionMode::Move3d => InteractiveMarkerControl::MOVE_3D,
+            InteractionMode::Rotate3d => InteractiveMarkerControl::ROTATE_3D,
+            InteractionMode::MoveRotate3d => InteractiveMarkerControl::MOVE_ROTATE_3D,
+        }) as u8
+    }
+}
+
+impl OrientationMode {
+    pub fn to_msg(self) -> u8 {
+        (match self {
+            OrientationMode::Inherit => InteractiveMarkerControl::INHERIT,
+            OrientationMode::Fixed => InteractiveMarkerControl::FIXED,
+            OrientationMode::ViewFacing => InteractiveMarkerControl::VIEW_FACING,
+        }) as u8
+    }
+}
+
+impl ShapeDescription {
+    pub fn to_marker(&self) -> Marker {
+        let type_ = match self.shape {
+            ShapeType::Arrow => Marker::ARROW,
+            ShapeType::Cube => Marker::CUBE,
+            ShapeType::Sphere => Marker::SPHERE,
+            ShapeType::Cylinder => Marker::CYLINDER,
+            ShapeType::Text => Marker::TEXT_VIEW_FACING,
+            ShapeType::Mesh => Marker::MESH_RESOURCE,
+        };
+        let [x, y, z] = self.scale;
+        let [r, g, b, a] = self.color;
+        Marker {
+            type_: type_ as i32,
+            pose: Pose {
+                position: point(self.position),
+                orientation: quaternion(self.orientation),
+            },
+            scale: Vector3 { x, y, z },
+            color: ColorRGBA { r, g, b, a },
+            text: self.text.clone(),
+            mesh_resource: self.mesh.clone(),
+            // Meshes keep their own materials
+            mesh_use_embedded_materials: self.shape == ShapeType::Mesh,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of the module documentation
+    fn example() -> String {
+        include_str!("description.rs")
+            .lines()
+            .skip_while(|line| *line != "//! ```yaml")
+            .skip(1)
+            .take_while(|line| *line != "//! ```")
+            .map(|line| format!("{}\n", line.strip_prefix("//! ").unwrap_or_default()))
+            .collect()
+    }
+
+    #[test]
+    fn documented_example_parses() {
+        let scene = SceneDescription::from_yaml(&example()).unwrap();
+        let gripper = &scene.markers[0];
+        assert_eq!(gripper.name, "gripper");
+        assert_eq!(gripper.frame, "base_link");
+        assert_eq!(gripper.controls[0].interaction, InteractionMode::Move3d);
+        assert_eq!(gripper.controls[0].shapes[0].color, [1.0, 0.5, 0.0, 1.0]);
+
+        let marker = gripper.to_marker();
+        assert_eq!(marker.header.frame_id, "base_link");
+        assert_eq!(marker.pose.position.x, 0.5);
+        assert_eq!(marker.pose.orientation.w, 1.0);
+        assert!(marker.controls.len() > 1);
+        assert_eq!(marker.menu_entries.len(), 4);
+    }
+
+    #[test]
+    fn menu_entries_are_numbered_depth_first() {
+        let scene = SceneDescription::from_yaml(&example()).unwrap();
+        let gripper = &scene.markers[0];
+        assert_eq!(gripper.menu_entry_id(&["Open"]), Some(1));
+        assert_eq!(gripper.menu_entry_id(&["Speed"]), Some(2));
+        assert_eq!(gripper.menu_entry_id(&["Speed", "Slow"]), Some(3));
+        assert_eq!(gripper.menu_entry_id(&["Speed", "Fast"]), Some(4));
+        assert_eq!(gripper.menu_entry_id(&["Fast"]), None);
+        assert_eq!(gripper.menu_entry_id(&[]), None);
+
+        let entries = gripper.to_marker().menu_entries;
+        assert_eq!(entries[3].parent_id, 2);
+    }
+
+    #[test]
+    fn misspelled_fields_are_rejected() {
+        for yaml in [
+            "marker: []",
+            "markers: [{name: a, postion: [0, 0, 0]}]",
+            "markers: [{name: a, controls: [{shapes: [{type: cube, colour: [1, 0, 0, 1]}]}]}]",
+            "markers: [{name: a, menu: [{title: Open, entry: []}]}]",
+        ] {
+            assert!(SceneDescription::from_yaml(yaml).is_err(), "{}", yaml);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// Status of a server published on /diagnostics with ServerConfig::diagnostics_period, so it shows
+// up in diagnostic aggregators next to the rest of the robot.
+
+use r2r::diagnostic_msgs::msg::{DiagnosticStatus, KeyValue};
+use std::time::{Duration, Instant};
+
+// What the server looked like when the status was sampled
+pub(crate) struct Sample {
+    pub topic_namespace: String,
+    pub markers: usize,
+    pub pending: usize,
+    pub clients: usize,
+    // Feedback messages received from all clients so far
+    pub feedback: u64,
+    pub since_feedback: Option<Duration>,
+    pub publish_failures: u64,
+    pub stopped_tasks: Vec<&'static str>,
+    pub active: bool,
+    pub paused: bool,
+}
+
+// Turns samples into statuses, remembering the previous sample for the rates
+#[derive(Default)]
+pub(crate) struct Reporter {
+    last: Option<(Instant, u64, u64)>,
+}
+
+impl Reporter {
+    pub(crate) fn status(&mut self, sample: Sample, now: Instant) -> DiagnosticStatus {
+        let (feedback_rate, new_failures) = match self.last {
+            Some((at, feedback, failures)) => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                let rate = if elapsed > 0.0 {
+                    sample.feedback.saturating_sub(feedback) as f64 / elapsed
+                } else {
+                    0.0
+                };
+                (rate, sample.publish_failures.saturating_sub(failures))
+            }
+            None => (0.0, sample.publish_failures),
+        };
+        self.last = Some((now, sample.feedback, sample.publish_failures));
+
+        let (level, message) = if !sample.stopped_tasks.is_empty() {
+            (
+                DiagnosticStatus::ERROR,
+                format!("Stopped: {}", sample.stopped_tasks.join(", ")),
+            )
+        } else if new_failures > 0 {
+            (
+                DiagnosticStatus::WARN,
+                format!("{} updates failed to publish", new_failures),
+            )
+        } else if !sample.active {
+            (DiagnosticStatus::OK, "Inactive".to_string())
+        } else if sample.paused {
+            (DiagnosticStatus::OK, "Paused".to_string())
+        } else {
+            (DiagnosticStatus::OK, "OK".to_string())
+        };
+
+        let since_feedback = match sample.since_feedback {
+            Some(since) => format!("{:.1}", since.as_secs_f64()),
+            None => "never".to_string(),
+        };
+        let values = [
+            ("markers", sample.markers.to_string()),
+            ("pending changes", sample.pending.to_string()),
+            ("clients", sample.clients.to_string()),
+            ("feedback rate (Hz)", format!("{:.1}", feedback_rate)),
+            ("seconds since last feedback", since_feedback),
+            ("publish failures", sample.publish_failures.to_string()),
+        ];
+        DiagnosticStatus {
+            level,
+            name: format!("interactive_markers: {}", sample.topic_namespace),
+            message,
+            hardware_id: String::new(),
+            values: values
+                .into_iter()
+                .map(|(key, value)| KeyValue {
+                    key: key.to_string(),
+                    value,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Sample {
+        Sample {
+            topic_namespace: "test".to_string(),
+            markers: 0,
+            pending: 0,
+            clients: 0,
+            feedback: 0,
+            since_feedback: None,
+            publish_failures: 0,
+            stopped_tasks: Vec::new(),
+            active: true,
+            paused: false,
+        }
+    }
+
+    fn value<'a>(status: &'a DiagnosticStatus, key: &str) -> &'a str {
+        &status
+            .values
+            .iter()
+            .find(|value| value.key == key)
+            .unwrap()
+            .value
+    }
+
+    #[test]
+    fn stopped_tasks_outrank_failures_and_states() {
+        let now = Instant::now();
+        let status = Reporter::default().status(
+            Sample {
+                publish_failures: 1,
+                stopped_tasks: vec!["keep_alive"],
+                active: false,
+                ..sample()
+            },
+            now,
+        );
+        assert_eq!(status.level, DiagnosticStatus::ERROR);
+        assert_eq!(status.message, "Stopped: keep_alive");
+
+        let mut reporter = Reporter::default();
+        let failing = || Sample {
+            publish_failures: 2,
+            paused: true,
+            ..sample()
+        };
+        let status = reporter.status(failing(), now);
+        assert_eq!(status.level, DiagnosticStatus::WARN);
+        // Only failures since the last sample warn
+        let status = reporter.status(failing(), now);
+        assert_eq!(
+            (status.level, status.message.as_str()),
+            (DiagnosticStatus::OK, "Paused")
+        );
+
+        for (active, paused, message) in [(false, true, "Inactive"), (true, false, "OK")] {
+            let status = Reporter::default().status(
+                Sample {
+                    active,
+                    paused,
+                    ..sample()
+                },
+                now,
+            );
+            assert_eq!(
+                (status.level, status.message.as_str()),
+                (DiagnosticStatus::OK, message)
+            );
+        }
+    }
+
+    #[test]
+    fn feedback_rate_is_taken_between_samples() {
+        let mut reporter = Reporter::default();
+        let start = Instant::now();
+        let status = reporter.status(sample(), start);
+        assert_eq!(value(&status, "feedback rate (Hz)"), "0.0");
+        assert_eq!(value(&status, "seconds since last feedback"), "never");
+
+        let status = reporter.status(
+            Sample {
+                feedback: 30,
+                since_feedback: Some(Duration::from_millis(250)),
+                ..sample()
+            },
+            start + Duration::from_secs(2),
+        );
+        assert_eq!(value(&status, "feedback rate (Hz)"), "15.0");
+        assert_eq!(value(&status, "seconds since last feedback"), "0.2");
+    }
+}
@@ -0,0 +1,24 @@
+use r2r::geometry_msgs::msg::Pose;
+use r2r::std_msgs::msg::Header;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A client dragging a marker, from its MOUSE_DOWN to its MOUSE_UP.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DragSession {
+    pub client_id: String,
+    /// The pose and header the marker had when the drag started.
+    pub start_pose: Pose,
+    pub start_header: Header,
+    pub started: Instant,
+}
+
+pub(crate) type DragStartCallback = Arc<dyn Fn(&DragSession) + Send + Sync + 'static>;
+// Gets the pose the marker was released at
+pub(crate) type DragEndCallback = Arc<dyn Fn(&DragSession, &Pose) + Send + Sync + 'static>;
+
+#[derive(Clone, Default)]
+pub(crate) struct DragCallbacks {
+    pub start: Option<DragStartCallback>,
+    pub end: Option<DragEndCallback>,
+}
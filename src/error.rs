@@ -0,0 +1,45 @@
+use std::fmt;
+
+/// Errors of the server and its widgets.
+#[derive(Debug)]
+pub enum Error {
+    /// Creating a publisher, subscription, service or client failed in the middleware.
+    Ros(r2r::Error),
+    /// The task publishing updates has stopped, e.g. because the runtime is shutting down.
+    PublisherStopped,
+    /// A rate, in times per second, is not positive and finite.
+    InvalidRate(f64),
+    /// A task handed out as a `JoinHandle` was started outside a tokio runtime by a server of
+    /// `new_with_runner`, which has no runtime of its own.
+    NoRuntime,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Ros(e) => write!(f, "ROS error: {}", e),
+            Error::PublisherStopped => write!(f, "the update publisher task has stopped"),
+            Error::InvalidRate(rate) => {
+                write!(f, "invalid rate {}, must be positive and finite", rate)
+            }
+            Error::NoRuntime => write!(f, "no tokio runtime to start the task on"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Ros(e) => Some(e),
+            Error::PublisherStopped | Error::InvalidRate(_) | Error::NoRuntime => None,
+        }
+    }
+}
+
+impl From<r2r::Error> for Error {
+    fn from(e: r2r::Error) -> Self {
+        Error::Ros(e)
+    }
+}
@@ -0,0 +1,63 @@
+use crate::config::OverflowPolicy;
+use crate::stats::FeedbackDropReason;
+use crate::validate::ValidationIssue;
+use futures::Stream;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Something noteworthy that happened inside an `InteractiveMarkerServer`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerEvent {
+    /// Publishing an update failed and will be retried.
+    PublishRetry {
+        seq_num: u64,
+        attempt: u32,
+        error: String,
+    },
+    /// Publishing an update failed on every attempt and the update was dropped.
+    PublishFailed { seq_num: u64, error: String },
+    /// A change to `name` did not fit in the pending updates, which already hold `pending`
+    /// markers. The configured `policy` decided what happened to it.
+    PendingOverflow {
+        name: String,
+        pending: usize,
+        policy: OverflowPolicy,
+    },
+    /// Feedback for `name` from `client_id` was dropped or is suspected to be lost.
+    FeedbackDropped {
+        name: String,
+        client_id: String,
+        reason: FeedbackDropReason,
+    },
+    /// `client_id` started dragging `name` but sent nothing for `ServerConfig::drag_timeout`,
+    /// so the marker was moved back to where the drag started.
+    DragReverted { name: String, client_id: String },
+    /// A pose of `name` dragged by `client_id` was rejected by the marker's placement constraint.
+    PlacementRejected { name: String, client_id: String },
+    /// An insert of `name` was rejected in strict mode because of `issues`.
+    InsertRejected {
+        name: String,
+        issues: Vec<ValidationIssue>,
+    },
+}
+
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+pub(crate) fn channel<T: Clone>() -> broadcast::Sender<T> {
+    broadcast::channel(EVENT_CHANNEL_CAPACITY).0
+}
+
+// Turns a broadcast receiver into a stream. Events missed by a lagging receiver are skipped.
+pub(crate) fn stream<T: Clone + Send + 'static>(
+    receiver: broadcast::Receiver<T>,
+) -> impl Stream<Item = T> + Send + Unpin {
+    Box::pin(futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }))
+}
@@ -0,0 +1,70 @@
+use r2r::geometry_msgs::msg::{Point, Pose};
+use r2r::std_msgs::msg::Header;
+use r2r::visualization_msgs::msg::InteractiveMarkerFeedback;
+use std::fmt;
+
+/// The event of an `InteractiveMarkerFeedback` with the fields that belong to it, instead of
+/// a raw `event_type`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FeedbackEvent {
+    KeepAlive,
+    PoseUpdate {
+        pose: Pose,
+        header: Header,
+    },
+    MenuSelect {
+        entry_id: u32,
+    },
+    /// `mouse_point` is where the control was clicked, if the client could tell.
+    ButtonClick {
+        mouse_point: Option<Point>,
+    },
+    MouseDown,
+    MouseUp,
+}
+
+/// The `event_type` of a feedback message is not one of the known constants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownEventType(pub u8);
+
+impl fmt::Display for UnknownEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown feedback event type {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownEventType {}
+
+impl TryFrom<&InteractiveMarkerFeedback> for FeedbackEvent {
+    type Error = UnknownEventType;
+
+    fn try_from(feedback: &InteractiveMarkerFeedback) -> Result<Self, Self::Error> {
+        let event = match feedback.event_type {
+            t if t == InteractiveMarkerFeedback::KEEP_ALIVE as u8 => FeedbackEvent::KeepAlive,
+            t if t == InteractiveMarkerFeedback::POSE_UPDATE as u8 => FeedbackEvent::PoseUpdate {
+                pose: feedback.pose.clone(),
+                header: feedback.header.clone(),
+            },
+            t if t == InteractiveMarkerFeedback::MENU_SELECT as u8 => FeedbackEvent::MenuSelect {
+                entry_id: feedback.menu_entry_id,
+            },
+            t if t == InteractiveMarkerFeedback::BUTTON_CLICK as u8 => FeedbackEvent::ButtonClick {
+                mouse_point: feedback
+                    .mouse_point_valid
+                    .then(|| feedback.mouse_point.clone()),
+            },
+            t if t == InteractiveMarkerFeedback::MOUSE_DOWN as u8 => FeedbackEvent::MouseDown,
+            t if t == InteractiveMarkerFeedback::MOUSE_UP as u8 => FeedbackEvent::MouseUp,
+            t => return Err(UnknownEventType(t)),
+        };
+        Ok(event)
+    }
+}
+
+impl TryFrom<InteractiveMarkerFeedback> for FeedbackEvent {
+    type Error = UnknownEventType;
+
+    fn try_from(feedback: InteractiveMarkerFeedback) -> Result<Self, Self::Error> {
+        FeedbackEvent::try_from(&feedback)
+    }
+}
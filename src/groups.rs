@@ -0,0 +1,173 @@
+use crate::{AppliedChanges, FeedbackCallbackBox, InteractiveMarkerServer, Result};
+use r2r::visualization_msgs::msg::InteractiveMarker;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Default)]
+pub(crate) struct Group {
+    pub members: BTreeSet<String>,
+    // Given to every marker that joins the group
+    pub callbacks: HashMap<u8, FeedbackCallbackBox>,
+}
+
+/// A named set of markers of a server, for operating on all markers of a feature at once.
+/// Groups are created on first use and hold marker names, so erased markers leave their groups.
+#[derive(Clone)]
+pub struct MarkerGroup {
+    server: InteractiveMarkerServer,
+    name: String,
+}
+
+impl MarkerGroup {
+    pub(crate) fn new(server: &InteractiveMarkerServer, name: &str) -> Self {
+        Self {
+            server: server.clone(),
+            name: name.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Inserts a marker into the server and adds it to the group, with the group callbacks.
+    pub fn insert(&self, marker: InteractiveMarker) {
+        let name = marker.name.clone();
+        self.server.insert(marker);
+        self.add(&name);
+    }
+
+    /// Adds a marker already in the server to the group and sets the group callbacks on it.
+    /// Returns false if the marker is unknown.
+    pub fn add(&self, name: &str) -> bool {
+        if self.server.get(name).is_none() {
+            return false;
+        }
+        let callbacks = {
+            let mut group = self.server.groups.entry(self.name.clone()).or_default();
+            group.members.insert(name.to_string());
+            group.callbacks.clone()
+        };
+        for (feedback_type, callback) in callbacks {
+            self.server
+                .set_callback(name, Some(callback), feedback_type);
+        }
+        true
+    }
+
+    /// Removes a marker from the group, keeping it in the server. Returns false if it was not a
+    /// member.
+    pub fn remove(&self, name: &str) -> bool {
+        self.server
+            .groups
+            .get_mut(&self.name)
+            .is_some_and(|mut group| group.members.remove(name))
+    }
+
+    /// The names of the markers in the group, sorted.
+    pub fn members(&self) -> Vec<String> {
+        self.server
+            .groups
+            .get(&self.name)
+            .map(|group| group.members.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Erases all markers of the group. The changes are published with the next apply.
+    pub fn erase(&self) {
+        for name in self.members() {
+            self.server.erase(&name);
+        }
+    }
+
+    /// Sets the callback for feedback of `feedback_type` on all markers of the group, including
+    /// those added later, like `InteractiveMarkerServer::set_callback`.
+    pub fn set_callback(&self, feedback_cb: Option<FeedbackCallbackBox>, feedback_type: u8) {
+        let members = {
+            let mut group = self.server.groups.entry(self.name.clone()).or_default();
+            match &feedback_cb {
+                Some(callback) => {
+                    group.callbacks.insert(feedback_type, callback.clone());
+                }
+                None => {
+                    group.callbacks.remove(&feedback_type);
+                }
+            }
+            group.members.clone()
+        };
+        for name in members {
+            self.server
+                .set_callback(&name, feedback_cb.clone(), feedback_type);
+        }
+    }
+
+    /// Publishes the pending changes of the group's markers only, leaving other changes pending.
+    pub fn apply_changes(&self) -> Result<AppliedChanges> {
+        let members: BTreeSet<String> = self.members().into_iter().collect();
+        self.server
+            .apply_changes_matching(|name| members.contains(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{drag, marker, pose_at, server};
+    use crate::ServerConfig;
+    use r2r::visualization_msgs::msg::InteractiveMarkerFeedback;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn poses_and_erases_apply_across_a_group() {
+        let server = server(ServerConfig::default());
+        let group = server.group("arm");
+        group.insert(marker("a"));
+        group.insert(marker("b"));
+        server.insert(marker("other"));
+        assert_eq!(group.apply_changes().unwrap().full_updates, 2);
+        // The marker outside the group is left pending
+        let pending = server.pending_changes();
+        assert_eq!((pending.len(), pending[0].name()), (1, "other"));
+        server.apply_changes().unwrap();
+
+        for name in group.members() {
+            assert!(server.set_pose(&name, pose_at(1.0), None));
+        }
+        server.set_pose("other", pose_at(1.0), None);
+        let applied = server.apply_changes_for_group("arm").unwrap();
+        assert_eq!(applied.pose_updates, 2);
+        let pending = server.pending_changes();
+        assert_eq!((pending.len(), pending[0].name()), (1, "other"));
+
+        server.erase_group("arm");
+        assert_eq!(server.apply_changes().unwrap().erases, 2);
+        assert!(server.get("a").is_none() && server.get("b").is_none());
+        assert!(server.get("other").is_some());
+        assert!(server.groups().is_empty());
+    }
+
+    #[tokio::test]
+    async fn member_feedback_reaches_the_group_callback() {
+        let server = server(ServerConfig::default());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&received);
+        server.set_group_callback(
+            "arm",
+            Some(Arc::new(move |feedback: InteractiveMarkerFeedback| {
+                sink.lock().unwrap().push(feedback.marker_name);
+            })),
+            InteractiveMarkerFeedback::POSE_UPDATE as u8,
+        );
+        let group = server.group("arm");
+        group.insert(marker("a"));
+        server.insert(marker("other"));
+        server.apply_changes().unwrap();
+        // Markers added later get the callback too
+        server.insert(marker("b"));
+        server.apply_changes().unwrap();
+        assert!(group.add("b"));
+
+        for name in ["a", "b", "other"] {
+            server.handle_feedback(drag(name, pose_at(1.0)));
+        }
+        assert_eq!(*received.lock().unwrap(), ["a", "b"]);
+    }
+}
@@ -0,0 +1,289 @@
+// Content hashing of ROS messages. The generated message types implement Serialize but not Hash
+// (they contain floats), so values are hashed by feeding everything serde visits into a hasher.
+
+use r2r::visualization_msgs::msg::InteractiveMarker;
+use serde::ser::{self, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+pub(crate) fn content_hash<T: Serialize + ?Sized>(value: &T) -> u64 {
+    let mut serializer = HashSerializer(DefaultHasher::new());
+    // Hashing cannot fail, the error type only exists to satisfy serde
+    let _ = value.serialize(&mut serializer);
+    serializer.0.finish()
+}
+
+// Hash of everything that a pose update cannot express: the controls, menu, description and scale
+pub(crate) fn appearance_hash(marker: &InteractiveMarker) -> u64 {
+    content_hash(&(
+        &marker.description,
+        marker.scale,
+        &marker.menu_entries,
+        &marker.controls,
+    ))
+}
+
+struct HashSerializer(DefaultHasher);
+
+#[derive(Debug)]
+struct Error;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("hashing failed")
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Error
+    }
+}
+
+macro_rules! hash_primitive {
+    ($($method:ident: $ty:ty),* $(,)?) => {
+        $(fn $method(self, v: $ty) -> Result<(), Error> {
+            v.hash(&mut self.0);
+            Ok(())
+        })*
+    };
+}
+
+impl ser::Serializer for &mut HashSerializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    hash_primitive!(
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_char: char,
+        serialize_str: &str,
+        serialize_bytes: &[u8],
+    );
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        v.to_bits().hash(&mut self.0);
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        v.to_bits().hash(&mut self.0);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        0u8.hash(&mut self.0);
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        1u8.hash(&mut self.0);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Error> {
+        variant_index.hash(&mut self.0);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        variant_index.hash(&mut self.0);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self, Error> {
+        len.hash(&mut self.0);
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        variant_index.hash(&mut self.0);
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self, Error> {
+        len.hash(&mut self.0);
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        variant_index.hash(&mut self.0);
+        Ok(self)
+    }
+}
+
+macro_rules! hash_compound {
+    ($($trait:ident :: $method:ident),* $(,)?) => {
+        $(impl ser::$trait for &mut HashSerializer {
+            type Ok = ();
+            type Error = Error;
+
+            fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+                value.serialize(&mut **self)
+            }
+
+            fn end(self) -> Result<(), Error> {
+                Ok(())
+            }
+        })*
+    };
+}
+
+hash_compound!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field,
+);
+
+impl ser::SerializeMap for &mut HashSerializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut HashSerializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut HashSerializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appearance_ignores_the_pose() {
+        let marker = InteractiveMarker {
+            description: "box".to_string(),
+            ..Default::default()
+        };
+        let mut moved = marker.clone();
+        moved.pose.position.x = 1.0;
+        moved.header.frame_id = "map".to_string();
+        assert_eq!(appearance_hash(&marker), appearance_hash(&moved));
+
+        let mut renamed = marker.clone();
+        renamed.description = "crate".to_string();
+        assert_ne!(appearance_hash(&marker), appearance_hash(&renamed));
+    }
+
+    #[test]
+    fn structure_is_hashed() {
+        assert_ne!(content_hash(&Some(0u8)), content_hash(&None::<u8>));
+        assert_ne!(
+            content_hash(&(vec![1u8], vec![2u8])),
+            content_hash(&(vec![1u8, 2], Vec::<u8>::new()))
+        );
+        // Floats are hashed by their bits
+        assert_ne!(content_hash(&0.0f64), content_hash(&-0.0f64));
+        assert_eq!(content_hash(&f64::NAN), content_hash(&f64::NAN));
+    }
+}
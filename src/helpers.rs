@@ -0,0 +1,177 @@
+//! Standard controls, like the helpers of the basic_controls tutorial of the C++ library: the
+//! translation arrows and rotation rings RViz draws for MOVE_AXIS and ROTATE_AXIS controls.
+
+use r2r::geometry_msgs::msg::{Point, Pose, Quaternion};
+use r2r::visualization_msgs::msg::{InteractiveMarker, InteractiveMarkerControl, Marker};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::FRAC_1_SQRT_2;
+
+/// An axis of a marker's frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    /// The normalized control orientation that turns the control's x axis onto this axis. Move
+    /// and rotate controls act along their x axis, plane controls in the plane normal to it.
+    pub fn orientation(self) -> Quaternion {
+        let (x, y, z) = match self {
+            Axis::X => (FRAC_1_SQRT_2, 0.0, 0.0),
+            Axis::Y => (0.0, 0.0, FRAC_1_SQRT_2),
+            Axis::Z => (0.0, FRAC_1_SQRT_2, 0.0),
+        };
+        Quaternion {
+            x,
+            y,
+            z,
+            w: FRAC_1_SQRT_2,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Axis::X => "x",
+            Axis::Y => "y",
+            Axis::Z => "z",
+        }
+    }
+}
+
+const AXES: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+fn axis_control(
+    name: String,
+    axis: Axis,
+    interaction_mode: u8,
+    fixed: bool,
+) -> InteractiveMarkerControl {
+    InteractiveMarkerControl {
+        name,
+        orientation: axis.orientation(),
+        orientation_mode: if fixed {
+            InteractiveMarkerControl::FIXED
+        } else {
+            InteractiveMarkerControl::INHERIT
+        } as u8,
+        interaction_mode,
+        ..Default::default()
+    }
+}
+
+/// A grey box sized to a marker with `scale`.
+pub fn make_box(scale: f32) -> Marker {
+    let side = scale as f64 * 0.45;
+    let mut marker = Marker {
+        type_: Marker::CUBE as i32,
+        ..Default::default()
+    };
+    marker.pose.orientation.w = 1.0;
+    marker.scale.x = side;
+    marker.scale.y = side;
+    marker.scale.z = side;
+    marker.color.r = 0.5;
+    marker.color.g = 0.5;
+    marker.color.b = 0.5;
+    marker.color.a = 1.0;
+    marker
+}
+
+/// A non-interactive control that always shows a grey box.
+pub fn make_box_control(scale: f32) -> InteractiveMarkerControl {
+    InteractiveMarkerControl {
+        name: "box".to_string(),
+        orientation: Quaternion {
+            w: 1.0,
+            ..Default::default()
+        },
+        always_visible: true,
+        markers: vec![make_box(scale)],
+        ..Default::default()
+    }
+}
+
+/// Arrows moving the marker along its x, y and z axes, named `move_x`, `move_y` and `move_z`.
+/// With `fixed`, the arrows keep the orientation of the marker's frame when it is rotated.
+pub fn make_move_axis_controls(fixed: bool) -> Vec<InteractiveMarkerControl> {
+    AXES.iter()
+        .map(|&axis| {
+            axis_control(
+                format!("move_{}", axis.name()),
+                axis,
+                InteractiveMarkerControl::MOVE_AXIS as u8,
+                fixed,
+            )
+        })
+        .collect()
+}
+
+/// Rings rotating the marker around its x, y and z axes, named `rotate_x`, `rotate_y` and
+/// `rotate_z`.
+pub fn make_rotate_controls(fixed: bool) -> Vec<InteractiveMarkerControl> {
+    AXES.iter()
+        .map(|&axis| {
+            axis_control(
+                format!("rotate_{}", axis.name()),
+                axis,
+                InteractiveMarkerControl::ROTATE_AXIS as u8,
+                fixed,
+            )
+        })
+        .collect()
+}
+
+/// A control moving the marker in the plane normal to `normal`, named e.g. `move_plane_z`.
+pub fn make_plane_control(normal: Axis, fixed: bool) -> InteractiveMarkerControl {
+    axis_control(
+        format!("move_plane_{}", normal.name()),
+        normal,
+        InteractiveMarkerControl::MOVE_PLANE as u8,
+        fixed,
+    )
+}
+
+/// Move and rotate controls for all three axes.
+pub fn make_6dof_controls(fixed: bool) -> Vec<InteractiveMarkerControl> {
+    let mut controls = make_move_axis_controls(fixed);
+    controls.extend(make_rotate_controls(fixed));
+    controls
+}
+
+/// A box marker like `make6DofMarker` of the basic_controls tutorial. `interaction_mode` is
+/// given to the box control, e.g. `MOVE_3D` to drag the box itself or `NONE` to only show it.
+/// With `show_6dof`, move and rotate controls for all axes are added.
+pub fn make_6dof_marker(
+    name: &str,
+    frame_id: &str,
+    position: Point,
+    fixed: bool,
+    interaction_mode: u8,
+    show_6dof: bool,
+) -> InteractiveMarker {
+    let scale = 1.0;
+    let mut box_control = make_box_control(scale);
+    box_control.interaction_mode = interaction_mode;
+
+    let mut marker = InteractiveMarker {
+        name: name.to_string(),
+        pose: Pose {
+            position,
+            orientation: Quaternion {
+                w: 1.0,
+                ..Default::default()
+            },
+        },
+        scale,
+        controls: vec![box_control],
+        ..Default::default()
+    };
+    marker.header.frame_id = frame_id.to_string();
+    if show_6dof {
+        marker.controls.extend(make_6dof_controls(fixed));
+    }
+    marker
+}
@@ -1,24 +1,116 @@
-use futures::{Stream, StreamExt};
-use r2r::geometry_msgs::msg::Pose;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use futures::channel::mpsc::UnboundedSender;
+use futures::{Future, Stream, StreamExt};
+use r2r::geometry_msgs::msg::{
+    Pose, PoseArray, PoseStamped, PoseWithCovariance, TransformStamped, TwistWithCovariance,
+};
+use r2r::diagnostic_msgs::msg::DiagnosticArray;
+use r2r::builtin_interfaces::msg::Time;
+use r2r::nav_msgs::msg::Odometry;
 use r2r::std_msgs::msg::Header;
+use r2r::tf2_msgs::msg::TFMessage;
 use r2r::visualization_msgs::msg::{
-    InteractiveMarker, InteractiveMarkerFeedback, InteractiveMarkerPose, InteractiveMarkerUpdate,
+    InteractiveMarker, InteractiveMarkerFeedback, InteractiveMarkerInit, InteractiveMarkerPose,
+    InteractiveMarkerUpdate, MenuEntry,
 };
+use r2r::std_srvs::srv::Trigger;
 use r2r::visualization_msgs::srv::GetInteractiveMarkers;
 use r2r::{Publisher, QosProfile, ServiceRequest};
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+mod builder;
+mod builders;
+mod chunk;
+mod client;
+mod clock;
+mod complete;
+mod config;
+pub mod description;
+mod diagnostics;
+mod drag;
+mod error;
+mod events;
+mod feedback;
+mod groups;
+mod hash;
+pub mod helpers;
+mod log;
+mod math;
+mod menu_handler;
+mod pending;
+mod placement;
+mod prefix;
+mod publisher;
+#[cfg(feature = "serde")]
+mod recording;
+mod registry;
+#[cfg(feature = "serde")]
+mod scene;
+mod spawn;
+mod stats;
+mod sync;
+#[cfg(test)]
+mod tests;
+mod tf;
+mod transaction;
+mod validate;
+pub mod widgets;
+
+pub use builder::ServerBuilder;
+pub use builders::{ControlBuilder, InteractiveMarkerBuilder};
+pub use client::{ClientState, ClientStatus, InteractiveMarkerClient};
+pub use config::{DragProtection, OverflowPolicy, PoseMode, ServerConfig};
+pub use description::SceneDescription;
+pub use drag::DragSession;
+pub use error::{Error, Result};
+pub use events::ServerEvent;
+pub use feedback::{FeedbackEvent, UnknownEventType};
+pub use groups::MarkerGroup;
+pub use log::LogLevel;
+pub use menu_handler::{CheckState, EntryHandle, MenuHandler};
+pub use pending::{AppliedChanges, PendingChange};
+#[cfg(feature = "serde")]
+pub use recording::{FeedbackPlayer, FeedbackRecorder, RecordedFeedback};
+pub use registry::{RegistryStats, ServerRegistry};
+#[cfg(feature = "serde")]
+pub use scene::Scene;
+pub use placement::{
+    HeightMap, OccupancyConstraint, PlacementConstraint, PoseConstraint, Snapping, SurfaceSnap,
+};
+pub use spawn::BackgroundTask;
+pub use stats::{ClientActivity, FeedbackCounts, FeedbackDropReason, FeedbackDrops, ServerStats};
+pub use transaction::Transaction;
+pub use validate::{validate, ValidationIssue};
+
+// Marker names are shared between the marker and pending maps, so cloning one is a refcount bump
+type MarkerName = Arc<str>;
 
 type FeedbackCallbackBox = Arc<dyn Fn(InteractiveMarkerFeedback) + Send + Sync + 'static>;
 
 const DEFAULT_FEEDBACK_CB: u8 = 255;
+// How often the update topic is checked for new subscribers
+const RECONNECT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// How often wait_for_client checks for subscribers
+const CLIENT_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
+// Pose updates only carry the pose and header, so the common drag case never builds a full marker.
+// Markers are shared between the pending and published maps and only copied when one of them
+// changes a shared marker.
 #[derive(Clone)]
 enum UpdateType {
-    FullUpdate,
-    PoseUpdate,
+    FullUpdate {
+        int_marker: Arc<InteractiveMarker>,
+        appearance_hash: u64,
+    },
+    PoseUpdate { pose: Pose, header: Arc<Header> },
     Erase,
 }
 
@@ -27,428 +119,3349 @@ enum UpdateType {
 struct MarkerContext {
     pub last_feedback: SystemTime,
     pub last_client_id: String,
+    // Clients that sent MOUSE_DOWN but no MOUSE_UP yet
+    pub dragging_clients: HashMap<String, Drag>,
     pub default_feedback_cb: Option<FeedbackCallbackBox>,
     pub feedback_cbs: HashMap<u8, FeedbackCallbackBox>,
-    pub int_marker: InteractiveMarker,
+    pub int_marker: Arc<InteractiveMarker>,
+    // Shared copy of int_marker.header, handed to pose updates that keep the same frame and stamp
+    pub header: Arc<Header>,
+    // Hash of the parts of int_marker that only a full update can change
+    pub appearance_hash: u64,
+}
+
+// The published markers with the sequence number of the update that produced them, shared by
+// service responses and init messages until the next update
+struct Snapshot {
+    seq_num: u64,
+    markers: Vec<InteractiveMarker>,
+}
+
+#[derive(Default)]
+struct PoseHistory {
+    undo: VecDeque<PoseStamped>,
+    redo: Vec<PoseStamped>,
+}
+
+// A drag in progress, with the pose the marker had when it started
+#[derive(Clone)]
+struct Drag {
+    start_pose: Pose,
+    start_header: Arc<Header>,
+    started: Instant,
+    last_feedback: Instant,
+}
+
+impl Drag {
+    fn session(&self, client_id: &str) -> DragSession {
+        DragSession {
+            client_id: client_id.to_string(),
+            start_pose: self.start_pose.clone(),
+            start_header: (*self.start_header).clone(),
+            started: self.started,
+        }
+    }
+}
+
+/// Index of the update shard that carries the marker `name` when updates are spread over
+/// `num_shards` topics. Uses FNV-1a, so the mapping is stable across builds and platforms and
+/// can be reproduced by clients.
+pub fn shard_of(name: &str, num_shards: usize) -> usize {
+    if num_shards <= 1 {
+        return 0;
+    }
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    (hash % num_shards as u64) as usize
+}
+
+// Fully qualified names are kept, others are put in the topic namespace
+fn endpoint_name(topic_namespace: &str, name: &str) -> String {
+    if name.starts_with('/') {
+        name.to_string()
+    } else {
+        format!("{}/{}", topic_namespace, name)
+    }
+}
+
+// The time between two ticks at `rate` times per second, which must leave a nonzero period
+pub(crate) fn rate_period(rate: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(1.0 / rate)
+        .ok()
+        .filter(|period| !period.is_zero())
+        .ok_or(Error::InvalidRate(rate))
+}
+
+// Shares the current header when the new one has the same frame and stamp
+fn share_header(current: &Arc<Header>, header: Header) -> Arc<Header> {
+    if **current == header {
+        Arc::clone(current)
+    } else {
+        Arc::new(header)
+    }
+}
+
+fn publish_pose(
+    logger: &log::Logger,
+    publisher: &Publisher<PoseStamped>,
+    name: &str,
+    header: &Header,
+    pose: &Pose,
+) {
+    let pose = PoseStamped {
+        header: header.clone(),
+        pose: pose.clone(),
+    };
+    if let Err(e) = publisher.publish(&pose) {
+        logger.warn(format_args!("Failed to mirror the pose of '{}': {}", name, e));
+    }
 }
 
 // Struct to hold the information about an update
 #[derive(Clone)]
 struct UpdateContext {
     pub update_type: UpdateType,
-    pub int_marker: InteractiveMarker,
     pub default_feedback_cb: Option<FeedbackCallbackBox>,
     pub feedback_cbs: HashMap<u8, FeedbackCallbackBox>,
+    // Published even if the pose equals the stored one, e.g. to correct a client whose drag was
+    // not taken over
+    pub forced: bool,
+}
+
+impl UpdateContext {
+    fn new(update_type: UpdateType) -> Self {
+        Self {
+            update_type,
+            default_feedback_cb: None,
+            feedback_cbs: HashMap::new(),
+            forced: false,
+        }
+    }
+
+    // Header of the pending change, if it carries one
+    fn header(&self) -> Option<&Header> {
+        match &self.update_type {
+            UpdateType::FullUpdate { int_marker, .. } => Some(&int_marker.header),
+            UpdateType::PoseUpdate { header, .. } => Some(&**header),
+            UpdateType::Erase => None,
+        }
+    }
+
+    // Moves the pending change to a new pose. A pending full update stays a full update,
+    // and existing buffers are reused so repeated pose updates do not allocate. A shared header
+    // is only replaced when the frame or stamp actually changes.
+    fn set_pose(&mut self, pose: &Pose, header: &Header) {
+        match &mut self.update_type {
+            UpdateType::FullUpdate { int_marker, .. } => {
+                let int_marker = Arc::make_mut(int_marker);
+                int_marker.pose = pose.clone();
+                int_marker.header.clone_from(header);
+            }
+            UpdateType::PoseUpdate {
+                pose: pending_pose,
+                header: pending_header,
+            } => {
+                *pending_pose = pose.clone();
+                if **pending_header != *header {
+                    match Arc::get_mut(pending_header) {
+                        Some(pending_header) => pending_header.clone_from(header),
+                        None => *pending_header = Arc::new(header.clone()),
+                    }
+                }
+            }
+            UpdateType::Erase => {
+                self.update_type = UpdateType::PoseUpdate {
+                    pose: pose.clone(),
+                    header: Arc::new(header.clone()),
+                };
+            }
+        }
+    }
+}
+
+// ROS endpoints of the server and the tasks serving them. Replaced as a whole on rebind.
+struct Endpoints {
+    topic_namespace: String,
+    // Kept for counting the subscribers of the update topics
+    update_pubs: Vec<Publisher<InteractiveMarkerUpdate>>,
+    // Messages built by apply_changes are published by a background task
+    outgoing_tx: mpsc::UnboundedSender<publisher::Outgoing>,
+    publisher_task: Option<BackgroundTask>,
+    tasks: Vec<BackgroundTask>,
+}
+
+impl Endpoints {
+    // Placeholder used while the server is constructed, before it can bind its endpoints
+    fn unbound() -> Self {
+        Self {
+            topic_namespace: String::new(),
+            update_pubs: Vec::new(),
+            outgoing_tx: mpsc::unbounded_channel().0,
+            publisher_task: None,
+            tasks: Vec::new(),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(publisher_task) = self.publisher_task.take() {
+            publisher_task.abort();
+        }
+        self.close();
+    }
+
+    // Stops serving, but lets the publisher task finish sending what was already handed to it
+    fn close(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+        self.publisher_task = None;
+        self.outgoing_tx = mpsc::unbounded_channel().0;
+    }
+}
+
+// Shared by the handles of a server given to the application. When the last of them is dropped,
+// the server shuts down. Background tasks and callbacks installed by the server itself hold
+// detached handles without it, so they do not keep the server alive.
+struct Owner {
+    server: InteractiveMarkerServer,
+}
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        self.server.report("Shutting down", self.server.shutdown());
+    }
 }
 
+// Markers and pending updates live in concurrent maps, so feedback for one marker never waits on
+// an operation touching another. No map guard is ever held while calling into user code or while
+// accessing the other map.
 #[derive(Clone)]
 pub struct InteractiveMarkerServer {
-    pub topic_namespace: String,
-    marker_contexts: Arc<Mutex<HashMap<String, MarkerContext>>>,
-    pending_updates: Arc<Mutex<HashMap<String, UpdateContext>>>,
+    node: Arc<Mutex<r2r::Node>>,
+    // Runs the background tasks, see new_with_runner
+    spawner: Arc<spawn::Spawner>,
+    // Source of interaction times and pose update stamps, see ServerBuilder::clock
+    clock: clock::Clock,
+    // Tasks started with the server that are not tied to its endpoints
+    tasks: Arc<sync::Mutex<Vec<BackgroundTask>>>,
+    // None in detached handles, see Owner
+    owner: Option<Arc<Owner>>,
+    config: Arc<ServerConfig>,
+    logger: Arc<log::Logger>,
+    prefix: Arc<prefix::RobotPrefix>,
+    // Created on first use, or at construction with ServerConfig::transform_poses
+    tf: Arc<OnceLock<Arc<tf::TfBuffer>>>,
+    marker_contexts: Arc<DashMap<MarkerName, MarkerContext>>,
+    pending_updates: Arc<DashMap<MarkerName, UpdateContext>>,
+    // Serializes apply_changes so published sequence numbers stay ordered
+    apply_lock: Arc<sync::Mutex<()>>,
+    // The thread queueing changes of a transaction or an apply under the apply lock, which is
+    // about to publish everything pending. Its overflowing changes are admitted instead of
+    // applied early or rejected, while other threads still get the overflow policy.
+    committing: Arc<sync::Mutex<Option<ThreadId>>>,
+    // With ServerConfig::max_pose_rate, when poses were last published and whether an apply left
+    // poses pending for flush_held_poses
+    last_pose_publish: Arc<sync::Mutex<Option<Instant>>>,
+    poses_held: Arc<AtomicBool>,
+    // Set between pause and resume, when applies leave everything pending
+    paused: Arc<AtomicBool>,
+    // Cleared by deactivate, when clients see no markers and feedback is ignored
+    active: Arc<AtomicBool>,
+    // Set by the first shutdown, later ones do nothing
+    shut_down: Arc<AtomicBool>,
+    // Built on first use after an update, see marker_snapshot
+    snapshot: Arc<sync::Mutex<Option<Arc<Snapshot>>>>,
     pub sequence_number: Arc<AtomicU64>,
-    pub update_pub: Publisher<InteractiveMarkerUpdate>,
+    endpoints: Arc<sync::Mutex<Endpoints>>,
+    events: broadcast::Sender<ServerEvent>,
+    // Every received feedback message, for observers that do not register callbacks
+    all_feedback: broadcast::Sender<InteractiveMarkerFeedback>,
+    feedback_drops: Arc<stats::DropCounters>,
+    // Updates and init messages dropped after every publish attempt failed
+    publish_failures: Arc<AtomicU64>,
+    updates_published: Arc<AtomicU64>,
+    last_apply: Arc<sync::Mutex<Option<SystemTime>>>,
+    client_activity: Arc<DashMap<String, ClientActivity>>,
+    // Markers that move along with a leader, with their pose relative to it
+    followers: Arc<DashMap<MarkerName, Vec<(MarkerName, Pose)>>>,
+    // Markers that are not in the default optimistic mode
+    pose_modes: Arc<DashMap<MarkerName, PoseMode>>,
+    // Surfaces that dragged markers are snapped onto
+    surfaces: Arc<DashMap<MarkerName, Arc<dyn SurfaceSnap>>>,
+    constraints: Arc<DashMap<MarkerName, Arc<dyn PlacementConstraint>>>,
+    pose_constraints: Arc<DashMap<MarkerName, Arc<[PoseConstraint]>>>,
+    snapping: Arc<DashMap<MarkerName, Snapping>>,
+    drag_callbacks: Arc<DashMap<MarkerName, drag::DragCallbacks>>,
+    // Parents of markers attached with set_parent, which also follow their parent
+    parents: Arc<DashMap<MarkerName, MarkerName>>,
+    // Named groups of markers, by unprefixed marker names
+    groups: Arc<DashMap<String, groups::Group>>,
+    // Poses set while a client dragged the marker, queued when the drag ends
+    deferred_poses: Arc<DashMap<MarkerName, (Pose, Option<Header>)>>,
+    // Per-marker overrides of ServerConfig::allowed_clients
+    allowed_clients: Arc<DashMap<MarkerName, Option<Arc<HashSet<String>>>>>,
+    // Earlier and undone poses of markers, with ServerConfig::pose_history_depth
+    histories: Arc<DashMap<MarkerName, PoseHistory>>,
+    feedback_streams: Arc<DashMap<MarkerName, Vec<UnboundedSender<InteractiveMarkerFeedback>>>>,
+    // Publishers mirroring the pose of a marker as PoseStamped
+    pose_mirrors: Arc<DashMap<MarkerName, Publisher<PoseStamped>>>,
 }
 
 impl InteractiveMarkerServer {
     pub fn new(
         topic_namespace: &str,
         node: Arc<Mutex<r2r::Node>>,
-    ) -> Self {
-        let update_topic = format!("{}/update", topic_namespace);
-        let feedback_topic = format!("{}/feedback", topic_namespace);
-        let service_name = format!("{}/get_interactive_markers", topic_namespace);
-
-        let mut update_pub_qos = QosProfile::default();
-        let mut feedback_sub_qos = QosProfile::default();
-
-        update_pub_qos.depth = 100;
-        feedback_sub_qos.depth = 1;
+    ) -> Result<Self> {
+        Self::new_with_config(topic_namespace, node, ServerConfig::default())
+    }
 
-        let update_pub = node
-            .lock()
-            .unwrap()
-            .create_publisher::<InteractiveMarkerUpdate>(&update_topic, update_pub_qos)
-            .expect("Failed to create publisher");
+    /// Starts building a server, for setting QoS profiles and the topic layout.
+    pub fn builder(topic_namespace: &str, node: Arc<Mutex<r2r::Node>>) -> ServerBuilder {
+        ServerBuilder::new(topic_namespace, node)
+    }
 
-        let marker_contexts = Arc::new(Mutex::new(HashMap::new()));
-        let pending_updates = Arc::new(Mutex::new(HashMap::new()));
-        let sequence_number = Arc::new(AtomicU64::new(0));
+    pub fn new_with_config(
+        topic_namespace: &str,
+        node: Arc<Mutex<r2r::Node>>,
+        config: ServerConfig,
+    ) -> Result<Self> {
+        Self::start(
+            topic_namespace,
+            node,
+            config,
+            spawn::Spawner::Ambient,
+            clock::Clock::System,
+        )
+    }
 
-        let feedback_sub = node
-            .lock()
-            .unwrap()
-            .subscribe::<InteractiveMarkerFeedback>(&feedback_topic, feedback_sub_qos)
-            .unwrap();
-
-        let marker_contexts_clone = Arc::clone(&marker_contexts);
-        let pending_updates_clone = Arc::clone(&pending_updates);
-        let sequence_number_clone = Arc::clone(&sequence_number);
-
-        tokio::task::spawn(async move {
-            match Self::feedback_subscriber_callback(
-                feedback_sub,
-                marker_contexts_clone,
-                pending_updates_clone,
-                sequence_number_clone,
-            )
-            .await
-            {
-                Ok(()) => (),
-                Err(e) => r2r::log_error!("asdf", "Feedback subscriber failed with: '{}'.", e),
-            }
-        });
+    /// Creates a server that spawns its background work on `runtime` instead of the ambient
+    /// runtime, so it can be created and used from threads outside any runtime, e.g. in
+    /// synchronous applications or nodes with a dedicated runtime. Tasks returned as
+    /// `JoinHandle`s, like the one of `follow_frame`, run there too.
+    pub fn new_with_handle(
+        topic_namespace: &str,
+        node: Arc<Mutex<r2r::Node>>,
+        config: ServerConfig,
+        runtime: tokio::runtime::Handle,
+    ) -> Result<Self> {
+        Self::start(
+            topic_namespace,
+            node,
+            config,
+            spawn::Spawner::Handle(runtime),
+            clock::Clock::System,
+        )
+    }
 
-        let marker_contexts_clone = Arc::clone(&marker_contexts);
-        let sequence_number_clone = Arc::clone(&sequence_number);
+    /// Creates a server that does not spawn its background work on the ambient runtime, and
+    /// returns it together with a future running that work: serving the feedback subscription
+    /// and services, publishing updates and the periodic tasks of the configuration. The server
+    /// is deaf and mute until the future is polled, e.g. with `tokio::spawn` or in a `select!`
+    /// next to the application's own work. The future needs a tokio runtime for its timers, and
+    /// runs for as long as the server does. Tasks returned as `JoinHandle`s, like the one of
+    /// `follow_frame`, run on the ambient runtime and fail with `Error::NoRuntime` outside one.
+    pub fn new_with_runner(
+        topic_namespace: &str,
+        node: Arc<Mutex<r2r::Node>>,
+        config: ServerConfig,
+    ) -> Result<(Self, impl Future<Output = ()> + Send + 'static)> {
+        let (spawner, run) = spawn::runner();
+        let server = Self::start(topic_namespace, node, config, spawner, clock::Clock::System)?;
+        Ok((server, run))
+    }
 
-        let get_interactive_markers_service = node
-            .lock()
-            .unwrap()
-            .create_service::<GetInteractiveMarkers::Service>(&service_name, QosProfile::default()).unwrap();
+    fn start(
+        topic_namespace: &str,
+        node: Arc<Mutex<r2r::Node>>,
+        config: ServerConfig,
+        spawner: spawn::Spawner,
+        clock: clock::Clock,
+    ) -> Result<Self> {
+        let pose_period = config.max_pose_rate.map(rate_period).transpose()?;
+        let logger_name = match &config.logger_name {
+            Some(name) => name.clone(),
+            None => log::Logger::derived_name(node.lock().unwrap().logger(), topic_namespace),
+        };
+        let initial_sequence_number = config.initial_sequence_number;
+        let server = Self {
+            node,
+            spawner: Arc::new(spawner),
+            clock,
+            tasks: Arc::new(sync::Mutex::new(Vec::new())),
+            owner: None,
+            logger: Arc::new(log::Logger::new(logger_name, config.log_level)),
+            prefix: Arc::new(prefix::RobotPrefix::new(
+                &config.robot_prefix,
+                config.prefix_names,
+            )),
+            tf: Arc::new(OnceLock::new()),
+            config: Arc::new(config),
+            marker_contexts: Arc::new(DashMap::new()),
+            pending_updates: Arc::new(DashMap::new()),
+            apply_lock: Arc::new(sync::Mutex::new(())),
+            committing: Arc::new(sync::Mutex::new(None)),
+            last_pose_publish: Arc::new(sync::Mutex::new(None)),
+            poses_held: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            active: Arc::new(AtomicBool::new(true)),
+            shut_down: Arc::new(AtomicBool::new(false)),
+            snapshot: Arc::new(sync::Mutex::new(None)),
+            sequence_number: Arc::new(AtomicU64::new(initial_sequence_number)),
+            endpoints: Arc::new(sync::Mutex::new(Endpoints::unbound())),
+            events: events::channel(),
+            all_feedback: events::channel(),
+            feedback_drops: Arc::new(stats::DropCounters::default()),
+            publish_failures: Arc::new(AtomicU64::new(0)),
+            updates_published: Arc::new(AtomicU64::new(0)),
+            last_apply: Arc::new(sync::Mutex::new(None)),
+            client_activity: Arc::new(DashMap::new()),
+            followers: Arc::new(DashMap::new()),
+            pose_modes: Arc::new(DashMap::new()),
+            surfaces: Arc::new(DashMap::new()),
+            constraints: Arc::new(DashMap::new()),
+            pose_constraints: Arc::new(DashMap::new()),
+            snapping: Arc::new(DashMap::new()),
+            drag_callbacks: Arc::new(DashMap::new()),
+            parents: Arc::new(DashMap::new()),
+            groups: Arc::new(DashMap::new()),
+            deferred_poses: Arc::new(DashMap::new()),
+            allowed_clients: Arc::new(DashMap::new()),
+            histories: Arc::new(DashMap::new()),
+            feedback_streams: Arc::new(DashMap::new()),
+            pose_mirrors: Arc::new(DashMap::new()),
+        };
 
-        tokio::task::spawn(async move {
-            let result = Self::get_interactive_markers_server(
-                get_interactive_markers_service,
-                marker_contexts_clone,
-                sequence_number_clone,
-            )
-            .await;
-            match result {
-                Ok(()) => r2r::log_info!("node", "Asdf succeeded."),
-                Err(e) => r2r::log_error!("node", "Asdf service call failed with: {}.", e),
+        if server.config.transform_poses {
+            server.tf_buffer();
+        }
+        if let Some(timeout) = server.config.drag_timeout {
+            let server_clone = server.clone();
+            server.spawn_task("drag_watchdog", async move {
+                server_clone.drag_watchdog(timeout).await;
+            });
+        }
+        if let Some(period) = server.config.tf_broadcast_period {
+            let (publisher, clock) = {
+                let mut node = server.node.lock().unwrap();
+                let publisher = node.create_publisher::<TFMessage>("/tf", QosProfile::default())?;
+                (publisher, node.get_ros_clock())
             };
-        });
-
-
-        Self {
-            topic_namespace: topic_namespace.to_string(),
-            marker_contexts,
-            pending_updates,
-            sequence_number,
-            update_pub,
+            let server_clone = server.clone();
+            server.spawn_task("tf_broadcast", async move {
+                server_clone.broadcast_tf(publisher, clock, period).await;
+            });
+        }
+        if let Some(period) = server.config.diagnostics_period {
+            let (publisher, clock) = {
+                let mut node = server.node.lock().unwrap();
+                let publisher = node
+                    .create_publisher::<DiagnosticArray>("/diagnostics", QosProfile::default())?;
+                (publisher, node.get_ros_clock())
+            };
+            let server_clone = server.clone();
+            server.spawn_task("diagnostics", async move {
+                server_clone.publish_diagnostics(publisher, clock, period).await;
+            });
+        }
+        if let Some(period) = server.config.keep_alive_period {
+            let server_clone = server.clone();
+            server.spawn_task("keep_alive", async move {
+                server_clone.keep_alive(period).await;
+            });
+        }
+        if let Some(period) = pose_period {
+            let server_clone = server.clone();
+            server.spawn_task("pose_flush", async move {
+                server_clone.flush_held_poses(period).await;
+            });
         }
+        *server.endpoints.lock() = server.bind(topic_namespace)?;
+        // Late joiners on the snapshot topics see an empty marker set until the first apply
+        server.publish_init()?;
+        // Everything cloned so far is detached, the handle returned is the first owning one
+        let mut server = server;
+        server.owner = Some(Arc::new(Owner {
+            server: server.clone(),
+        }));
+        Ok(server)
     }
 
-    async fn get_interactive_markers_server(
-        mut service: impl Stream<Item = ServiceRequest<GetInteractiveMarkers::Service>> + Unpin,
-        marker_contexts: Arc<Mutex<HashMap<String, MarkerContext>>>,
-        sequence_number: Arc<AtomicU64>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        loop {
-            match service.next().await {
-                Some(request) => {
-                    let response = GetInteractiveMarkers::Response {
-                        sequence_number: sequence_number.load(Ordering::SeqCst),
-                        markers: marker_contexts.lock().unwrap().values().map(|ctx| ctx.int_marker.clone()).collect()
-                    };
-                    request
-                        .respond(response)
-                        .expect("Could not send service response.");
-                }
-                None => ()
-            }
+    // A handle for the server's own tasks and callbacks, which does not keep it from shutting
+    // down when the application drops its handles
+    fn detached(&self) -> Self {
+        Self {
+            owner: None,
+            ..self.clone()
         }
     }
 
-    async fn feedback_subscriber_callback(
-        mut subscriber: impl Stream<Item = InteractiveMarkerFeedback> + Unpin,
-        marker_contexts: Arc<Mutex<HashMap<String, MarkerContext>>>,
-        pending_updates: Arc<Mutex<HashMap<String, UpdateContext>>>,
-        sequence_number: Arc<AtomicU64>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        while let Some(feedback) = subscriber.next().await {
-            Self::process_feedback(
-                &marker_contexts,
-                &pending_updates,
-                &sequence_number,
-                feedback,
-            );
+    // Logs an error where there is no caller to return it to, e.g. in callbacks and tasks
+    fn report<T>(&self, action: &str, result: Result<T>) {
+        if let Err(e) = result {
+            self.logger.error(format_args!("{} failed with: {}.", action, e));
         }
-        Ok(())
     }
 
-    pub fn insert(&self, marker: InteractiveMarker) {
-        let mut pending_updates = self.pending_updates.lock().unwrap();
-        let name = marker.name.clone();
-
-        let update_context = pending_updates
-            .entry(name.clone())
-            .or_insert_with(|| UpdateContext {
-                update_type: UpdateType::FullUpdate,
-                int_marker: marker.clone(),
-                default_feedback_cb: None,
-                feedback_cbs: HashMap::new(),
-            });
-
-        update_context.update_type = UpdateType::FullUpdate;
-        update_context.int_marker = marker;
-
-        println!("Marker inserted with name '{}'", name);
+    // Spawns a task that runs until shutdown
+    fn spawn_task(&self, name: &'static str, task: impl Future<Output = ()> + Send + 'static) {
+        let task = self.spawner.spawn(name, task);
+        self.tasks.lock().push(task);
     }
 
-    pub fn insert_with_callback(
-        &self,
-        marker: &InteractiveMarker,
-        feedback_cb: Option<FeedbackCallbackBox>,
-        feedback_type: u8,
-    ) {
-        self.insert(marker.clone());
-        self.set_callback(&marker.name, feedback_cb, feedback_type);
+    /// The background tasks of the server: the feedback subscriber, the update publisher, the
+    /// services and the periodic tasks of the configuration. A finished task means that part of
+    /// the server stopped, e.g. because its ROS endpoint failed, which can be watched for with
+    /// `BackgroundTask::finished`. `rebind` replaces the tasks serving the endpoints.
+    pub fn background_tasks(&self) -> Vec<BackgroundTask> {
+        let mut tasks = self.tasks.lock().clone();
+        let endpoints = self.endpoints.lock();
+        tasks.extend(endpoints.publisher_task.iter().cloned());
+        tasks.extend(endpoints.tasks.iter().cloned());
+        tasks
     }
 
-    pub fn set_callback(
-        &self,
-        name: &str,
-        feedback_cb: Option<FeedbackCallbackBox>,
-        feedback_type: u8,
-    ) -> bool {
-        let mut marker_contexts = self.marker_contexts.lock().unwrap();
-        let mut pending_updates = self.pending_updates.lock().unwrap();
+    /// Erases all markers from the clients and stops the background tasks, so no stale markers
+    /// are left in RViz when the application ends. Happens automatically when the last handle
+    /// to the server is dropped, but handles held by widgets or by callbacks registered on the
+    /// server keep it alive. Afterwards the server ignores feedback and applies fail with
+    /// `Error::PublisherStopped`.
+    pub fn shutdown(&self) -> Result<()> {
+        let _apply_guard = self.apply_lock.lock();
+        // Dropping the last handle after an explicit shutdown must not publish again
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.pending_updates.clear();
+        let result = self.publish_erase_all();
+        self.marker_contexts.clear();
+        let result = result.and_then(|()| self.publish_init());
+        for task in self.tasks.lock().drain(..) {
+            task.abort();
+        }
+        self.endpoints.lock().close();
+        result
+    }
 
-        let marker_exists =
-            marker_contexts.contains_key(name) || pending_updates.contains_key(name);
+    /// Takes the markers away from clients and ignores their feedback, e.g. while a managed node
+    /// is inactive. The server keeps its markers, and applies leave changes pending until
+    /// `activate`.
+    pub fn deactivate(&self) -> Result<()> {
+        let _apply_guard = self.apply_lock.lock();
+        if !self.active.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        *self.snapshot.lock() = None;
+        self.publish_erase_all()?;
+        self.publish_init()
+    }
 
-        if !marker_exists {
-            return false;
+    /// Publishes the full marker set again after `deactivate`, including changes made while
+    /// inactive, and accepts feedback again.
+    pub fn activate(&self) -> Result<()> {
+        if self.active.swap(true, Ordering::SeqCst) {
+            return Ok(());
         }
+        *self.snapshot.lock() = None;
+        self.republish_all()?;
+        self.apply_changes()?;
+        self.publish_init()
+    }
 
-        if let Some(marker_context) = marker_contexts.get_mut(name) {
-            if feedback_type == DEFAULT_FEEDBACK_CB {
-                marker_context.default_feedback_cb = feedback_cb.clone();
-            } else {
-                if let Some(callback) = feedback_cb.clone() {
-                    marker_context.feedback_cbs.insert(feedback_type, callback);
-                } else {
-                    marker_context.feedback_cbs.remove(&feedback_type);
-                }
-            }
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    // Erases every published marker from the clients, without forgetting it. Called with the
+    // apply lock held.
+    fn publish_erase_all(&self) -> Result<()> {
+        let erases: Vec<String> = self
+            .marker_contexts
+            .iter()
+            .map(|marker_context| marker_context.key().to_string())
+            .collect();
+        if erases.is_empty() {
+            return Ok(());
         }
+        let update = InteractiveMarkerUpdate {
+            type_: InteractiveMarkerUpdate::UPDATE as u8,
+            erases,
+            ..Default::default()
+        };
+        self.send_update(update)?;
+        Ok(())
+    }
 
-        if let Some(update_context) = pending_updates.get_mut(name) {
-            if feedback_type == DEFAULT_FEEDBACK_CB {
-                update_context.default_feedback_cb = feedback_cb;
-            } else {
-                if let Some(callback) = feedback_cb {
-                    update_context.feedback_cbs.insert(feedback_type, callback);
-                } else {
-                    update_context.feedback_cbs.remove(&feedback_type);
+    /// Waits until a client subscribes to the update topic, e.g. so markers are not published
+    /// before RViz starts. Returns false if none did within `timeout`. Markers published earlier
+    /// still reach late clients through the service and `republish_on_reconnect`.
+    pub async fn wait_for_client(&self, timeout: Duration) -> bool {
+        let wait = async {
+            let mut interval = tokio::time::interval(CLIENT_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                if self.update_subscribers() > 0 {
+                    return;
                 }
             }
-        }
+        };
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
 
-        true
+    // Subscribers of the update topic, the most of any shard
+    fn update_subscribers(&self) -> usize {
+        self.endpoints
+            .lock()
+            .update_pubs
+            .iter()
+            .filter_map(|update_pub| update_pub.get_inter_process_subscription_count().ok())
+            .max()
+            .unwrap_or(0)
     }
 
-    pub fn set_pose(&self, name: &str, pose: Pose, header: Option<Header>) -> bool {
-        let marker_contexts = self.marker_contexts.lock().unwrap();
-        let mut pending_updates = self.pending_updates.lock().unwrap();
+    pub fn topic_namespace(&self) -> String {
+        self.endpoints.lock().topic_namespace.clone()
+    }
 
-        if !marker_contexts.contains_key(name) && !pending_updates.contains_key(name) {
-            return false;
+    /// Moves the server to a new topic namespace. The endpoints in the old namespace are torn
+    /// down, new ones are created, and the full marker set is republished. Markers, pending
+    /// changes and callbacks are kept.
+    pub fn rebind(&self, topic_namespace: &str) -> Result<()> {
+        let endpoints = self.bind(topic_namespace)?;
+        {
+            let mut current = self.endpoints.lock();
+            current.shutdown();
+            *current = endpoints;
         }
+        self.republish_all()?;
+        self.publish_init()
+    }
 
-        // Get the new_header before obtaining a mutable reference to pending_updates
-        let new_header = if let Some(marker_context) = marker_contexts.get(name) {
-            header.unwrap_or_else(|| marker_context.int_marker.header.clone())
-        } else if let Some(existing_update) = pending_updates.get(name) {
-            header.unwrap_or_else(|| existing_update.int_marker.header.clone())
+    // Creates the publishers, subscription and services for a namespace, and spawns the tasks
+    // serving them.
+    fn bind(&self, topic_namespace: &str) -> Result<Endpoints> {
+        let config = &self.config;
+        let node = &self.node;
+
+        let update_topic = endpoint_name(topic_namespace, &config.update_topic);
+        let feedback_topic = endpoint_name(topic_namespace, &config.feedback_topic);
+        let service_name = endpoint_name(topic_namespace, &config.service_name);
+
+        let feedback_sub_qos = config
+            .feedback_qos
+            .clone()
+            .unwrap_or_else(|| QosProfile::default().keep_last(config.feedback_queue_depth));
+
+        let update_topics = if config.update_shards > 1 {
+            (0..config.update_shards)
+                .map(|shard| format!("{}_{}", update_topic, shard))
+                .collect()
         } else {
-            // This should not happen due to the earlier check
-            return false;
+            vec![update_topic]
         };
+        let update_pubs: Vec<Publisher<InteractiveMarkerUpdate>> = update_topics
+            .iter()
+            .map(|topic| {
+                node.lock()
+                    .unwrap()
+                    .create_publisher::<InteractiveMarkerUpdate>(topic, config.update_qos.clone())
+            })
+            .collect::<r2r::Result<_>>()?;
 
-        // Now obtain a mutable reference to the update context
-        let update_context =
-            pending_updates
-                .entry(name.to_string())
-                .or_insert_with(|| UpdateContext {
-                    update_type: UpdateType::PoseUpdate,
-                    int_marker: InteractiveMarker::default(),
-                    default_feedback_cb: None,
-                    feedback_cbs: HashMap::new(),
-                });
+        let update_shards_service = if config.update_shards > 1 {
+            let service_name = format!("{}/get_update_shards", topic_namespace);
+            Some(
+                node.lock()
+                    .unwrap()
+                    .create_service::<Trigger::Service>(&service_name, config.service_qos.clone())?,
+            )
+        } else {
+            None
+        };
 
-        update_context.int_marker.pose = pose;
-        update_context.int_marker.header = new_header;
-        update_context.update_type = UpdateType::PoseUpdate;
-        true
-    }
+        let feedback_sub = node
+            .lock()
+            .unwrap()
+            .subscribe::<InteractiveMarkerFeedback>(&feedback_topic, feedback_sub_qos)?;
+
+        let get_interactive_markers_service = if config.get_interactive_markers_service {
+            Some(
+                node.lock()
+                    .unwrap()
+                    .create_service::<GetInteractiveMarkers::Service>(
+                        &service_name,
+                        config.service_qos.clone(),
+                    )?,
+            )
+        } else {
+            None
+        };
 
-    pub fn erase(&self, name: &str) -> bool {
-        let marker_contexts = self.marker_contexts.lock().unwrap();
-        let mut pending_updates = self.pending_updates.lock().unwrap();
+        let poses_service = if config.poses_service {
+            let service_name = format!("{}/get_poses", topic_namespace);
+            Some(
+                node.lock()
+                    .unwrap()
+                    .create_service::<GetInteractiveMarkers::Service>(
+                        &service_name,
+                        config.service_qos.clone(),
+                    )?,
+            )
+        } else {
+            None
+        };
 
-        if !marker_contexts.contains_key(name) && !pending_updates.contains_key(name) {
-            return false;
+        let mut init_topics = Vec::new();
+        if config.legacy_init_topic {
+            init_topics.push(format!("{}/init", topic_namespace));
         }
+        if config.snapshot_topic {
+            init_topics.push(format!("{}/snapshot", topic_namespace));
+        }
+        let init_pubs = init_topics
+            .iter()
+            .map(|topic| {
+                node.lock()
+                    .unwrap()
+                    .create_publisher::<InteractiveMarkerInit>(topic, config.init_qos.clone())
+            })
+            .collect::<r2r::Result<_>>()?;
 
-        pending_updates.insert(
-            name.to_string(),
-            UpdateContext {
-                update_type: UpdateType::Erase,
-                int_marker: InteractiveMarker::default(),
-                default_feedback_cb: None,
-                feedback_cbs: HashMap::new(),
-            },
-        );
-        true
-    }
+        let pose_array_pub = if config.pose_array_topic {
+            let topic = format!("{}/poses", topic_namespace);
+            Some(
+                node.lock()
+                    .unwrap()
+                    .create_publisher::<PoseArray>(&topic, config.update_qos.clone())?,
+            )
+        } else {
+            None
+        };
 
-    pub fn clear(&self) {
-        let mut pending_updates = self.pending_updates.lock().unwrap();
-        pending_updates.clear();
-
-        let marker_contexts = self.marker_contexts.lock().unwrap();
-        for name in marker_contexts.keys() {
-            pending_updates.insert(
-                name.clone(),
-                UpdateContext {
-                    update_type: UpdateType::Erase,
-                    int_marker: InteractiveMarker::default(),
-                    default_feedback_cb: None,
-                    feedback_cbs: HashMap::new(),
-                },
-            );
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let mut tasks = Vec::new();
+
+        if config.republish_on_reconnect {
+            let update_pub = update_pubs[0].clone();
+            let server_clone = self.detached();
+            tasks.push(self.spawner.spawn("republish_on_reconnect", async move {
+                server_clone.republish_on_reconnect(update_pub).await;
+            }));
         }
-    }
 
-    pub fn empty(&self) -> bool {
-        self.marker_contexts.lock().unwrap().is_empty()
-    }
+        let update_pubs_clone = update_pubs.clone();
+        let publishers = publisher::Publishers {
+            update_pubs,
+            init_pubs,
+            pose_array_pub,
+        };
+        let events_clone = self.events.clone();
+        let publish_failures = Arc::clone(&self.publish_failures);
+        let logger = Arc::clone(&self.logger);
+        let publisher_task = self.spawner.spawn("publisher", async move {
+            publisher::run(publishers, outgoing_rx, events_clone, publish_failures, logger).await;
+        });
 
-    pub fn size(&self) -> usize {
-        self.marker_contexts.lock().unwrap().len()
-    }
+        if let Some(mut service) = update_shards_service {
+            let update_shards = config.update_shards;
+            let logger = Arc::clone(&self.logger);
+            tasks.push(self.spawner.spawn("get_update_shards", async move {
+                while let Some(request) = service.next().await {
+                    let response = Trigger::Response {
+                        success: true,
+                        message: update_shards.to_string(),
+                    };
+                    if let Err(e) = request.respond(response) {
+                        logger.warn(format_args!("Could not send service response: {}.", e));
+                    }
+                }
+            }));
+        }
 
-    pub fn apply_changes(&self) {
-        let mut marker_contexts = self.marker_contexts.lock().unwrap();
-        let mut pending_updates = self.pending_updates.lock().unwrap();
-        let sequence_number = self.sequence_number.clone();
+        let server_clone = self.detached();
+        tasks.push(self.spawner.spawn("feedback_subscriber", async move {
+            match server_clone.feedback_subscriber_callback(feedback_sub).await {
+                Ok(()) => (),
+                Err(e) => server_clone
+                    .logger
+                    .error(format_args!("Feedback subscriber failed with: '{}'.", e)),
+            }
+        }));
 
-        if pending_updates.is_empty() {
-            println!("No changes to apply");
-            return;
+        if let Some(service) = get_interactive_markers_service {
+            let server_clone = self.detached();
+            tasks.push(self.spawner.spawn("get_interactive_markers", async move {
+                let result = server_clone.get_interactive_markers_server(service).await;
+                match result {
+                    Ok(()) => server_clone
+                        .logger
+                        .info(format_args!("The get_interactive_markers service stopped.")),
+                    Err(e) => server_clone.logger.error(format_args!(
+                        "The get_interactive_markers service failed with: {}.",
+                        e
+                    )),
+                };
+            }));
         }
 
-        let mut update = InteractiveMarkerUpdate::default();
-        update.type_ = InteractiveMarkerUpdate::UPDATE as u8;
-        update.markers = Vec::new();
-        update.poses = Vec::new();
-        update.erases = Vec::new();
+        if let Some(service) = poses_service {
+            let server_clone = self.detached();
+            tasks.push(self.spawner.spawn("get_poses", async move {
+                server_clone.get_poses_server(service).await;
+            }));
+        }
 
-        for (name, update_context) in pending_updates.iter() {
-            match update_context.update_type {
-                UpdateType::FullUpdate => {
-                    let marker_context =
-                        marker_contexts
-                            .entry(name.clone())
-                            .or_insert_with(|| MarkerContext {
-                                last_feedback: SystemTime::now(),
-                                last_client_id: "".to_string(),
-                                default_feedback_cb: update_context.default_feedback_cb.clone(),
-                                feedback_cbs: update_context.feedback_cbs.clone(),
-                                int_marker: update_context.int_marker.clone(),
-                            });
-                    marker_context.int_marker = update_context.int_marker.clone();
-                    marker_context.default_feedback_cb = update_context.default_feedback_cb.clone();
-                    marker_context.feedback_cbs = update_context.feedback_cbs.clone();
-                    update.markers.push(marker_context.int_marker.clone());
-                }
-                UpdateType::PoseUpdate => {
-                    if let Some(marker_context) = marker_contexts.get_mut(name) {
-                        marker_context.int_marker.pose = update_context.int_marker.pose.clone();
-                        marker_context.int_marker.header = update_context.int_marker.header.clone();
-
-                        let pose_update = InteractiveMarkerPose {
-                            header: marker_context.int_marker.header.clone(),
-                            pose: marker_context.int_marker.pose.clone(),
-                            name: marker_context.int_marker.name.clone(),
-                        };
-                        update.poses.push(pose_update);
-                    } else {
-                        println!("Pending pose update for non-existing marker '{}'.", name);
+        Ok(Endpoints {
+            topic_namespace: topic_namespace.to_string(),
+            update_pubs: update_pubs_clone,
+            outgoing_tx,
+            publisher_task: Some(publisher_task),
+            tasks,
+        })
+    }
+
+    // Republishes the full state whenever the update topic gains subscribers. A client that
+    // re-initializes (e.g. RViz after a reconnect) resubscribes, and the full update brings it
+    // and everyone else back in sync, menus included, without toggling the display.
+    async fn republish_on_reconnect(&self, update_pub: Publisher<InteractiveMarkerUpdate>) {
+        let mut subscribers = 0;
+        let mut interval = tokio::time::interval(RECONNECT_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Ok(count) = update_pub.get_inter_process_subscription_count() else {
+                continue;
+            };
+            // An inactive server has nothing to show, so clients would only be cleared
+            if count > subscribers && self.is_active() && !self.marker_contexts.is_empty() {
+                // The endpoints were torn down if publishing stopped
+                if self.republish_all().and_then(|()| self.publish_init()).is_err() {
+                    return;
+                }
+            }
+            subscribers = count;
+        }
+    }
+
+    // Reverts markers whose drag stopped without a MOUSE_UP, e.g. because the client crashed,
+    // so a half-dragged goal is not mistaken for a placed one
+    async fn drag_watchdog(&self, timeout: Duration) {
+        // Checked at least every millisecond, also for timeouts too short to divide
+        let mut interval = tokio::time::interval((timeout / 4).max(Duration::from_millis(1)));
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            let mut reverts = Vec::new();
+            for mut marker_context in self.marker_contexts.iter_mut() {
+                let before = marker_context.dragging_clients.len();
+                let mut stale = Vec::new();
+                marker_context.dragging_clients.retain(|client_id, drag| {
+                    let alive = now.duration_since(drag.last_feedback) < timeout;
+                    if !alive {
+                        stale.push((client_id.clone(), drag.clone()));
+                    }
+                    alive
+                });
+                // Another client still dragging owns the marker now, so it is not reverted
+                if before > 0 && marker_context.dragging_clients.is_empty() {
+                    if let Some((_, drag)) = stale.first() {
+                        reverts.push((marker_context.key().clone(), drag.clone()));
                     }
                 }
-                UpdateType::Erase => {
-                    marker_contexts.remove(name);
-                    update.erases.push(name.clone());
+                for (client_id, _) in stale {
+                    let _ = self.events.send(ServerEvent::DragReverted {
+                        name: marker_context.int_marker.name.clone(),
+                        client_id,
+                    });
                 }
             }
+            if reverts.is_empty() {
+                continue;
+            }
+            for (name, drag) in reverts {
+                self.queue_pose(&name, drag.start_pose, Some((*drag.start_header).clone()));
+                self.force_pending(&name);
+                self.queue_deferred_pose(&name);
+            }
+            self.report("Applying changes", self.apply_changes());
         }
-
-        let seq_num = sequence_number.fetch_add(1, Ordering::SeqCst) + 1;
-        update.seq_num = seq_num;
-        self.update_pub
-            .publish(&update)
-            .expect("Failed to publish update");
-        pending_updates.clear();
     }
 
-    fn process_feedback(
-        marker_contexts: &Arc<Mutex<HashMap<String, MarkerContext>>>,
-        pending_updates: &Arc<Mutex<HashMap<String, UpdateContext>>>,
-        _sequence_number: &Arc<AtomicU64>,
-        feedback: InteractiveMarkerFeedback,
-    ) {
-        let mut marker_contexts = marker_contexts.lock().unwrap();
-        let name = feedback.marker_name.clone();
-
-        if let Some(marker_context) = marker_contexts.get_mut(&name) {
-            marker_context.last_feedback = SystemTime::now();
-            marker_context.last_client_id = feedback.client_id.clone();
-
-            if feedback.event_type == InteractiveMarkerFeedback::POSE_UPDATE as u8 {
-                let mut pending_updates = pending_updates.lock().unwrap();
-                let update_context =
-                    pending_updates
-                        .entry(name.clone())
-                        .or_insert_with(|| UpdateContext {
-                            update_type: UpdateType::PoseUpdate,
-                            int_marker: InteractiveMarker::default(),
-                            default_feedback_cb: None,
-                            feedback_cbs: HashMap::new(),
-                        });
+    // Publishes a keep-alive whenever a period passed without updates
+    async fn keep_alive(&self, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        let mut last_seq_num = None;
+        loop {
+            interval.tick().await;
+            self.send_keep_alive(&mut last_seq_num);
+        }
+    }
 
-                update_context.int_marker.pose = feedback.pose.clone();
-                update_context.int_marker.header = feedback.header.clone();
-                update_context.update_type = UpdateType::PoseUpdate;
-            }
+    // Sends a keep-alive unless the sequence number moved on since `last_seq_num`. Paused and
+    // inactive servers publish nothing, so they do not announce anything either.
+    fn send_keep_alive(&self, last_seq_num: &mut Option<u64>) {
+        // Under the apply lock, so a keep-alive never overtakes the update it announces
+        let _apply_guard = self.apply_lock.lock();
+        if self.is_paused() || !self.is_active() {
+            return;
+        }
+        let seq_num = self.sequence_number.load(Ordering::SeqCst);
+        if last_seq_num.replace(seq_num) != Some(seq_num) {
+            return;
+        }
+        let keep_alive = InteractiveMarkerUpdate {
+            seq_num,
+            type_: InteractiveMarkerUpdate::KEEP_ALIVE as u8,
+            ..Default::default()
+        };
+        for (shard, update) in self.split_into_shards(keep_alive).into_iter().enumerate() {
+            let update = publisher::Outgoing::Update { shard, update };
+            self.report("Publishing a keep-alive", self.send_outgoing(update));
+        }
+    }
 
-            let event_type = feedback.event_type;
-            if let Some(callback) = marker_context.feedback_cbs.get(&event_type) {
-                callback(feedback.clone());
-            } else if let Some(callback) = &marker_context.default_feedback_cb {
-                callback(feedback.clone());
+    // Publishes the poses held back by max_pose_rate once per window
+    async fn flush_held_poses(&self, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if self.poses_held.swap(false, Ordering::SeqCst) {
+                // The window has passed, so this apply must not hold the poses again
+                *self.last_pose_publish.lock() = None;
+                self.report("Applying changes", self.apply_changes());
             }
-        } else {
-            // This should also not happen
-            println!("Received feedback for unknown marker '{}', ignoring.", name);
         }
     }
 
-    pub fn get(&self, name: &str) -> Option<InteractiveMarker> {
-        let marker_contexts = self.marker_contexts.lock().unwrap();
-        let pending_updates = self.pending_updates.lock().unwrap();
+    // Broadcasts a frame at the published pose of every marker. Markers without a frame have no
+    // parent to hang the frame from and are skipped.
+    async fn broadcast_tf(
+        &self,
+        publisher: Publisher<TFMessage>,
+        clock: Arc<Mutex<r2r::Clock>>,
+        period: Duration,
+    ) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let Ok(now) = clock.lock().unwrap().get_now() else {
+                continue;
+            };
+            let stamp = r2r::Clock::to_builtin_time(&now);
+            let transforms: Vec<TransformStamped> = self
+                .marker_contexts
+                .iter()
+                .filter(|marker_context| !marker_context.header.frame_id.is_empty())
+                .map(|marker_context| TransformStamped {
+                    header: Header {
+                        stamp: stamp.clone(),
+                        frame_id: marker_context.header.frame_id.clone(),
+                    },
+                    child_frame_id: format!(
+                        "{}{}",
+                        self.config.tf_frame_prefix,
+                        marker_context.key()
+                    ),
+                    transform: math::transform_of(&marker_context.int_marker.pose),
+                })
+                .collect();
+            if transforms.is_empty() {
+                continue;
+            }
+            if let Err(e) = publisher.publish(&TFMessage { transforms }) {
+                self.logger
+                    .warn(format_args!("Failed to broadcast the marker frames: {}", e));
+            }
+        }
+    }
+
+    async fn publish_diagnostics(
+        &self,
+        publisher: Publisher<DiagnosticArray>,
+        clock: Arc<Mutex<r2r::Clock>>,
+        period: Duration,
+    ) {
+        let mut reporter = diagnostics::Reporter::default();
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let Ok(now) = clock.lock().unwrap().get_now() else {
+                continue;
+            };
+            let mut feedback = FeedbackCounts::default();
+            let mut last_feedback = None;
+            for activity in self.client_activity.iter() {
+                feedback.add(&activity);
+                last_feedback = last_feedback.max(Some(activity.last_seen));
+            }
+            let since_feedback = last_feedback.map(|last| {
+                self.clock
+                    .now()
+                    .duration_since(last)
+                    .unwrap_or(Duration::ZERO)
+            });
+            let sample = diagnostics::Sample {
+                topic_namespace: self.topic_namespace(),
+                markers: self.marker_contexts.len(),
+                pending: self.pending_updates.len(),
+                clients: self.client_activity.len(),
+                feedback: feedback.total(),
+                since_feedback,
+                publish_failures: self.publish_failures.load(Ordering::Relaxed),
+                stopped_tasks: self
+                    .background_tasks()
+                    .iter()
+                    .filter(|task| task.is_finished())
+                    .map(|task| task.name())
+                    .collect(),
+                active: self.is_active(),
+                paused: self.is_paused(),
+            };
+            let array = DiagnosticArray {
+                header: Header {
+                    stamp: r2r::Clock::to_builtin_time(&now),
+                    frame_id: String::new(),
+                },
+                status: vec![reporter.status(sample, Instant::now())],
+            };
+            if let Err(e) = publisher.publish(&array) {
+                self.logger
+                    .warn(format_args!("Failed to publish the diagnostics: {}", e));
+            }
+        }
+    }
+
+    // Hands a message to the publisher task of the current endpoints
+    fn send_outgoing(&self, outgoing: publisher::Outgoing) -> Result<()> {
+        self.endpoints
+            .lock()
+            .outgoing_tx
+            .send(outgoing)
+            .map_err(|_| Error::PublisherStopped)
+    }
+
+    // Publishes every marker as a full update, so clients that missed earlier updates catch up
+    // without calling the service.
+    fn republish_all(&self) -> Result<()> {
+        let _apply_guard = self.apply_lock.lock();
+        self.republish_locked()
+    }
+
+    fn republish_locked(&self) -> Result<()> {
+        let update = InteractiveMarkerUpdate {
+            type_: InteractiveMarkerUpdate::UPDATE as u8,
+            markers: self.marker_snapshot().markers.clone(),
+            ..Default::default()
+        };
+        self.send_update(update)?;
+        Ok(())
+    }
+
+    /// Restarts the sequence numbers at 0 and republishes every marker. Clients see the numbers
+    /// go back and reinitialize from the server, e.g. after they cached a state that is no
+    /// longer valid.
+    pub fn reset_sequence(&self) -> Result<()> {
+        let _apply_guard = self.apply_lock.lock();
+        self.sequence_number.store(0, Ordering::SeqCst);
+        *self.snapshot.lock() = None;
+        self.republish_locked()?;
+        self.publish_init()
+    }
+
+    // Numbers and publishes an update, split into parts with consecutive sequence numbers when
+    // it is larger than max_update_bytes. Returns the sequence number of the last part.
+    fn send_update(&self, update: InteractiveMarkerUpdate) -> Result<u64> {
+        let parts = match self.config.max_update_bytes {
+            Some(max_bytes) => chunk::split(update, max_bytes),
+            None => vec![update],
+        };
+        let count = parts.len() as u64;
+        let first = self.sequence_number.fetch_add(count, Ordering::SeqCst) + 1;
+        self.updates_published.fetch_add(count, Ordering::Relaxed);
+        *self.snapshot.lock() = None;
+        for (mut part, seq_num) in parts.into_iter().zip(first..) {
+            part.seq_num = seq_num;
+            for (shard, update) in self.split_into_shards(part).into_iter().enumerate() {
+                self.send_outgoing(publisher::Outgoing::Update { shard, update })?;
+            }
+        }
+        Ok(first + count - 1)
+    }
+
+    /// Counts of feedback messages dropped by the server or suspected lost in transport.
+    pub fn feedback_drops(&self) -> FeedbackDrops {
+        self.feedback_drops.snapshot()
+    }
+
+    /// Counters for monitoring dashboards: feedback by event type, dropped feedback, published
+    /// updates, the last apply and when each marker was last interacted with.
+    pub fn stats(&self) -> ServerStats {
+        let mut feedback = FeedbackCounts::default();
+        for activity in self.client_activity.iter() {
+            feedback.add(&activity);
+        }
+        let last_interaction = self
+            .marker_contexts
+            .iter()
+            .filter(|marker_context| !marker_context.last_client_id.is_empty())
+            .map(|marker_context| {
+                let name = self.prefix.unprefixed_name(marker_context.key()).to_string();
+                (name, marker_context.last_feedback)
+            })
+            .collect();
+        ServerStats {
+            markers: self.marker_contexts.len(),
+            pending: self.pending_updates.len(),
+            feedback,
+            feedback_drops: self.feedback_drops.snapshot(),
+            updates_published: self.updates_published.load(Ordering::Relaxed),
+            publish_failures: self.publish_failures.load(Ordering::Relaxed),
+            last_apply: *self.last_apply.lock(),
+            last_interaction,
+        }
+    }
+
+    /// Activity of every client that sent feedback, by client_id.
+    pub fn client_activity(&self) -> HashMap<String, ClientActivity> {
+        self.client_activity
+            .iter()
+            .map(|activity| (activity.key().clone(), *activity))
+            .collect()
+    }
+
+    /// Stream of events such as failed publishes. Each call returns an independent stream that
+    /// sees events from the moment it was created.
+    pub fn events(&self) -> impl Stream<Item = ServerEvent> + Send + Unpin {
+        events::stream(self.events.subscribe())
+    }
+
+    /// Stream of every feedback message the server receives, for all markers and including
+    /// feedback it drops, e.g. for monitoring or logging. Each call returns an independent stream
+    /// that sees feedback from the moment it was created. A stream that falls behind skips
+    /// messages.
+    pub fn all_feedback(&self) -> impl Stream<Item = InteractiveMarkerFeedback> + Send + Unpin {
+        events::stream(self.all_feedback.subscribe())
+    }
+
+    /// Stream of all feedback for the marker `name`, as an alternative to callbacks. Callbacks
+    /// still run, and each call returns an independent stream. The stream ends when the marker
+    /// is erased. For a marker that is not inserted yet, it starts once it is.
+    pub fn feedback_stream(
+        &self,
+        name: impl AsRef<str>,
+    ) -> impl Stream<Item = InteractiveMarkerFeedback> + Send + Unpin {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        self.feedback_streams
+            .entry(self.intern(&self.prefix.name(name.as_ref())))
+            .or_default()
+            .push(sender);
+        receiver
+    }
+
+    async fn get_interactive_markers_server(
+        &self,
+        mut service: impl Stream<Item = ServiceRequest<GetInteractiveMarkers::Service>> + Unpin,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        while let Some(request) = service.next().await {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::debug_span!("get_interactive_markers").entered();
+            let snapshot = self.marker_snapshot();
+            let response = GetInteractiveMarkers::Response {
+                sequence_number: snapshot.seq_num,
+                markers: snapshot.markers.clone(),
+            };
+            if let Err(e) = request.respond(response) {
+                self.logger
+                    .warn(format_args!("Could not send service response: {}.", e));
+            }
+        }
+        Ok(())
+    }
+
+    // Answers with markers that only carry a name, header and pose
+    async fn get_poses_server(
+        &self,
+        mut service: impl Stream<Item = ServiceRequest<GetInteractiveMarkers::Service>> + Unpin,
+    ) {
+        while let Some(request) = service.next().await {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::debug_span!("get_poses").entered();
+            let response = GetInteractiveMarkers::Response {
+                sequence_number: self.sequence_number.load(Ordering::SeqCst),
+                markers: self
+                    .marker_contexts
+                    .iter()
+                    .map(|ctx| InteractiveMarker {
+                        header: ctx.int_marker.header.clone(),
+                        pose: ctx.int_marker.pose.clone(),
+                        name: ctx.int_marker.name.clone(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            };
+            if let Err(e) = request.respond(response) {
+                self.logger
+                    .warn(format_args!("Could not send service response: {}.", e));
+            }
+        }
+    }
+
+    async fn feedback_subscriber_callback(
+        &self,
+        mut subscriber: impl Stream<Item = InteractiveMarkerFeedback> + Unpin,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        while let Some(feedback) = subscriber.next().await {
+            self.process_feedback(feedback);
+        }
+        Ok(())
+    }
+
+    /// The group `name` of markers, created on first use, e.g.
+    /// `server.group("arm_targets").insert(marker)`.
+    pub fn group(&self, name: &str) -> MarkerGroup {
+        MarkerGroup::new(self, name)
+    }
+
+    /// Names of all groups that have members or callbacks.
+    pub fn groups(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.groups.iter().map(|group| group.key().clone()).collect();
+        names.sort();
+        names
+    }
+
+    /// Erases all markers of the group `name` and forgets the group. The changes are published
+    /// with the next apply.
+    pub fn erase_group(&self, name: &str) {
+        self.group(name).erase();
+        self.groups.remove(name);
+    }
+
+    /// Sets the callback for feedback of `feedback_type` on all markers of the group `name`,
+    /// including markers added to the group later.
+    pub fn set_group_callback(
+        &self,
+        name: &str,
+        feedback_cb: Option<FeedbackCallbackBox>,
+        feedback_type: u8,
+    ) {
+        self.group(name).set_callback(feedback_cb, feedback_type);
+    }
+
+    /// Publishes the pending changes of the markers in the group `name` only.
+    pub fn apply_changes_for_group(&self, name: &str) -> Result<AppliedChanges> {
+        self.group(name).apply_changes()
+    }
+
+    fn leave_groups(&self, name: &str) {
+        if self.groups.is_empty() {
+            return;
+        }
+        let name = self.prefix.unprefixed_name(name);
+        for mut group in self.groups.iter_mut() {
+            group.members.remove(name);
+        }
+    }
+
+    pub fn insert(&self, marker: InteractiveMarker) {
+        self.stage_insert(marker);
+        self.auto_apply();
+    }
+
+    // Queues the insert without applying, also with ServerConfig::auto_apply
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", name = "insert", skip_all, fields(name = %marker.name))
+    )]
+    fn stage_insert(&self, mut marker: InteractiveMarker) {
+        if let Cow::Owned(name) = self.prefix.name(&marker.name) {
+            marker.name = name;
+        }
+        self.prefix.frame(&mut marker.header.frame_id);
+        if self.config.strict {
+            if let Err(issues) = validate(&marker) {
+                self.logger.warn(format_args!(
+                    "Rejected invalid marker '{}': {:?}.",
+                    marker.name, issues
+                ));
+                let _ = self.events.send(ServerEvent::InsertRejected {
+                    name: marker.name.clone(),
+                    issues,
+                });
+                return;
+            }
+        }
+        if self.config.auto_complete {
+            complete::auto_complete(&mut marker);
+        }
+
+        let appearance_hash = hash::appearance_hash(&marker);
+        if self.config.skip_duplicate_inserts && self.is_duplicate(&marker, appearance_hash) {
+            return;
+        }
+        if !self.admit_pending(&marker.name) {
+            return;
+        }
+
+        let name = self.intern(&marker.name);
+        // Re-inserting a published marker keeps its callbacks. They are copied out before the
+        // pending entry is locked.
+        let callbacks = self.marker_contexts.get(&name).map(|marker_context| {
+            (
+                marker_context.default_feedback_cb.clone(),
+                marker_context.feedback_cbs.clone(),
+            )
+        });
+        let update_type = UpdateType::FullUpdate {
+            int_marker: Arc::new(marker),
+            appearance_hash,
+        };
+        match self.pending_updates.entry(name) {
+            Entry::Occupied(mut entry) => {
+                let update_context = entry.get_mut();
+                // Only full updates carry callbacks, a pending pose update or erase has none
+                if !matches!(update_context.update_type, UpdateType::FullUpdate { .. }) {
+                    if let Some((default_feedback_cb, feedback_cbs)) = callbacks {
+                        update_context.default_feedback_cb = default_feedback_cb;
+                        update_context.feedback_cbs = feedback_cbs;
+                    }
+                }
+                update_context.update_type = update_type;
+            }
+            Entry::Vacant(entry) => {
+                let mut update_context = UpdateContext::new(update_type);
+                if let Some((default_feedback_cb, feedback_cbs)) = callbacks {
+                    update_context.default_feedback_cb = default_feedback_cb;
+                    update_context.feedback_cbs = feedback_cbs;
+                }
+                entry.insert(update_context);
+            }
+        }
+    }
+
+    // True if the marker is exactly what clients already have and nothing is pending for it.
+    // The appearance is compared by hash, the pose and header directly.
+    fn is_duplicate(&self, marker: &InteractiveMarker, appearance_hash: u64) -> bool {
+        if self.pending_updates.contains_key(marker.name.as_str()) {
+            return false;
+        }
+        self.marker_contexts
+            .get(marker.name.as_str())
+            .is_some_and(|marker_context| {
+                marker_context.appearance_hash == appearance_hash
+                    && marker_context.int_marker.pose == marker.pose
+                    && marker_context.int_marker.header == marker.header
+            })
+    }
+
+    /// Inserts a copy of the marker `name` named `new_name`, with its controls and menu, moved by
+    /// `offset` in the marker's frame. With `share_callbacks` the copy calls the same callbacks.
+    /// Returns false if `name` is unknown or `new_name` is taken.
+    pub fn duplicate(
+        &self,
+        name: impl AsRef<str>,
+        new_name: impl AsRef<str>,
+        offset: &Pose,
+        share_callbacks: bool,
+    ) -> bool {
+        let new_name = new_name.as_ref();
+        if self.contains(&self.prefix.name(new_name)) {
+            return false;
+        }
+        let Some(mut marker) = self.get(name.as_ref()) else {
+            return false;
+        };
+        let callbacks = if share_callbacks {
+            self.callbacks(&self.prefix.name(name.as_ref()))
+        } else {
+            None
+        };
+
+        marker.name = new_name.to_string();
+        marker.pose = math::transform_pose(&math::transform_of(offset), &marker.pose);
+        self.insert(marker);
+        if let Some((default_feedback_cb, feedback_cbs)) = callbacks {
+            self.set_callback(new_name, default_feedback_cb, DEFAULT_FEEDBACK_CB);
+            for (feedback_type, callback) in feedback_cbs {
+                self.set_callback(new_name, Some(callback), feedback_type);
+            }
+        }
+        true
+    }
+
+    /// Adds a "Duplicate" menu entry to the marker `name`, which inserts and publishes a copy
+    /// moved by `offset` named `<name>_copy<N>`. Copies share the callbacks and can be
+    /// duplicated further. Takes over the MENU_SELECT callback of the marker. Returns false if
+    /// the marker is unknown.
+    pub fn add_duplicate_menu_entry(&self, name: impl AsRef<str>, offset: Pose) -> bool {
+        let name = name.as_ref();
+        let Some(mut marker) = self.get(name) else {
+            return false;
+        };
+        let entry_id = marker.menu_entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+        marker.menu_entries.push(MenuEntry {
+            id: entry_id,
+            parent_id: 0,
+            title: "Duplicate".to_string(),
+            command_type: MenuEntry::FEEDBACK as u8,
+            ..Default::default()
+        });
+        self.insert(marker);
+
+        let server = self.detached();
+        self.set_callback(
+            name,
+            Some(Arc::new(move |feedback: InteractiveMarkerFeedback| {
+                if feedback.menu_entry_id != entry_id {
+                    return;
+                }
+                let copy_name = (1..=u64::MAX).find_map(|n| {
+                    let copy_name = format!("{}_copy{}", feedback.marker_name, n);
+                    (!server.contains(&server.prefix.name(&copy_name))).then_some(copy_name)
+                });
+                // A panic here would take down the feedback handler, not just this menu
+                let Some(copy_name) = copy_name else {
+                    server.logger.error(format_args!(
+                        "Ran out of names for copies of '{}'.",
+                        feedback.marker_name
+                    ));
+                    return;
+                };
+                if server.duplicate(&feedback.marker_name, copy_name, &offset, true) {
+                    server.report("Applying changes", server.apply_changes());
+                }
+            })),
+            InteractiveMarkerFeedback::MENU_SELECT as u8,
+        )
+    }
+
+    // The callbacks a marker will have after the next apply
+    fn callbacks(
+        &self,
+        name: &str,
+    ) -> Option<(Option<FeedbackCallbackBox>, HashMap<u8, FeedbackCallbackBox>)> {
+        if let Some(update_context) = self.pending_updates.get(name) {
+            match update_context.update_type {
+                UpdateType::FullUpdate { .. } => {
+                    return Some((
+                        update_context.default_feedback_cb.clone(),
+                        update_context.feedback_cbs.clone(),
+                    ))
+                }
+                UpdateType::Erase => return None,
+                UpdateType::PoseUpdate { .. } => (),
+            }
+        }
+        self.marker_contexts.get(name).map(|marker_context| {
+            (
+                marker_context.default_feedback_cb.clone(),
+                marker_context.feedback_cbs.clone(),
+            )
+        })
+    }
+
+    /// Sets how feedback poses of the marker `name` are handled, see `PoseMode`. Markers are
+    /// optimistic until set otherwise, and the mode is kept until the marker is erased. Returns
+    /// false if the marker is unknown.
+    pub fn set_pose_mode(&self, name: impl AsRef<str>, mode: PoseMode) -> bool {
+        let name = self.prefix.name(name.as_ref());
+        if !self.contains(&name) {
+            return false;
+        }
+        match mode {
+            PoseMode::Optimistic => {
+                self.pose_modes.remove(&*name);
+            }
+            PoseMode::Authoritative => {
+                self.pose_modes.insert(self.intern(&name), mode);
+            }
+        }
+        true
+    }
+
+    /// Snaps the marker `name` onto `surface` while it is dragged: the height of dragged poses
+    /// is replaced by the surface height below them, and clients are corrected with the next
+    /// apply. None stops snapping. Returns false if the marker is unknown.
+    pub fn set_surface(
+        &self,
+        name: impl AsRef<str>,
+        surface: Option<Arc<dyn SurfaceSnap>>,
+    ) -> bool {
+        let name = self.prefix.name(name.as_ref());
+        if !self.contains(&name) {
+            return false;
+        }
+        match surface {
+            Some(surface) => {
+                self.surfaces.insert(self.intern(&name), surface);
+            }
+            None => {
+                self.surfaces.remove(&*name);
+            }
+        }
+        true
+    }
+
+    /// Restricts where the marker `name` can be dragged to. Dragged poses the constraint does
+    /// not allow are rejected, the client is moved back with the next apply and a
+    /// `ServerEvent::PlacementRejected` is emitted. None removes the constraint. Returns false if
+    /// the marker is unknown.
+    pub fn set_constraint(
+        &self,
+        name: impl AsRef<str>,
+        constraint: Option<Arc<dyn PlacementConstraint>>,
+    ) -> bool {
+        let name = self.prefix.name(name.as_ref());
+        if !self.contains(&name) {
+            return false;
+        }
+        match constraint {
+            Some(constraint) => {
+                self.constraints.insert(self.intern(&name), constraint);
+            }
+            None => {
+                self.constraints.remove(&*name);
+            }
+        }
+        true
+    }
+
+    /// Limits where the marker `name` can be dragged, e.g. locking axes or keeping it inside a
+    /// workspace. Dragged poses are adjusted by the constraints in order before they are stored,
+    /// and clients are corrected with the next apply. An empty list removes all constraints.
+    /// Returns false if the marker is unknown.
+    pub fn set_pose_constraints(
+        &self,
+        name: impl AsRef<str>,
+        constraints: Vec<PoseConstraint>,
+    ) -> bool {
+        let name = self.prefix.name(name.as_ref());
+        if !self.contains(&name) {
+            return false;
+        }
+        if constraints.is_empty() {
+            self.pose_constraints.remove(&*name);
+        } else {
+            self.pose_constraints
+                .insert(self.intern(&name), constraints.into());
+        }
+        true
+    }
+
+    /// Whether a client is dragging the marker `name`, between its MOUSE_DOWN and MOUSE_UP.
+    pub fn is_dragging(&self, name: impl AsRef<str>) -> bool {
+        self.is_dragged(&self.prefix.name(name.as_ref()))
+    }
+
+    /// The drags in progress on the marker `name`, one per dragging client.
+    pub fn drag_sessions(&self, name: impl AsRef<str>) -> Vec<DragSession> {
+        self.marker_contexts
+            .get(&*self.prefix.name(name.as_ref()))
+            .map(|marker_context| {
+                marker_context
+                    .dragging_clients
+                    .iter()
+                    .map(|(client_id, drag)| drag.session(client_id))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Calls `on_drag_start` when a client starts dragging the marker `name`. Replaces the
+    /// previous drag start callback. Returns false if the marker is unknown.
+    pub fn on_drag_start(
+        &self,
+        name: impl AsRef<str>,
+        on_drag_start: impl Fn(&DragSession) + Send + Sync + 'static,
+    ) -> bool {
+        let name = self.prefix.name(name.as_ref());
+        if !self.contains(&name) {
+            return false;
+        }
+        self.drag_callbacks
+            .entry(self.intern(&name))
+            .or_default()
+            .start = Some(Arc::new(on_drag_start));
+        true
+    }
+
+    /// Calls `on_drag_end` with the session and the pose the marker was released at when a
+    /// client stops dragging the marker `name`, for applications that only want the final pose.
+    /// Not called for drags reverted by `ServerConfig::drag_timeout`. Replaces the previous drag
+    /// end callback. Returns false if the marker is unknown.
+    pub fn on_drag_end(
+        &self,
+        name: impl AsRef<str>,
+        on_drag_end: impl Fn(&DragSession, &Pose) + Send + Sync + 'static,
+    ) -> bool {
+        let name = self.prefix.name(name.as_ref());
+        if !self.contains(&name) {
+            return false;
+        }
+        self.drag_callbacks
+            .entry(self.intern(&name))
+            .or_default()
+            .end = Some(Arc::new(on_drag_end));
+        true
+    }
+
+    /// Snaps dragged poses of the marker `name` to a grid and to angle increments before they
+    /// are stored, so markers land on clean positions. Clients are corrected with the next
+    /// apply. Pose constraints are applied after snapping. None stops snapping. Returns false if
+    /// the marker is unknown.
+    pub fn set_snapping(&self, name: impl AsRef<str>, snapping: Option<Snapping>) -> bool {
+        let name = self.prefix.name(name.as_ref());
+        if !self.contains(&name) {
+            return false;
+        }
+        match snapping {
+            Some(snapping) => {
+                self.snapping.insert(self.intern(&name), snapping);
+            }
+            None => {
+                self.snapping.remove(&*name);
+            }
+        }
+        true
+    }
+
+    fn apply_pose_constraints(&self, name: &str, pose: &mut Pose) {
+        let Some(constraints) = self
+            .pose_constraints
+            .get(name)
+            .map(|constraints| Arc::clone(&constraints))
+        else {
+            return;
+        };
+        let Some(current) = self
+            .marker_contexts
+            .get(name)
+            .map(|marker_context| marker_context.int_marker.pose.clone())
+        else {
+            return;
+        };
+        for constraint in constraints.iter() {
+            constraint.apply(pose, &current);
+        }
+    }
+
+    fn placement_allowed(&self, name: &str, pose: &Pose) -> bool {
+        let Some(constraint) = self
+            .constraints
+            .get(name)
+            .map(|constraint| Arc::clone(&constraint))
+        else {
+            return true;
+        };
+        constraint.allows(pose)
+    }
+
+    fn snap_to_surface(&self, name: &str, pose: &mut Pose) {
+        let Some(surface) = self.surfaces.get(name).map(|surface| Arc::clone(&surface)) else {
+            return;
+        };
+        if let Some(z) = surface.height_at(pose.position.x, pose.position.y) {
+            pose.position.z = z;
+        }
+    }
+
+    pub fn insert_with_callback(
+        &self,
+        marker: &InteractiveMarker,
+        feedback_cb: Option<FeedbackCallbackBox>,
+        feedback_type: u8,
+    ) {
+        self.insert(marker.clone());
+        self.set_callback(&marker.name, feedback_cb, feedback_type);
+    }
+
+    /// Sets the callback for feedback of `feedback_type` on the marker `name`, or the default
+    /// callback with type 255. Callbacks run after the server released all its locks, so they may
+    /// call back into the server, e.g. `set_pose`, `insert`, `erase` or `apply_changes`.
+    pub fn set_callback(
+        &self,
+        name: impl AsRef<str>,
+        feedback_cb: Option<FeedbackCallbackBox>,
+        feedback_type: u8,
+    ) -> bool {
+        let name = self.prefix.name(name.as_ref());
+        let name = name.as_ref();
+        if !self.contains(name) {
+            return false;
+        }
+
+        if let Some(mut marker_context) = self.marker_contexts.get_mut(name) {
+            if feedback_type == DEFAULT_FEEDBACK_CB {
+                marker_context.default_feedback_cb = feedback_cb.clone();
+            } else if let Some(callback) = feedback_cb.clone() {
+                marker_context.feedback_cbs.insert(feedback_type, callback);
+            } else {
+                marker_context.feedback_cbs.remove(&feedback_type);
+            }
+        }
+
+        if let Some(mut update_context) = self.pending_updates.get_mut(name) {
+            if feedback_type == DEFAULT_FEEDBACK_CB {
+                update_context.default_feedback_cb = feedback_cb;
+            } else if let Some(callback) = feedback_cb {
+                update_context.feedback_cbs.insert(feedback_type, callback);
+            } else {
+                update_context.feedback_cbs.remove(&feedback_type);
+            }
+        }
+
+        true
+    }
+
+    /// Sets a callback for all feedback of the marker `name` that gets the event as a
+    /// `FeedbackEvent` next to the message, replacing the default callback. Feedback with an
+    /// unknown event type is not passed on.
+    pub fn set_event_callback(
+        &self,
+        name: impl AsRef<str>,
+        feedback_cb: impl Fn(FeedbackEvent, &InteractiveMarkerFeedback) + Send + Sync + 'static,
+    ) -> bool {
+        self.set_callback(
+            name,
+            Some(Arc::new(move |feedback: InteractiveMarkerFeedback| {
+                if let Ok(event) = FeedbackEvent::try_from(&feedback) {
+                    feedback_cb(event, &feedback);
+                }
+            })),
+            DEFAULT_FEEDBACK_CB,
+        )
+    }
+
+    /// Like `set_callback`, but with a callback returning a future, e.g. an `async` closure. The
+    /// future of every feedback message is spawned on the runtime given to `new_with_handle`, or
+    /// else on the runtime the callback was set from, so futures of consecutive messages may run
+    /// concurrently.
+    pub fn set_async_callback<F, Fut>(
+        &self,
+        name: impl AsRef<str>,
+        feedback_cb: F,
+        feedback_type: u8,
+    ) -> bool
+    where
+        F: Fn(InteractiveMarkerFeedback) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let spawner = Arc::clone(&self.spawner);
+        self.set_callback(
+            name,
+            Some(Arc::new(move |feedback: InteractiveMarkerFeedback| {
+                spawner.spawn_detached(feedback_cb(feedback));
+            })),
+            feedback_type,
+        )
+    }
+
+    pub fn set_pose(&self, name: impl AsRef<str>, pose: Pose, header: Option<Header>) -> bool {
+        let staged = self.stage_pose(name.as_ref(), pose, header);
+        if staged {
+            self.auto_apply();
+        }
+        staged
+    }
+
+    fn stage_pose(&self, name: &str, pose: Pose, header: Option<Header>) -> bool {
+        let name = self.prefix.name(name);
+        let name = name.as_ref();
+        if !self.contains(name) {
+            return false;
+        }
+
+        let header = header.map(|mut header| {
+            self.prefix.frame(&mut header.frame_id);
+            header
+        });
+        let (pose, header) = match header {
+            Some(header) if self.config.transform_poses => {
+                self.transform_to_marker_frame(self.tf_buffer(), name, pose, header)
+            }
+            header => (pose, header),
+        };
+        self.queue_pose_unless_dragged(name, pose, header)
+    }
+
+    /// Moves the marker `name` back to the pose it had before the last drag or `set_pose`, with
+    /// `ServerConfig::pose_history_depth`. The change is published with the next apply. Returns
+    /// false if there is nothing to undo.
+    pub fn undo(&self, name: impl AsRef<str>) -> bool {
+        self.step_history(&self.prefix.name(name.as_ref()), true)
+    }
+
+    /// Reapplies the last pose undone with `undo`. Returns false if there is nothing to redo.
+    pub fn redo(&self, name: impl AsRef<str>) -> bool {
+        self.step_history(&self.prefix.name(name.as_ref()), false)
+    }
+
+    /// The earlier poses of the marker `name` that `undo` goes back to, oldest first.
+    pub fn history(&self, name: impl AsRef<str>) -> Vec<PoseStamped> {
+        self.histories
+            .get(&*self.prefix.name(name.as_ref()))
+            .map(|history| history.undo.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Records the current pose of a marker before the API changes it
+    fn record_history(&self, name: &str) {
+        if self.config.pose_history_depth == 0 {
+            return;
+        }
+        if let Some(marker) = self.lookup(name) {
+            self.push_history(
+                name,
+                PoseStamped {
+                    header: marker.header.clone(),
+                    pose: marker.pose.clone(),
+                },
+            );
+        }
+    }
+
+    fn push_history(&self, name: &str, pose: PoseStamped) {
+        let depth = self.config.pose_history_depth;
+        if depth == 0 {
+            return;
+        }
+        let mut history = self.histories.entry(self.intern(name)).or_default();
+        if history.undo.len() == depth {
+            history.undo.pop_front();
+        }
+        history.undo.push_back(pose);
+        history.redo.clear();
+    }
+
+    fn step_history(&self, name: &str, undo: bool) -> bool {
+        let Some(current) = self.lookup(name) else {
+            return false;
+        };
+        let current = PoseStamped {
+            header: current.header.clone(),
+            pose: current.pose.clone(),
+        };
+        let target = {
+            let Some(mut history) = self.histories.get_mut(name) else {
+                return false;
+            };
+            let target = if undo {
+                history.undo.pop_back()
+            } else {
+                history.redo.pop()
+            };
+            let Some(target) = target else {
+                return false;
+            };
+            if undo {
+                history.redo.push(current);
+            } else {
+                history.undo.push_back(current);
+                if history.undo.len() > self.config.pose_history_depth {
+                    history.undo.pop_front();
+                }
+            }
+            target
+        };
+        self.queue_pose(name, target.pose, Some(target.header))
+    }
+
+    fn queue_deferred_pose(&self, name: &str) {
+        if let Some((_, (pose, header))) = self.deferred_poses.remove(name) {
+            self.queue_pose(name, pose, header);
+        }
+    }
+
+    // Queues a pose set through the API, holding it back as configured while a client drags
+    fn queue_pose_unless_dragged(&self, name: &str, pose: Pose, header: Option<Header>) -> bool {
+        if self.config.drag_protection == DragProtection::Off || !self.is_dragged(name) {
+            self.record_history(name);
+            return self.queue_pose(name, pose, header);
+        }
+        match self.config.drag_protection {
+            DragProtection::Defer => {
+                self.deferred_poses.insert(self.intern(name), (pose, header));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Positions the marker `name` from the PoseStamped messages on `topic`, e.g. the output of
+    /// a perception node. Each message is passed to `set_pose` (and transformed into the marker's
+    /// frame with `ServerConfig::transform_poses`) and published right away with
+    /// `apply_changes`. Messages arriving less than `min_period` after the last used one are
+    /// dropped. Abort the returned task to stop following the topic.
+    pub fn bind_pose_input(
+        &self,
+        name: impl AsRef<str>,
+        topic: &str,
+        min_period: Duration,
+    ) -> Result<JoinHandle<()>> {
+        self.pose_input(name.as_ref(), topic, min_period, false)
+    }
+
+    /// Keeps the marker `name` at the pose published on `topic` by another node that owns it,
+    /// e.g. a grasp pose estimator, like `bind_pose_input` but without rate limiting. While a
+    /// client drags the marker, messages are ignored so the drag is not fought, and the owner
+    /// can pick up the dragged pose from the feedback. Abort the returned task to stop syncing.
+    pub fn sync_pose_from(&self, name: impl AsRef<str>, topic: &str) -> Result<JoinHandle<()>> {
+        self.pose_input(name.as_ref(), topic, Duration::ZERO, true)
+    }
+
+    fn pose_input(
+        &self,
+        name: &str,
+        topic: &str,
+        min_period: Duration,
+        pause_while_dragged: bool,
+    ) -> Result<JoinHandle<()>> {
+        let mut subscriber = self
+            .node
+            .lock()
+            .unwrap()
+            .subscribe::<PoseStamped>(topic, QosProfile::default())?;
+        let name = name.to_string();
+        let published_name = self.prefix.name(&name).into_owned();
+        let server = self.detached();
+        self.spawner.spawn_joinable(async move {
+            let mut last_used: Option<tokio::time::Instant> = None;
+            while let Some(pose) = subscriber.next().await {
+                if pause_while_dragged && server.is_dragged(&published_name) {
+                    continue;
+                }
+                let now = tokio::time::Instant::now();
+                if last_used.is_some_and(|last_used| now - last_used < min_period) {
+                    continue;
+                }
+                last_used = Some(now);
+                if server.set_pose(&name, pose.pose, Some(pose.header)) {
+                    server.report("Applying changes", server.apply_changes());
+                }
+            }
+        })
+    }
+
+    /// Changes the frame of a marker while keeping it in place in the world, by transforming its
+    /// pose into `frame_id` with tf2. Returns false when the marker is unknown or no transform
+    /// between the frames is known.
+    pub fn reframe(&self, name: impl AsRef<str>, frame_id: impl AsRef<str>) -> bool {
+        let tf = self.tf_buffer();
+        let name = self.prefix.name(name.as_ref());
+        let name = name.as_ref();
+        let Some(marker) = self.lookup(name) else {
+            return false;
+        };
+
+        let mut header = marker.header.clone();
+        let mut frame_id = frame_id.as_ref().to_string();
+        self.prefix.frame(&mut frame_id);
+        if frame_id == header.frame_id {
+            return true;
+        }
+        let Some(transform) = tf.lookup(&frame_id, &header.frame_id) else {
+            return false;
+        };
+        header.frame_id = frame_id;
+        self.queue_pose(name, math::transform_pose(&transform, &marker.pose), Some(header))
+    }
+
+    /// Sets the pose of a marker from a pose expressed in `frame_id`, transforming it into the
+    /// marker's frame with tf2. The marker keeps its frame. Returns false when the marker is
+    /// unknown or no transform between the frames is known.
+    pub fn set_pose_in_frame(
+        &self,
+        name: impl AsRef<str>,
+        pose: Pose,
+        frame_id: impl AsRef<str>,
+    ) -> bool {
+        let tf = self.tf_buffer();
+        let name = self.prefix.name(name.as_ref());
+        let name = name.as_ref();
+        let Some(marker) = self.lookup(name) else {
+            return false;
+        };
+
+        let mut frame_id = frame_id.as_ref().to_string();
+        self.prefix.frame(&mut frame_id);
+        if frame_id == marker.header.frame_id {
+            return self.queue_pose_unless_dragged(name, pose, None);
+        }
+        let Some(transform) = tf.lookup(&marker.header.frame_id, &frame_id) else {
+            return false;
+        };
+        self.queue_pose_unless_dragged(name, math::transform_pose(&transform, &pose), None)
+    }
+
+    /// The pose of a marker, including pending changes, expressed in `frame_id` using tf2. `None`
+    /// when the marker is unknown or no transform between the frames is known.
+    pub fn get_pose_in_frame(
+        &self,
+        name: impl AsRef<str>,
+        frame_id: impl AsRef<str>,
+    ) -> Option<Pose> {
+        let tf = self.tf_buffer();
+        let name = self.prefix.name(name.as_ref());
+        let marker = self.lookup(&name)?;
+
+        let mut frame_id = frame_id.as_ref().to_string();
+        self.prefix.frame(&mut frame_id);
+        if frame_id == marker.header.frame_id {
+            return Some(marker.pose.clone());
+        }
+        let transform = tf.lookup(&frame_id, &marker.header.frame_id)?;
+        Some(math::transform_pose(&transform, &marker.pose))
+    }
+
+    // Queues a pose update for a known marker, with the name and header as they are published
+    fn queue_pose(&self, name: &str, pose: Pose, header: Option<Header>) -> bool {
+        // Get the new_header before obtaining a mutable reference to pending_updates
+        let new_header = match self.marker_contexts.get(name) {
+            Some(marker_context) => match header {
+                Some(header) => share_header(&marker_context.header, header),
+                None => Arc::clone(&marker_context.header),
+            },
+            None => Arc::new(header.unwrap_or_else(|| {
+                self.pending_updates
+                    .get(name)
+                    .and_then(|update_context| update_context.header().cloned())
+                    .unwrap_or_default()
+            })),
+        };
+
+        if !self.admit_pending(name) {
+            return false;
+        }
+        match self.pending_updates.get_mut(name) {
+            Some(mut update_context) => update_context.set_pose(&pose, &new_header),
+            None => {
+                self.pending_updates.insert(
+                    self.intern(name),
+                    UpdateContext::new(UpdateType::PoseUpdate {
+                        pose,
+                        header: new_header,
+                    }),
+                );
+            }
+        }
+        true
+    }
+
+    // Makes the pending change of `name` go out even if it does not move the marker
+    fn force_pending(&self, name: &str) {
+        if let Some(mut update_context) = self.pending_updates.get_mut(name) {
+            update_context.forced = true;
+        }
+    }
+
+    // Whether a client is dragging the marker with the published name `name`
+    fn is_dragged(&self, name: &str) -> bool {
+        self.marker_contexts
+            .get(name)
+            .is_some_and(|marker_context| !marker_context.dragging_clients.is_empty())
+    }
+
+    // The tf2 buffer, subscribing to /tf and /tf_static on first use
+    fn tf_buffer(&self) -> &Arc<tf::TfBuffer> {
+        self.tf.get_or_init(|| {
+            let tf = Arc::new(tf::TfBuffer::default());
+            // Without the subscriptions lookups fail, which callers already handle
+            match tf.subscribe(&self.node, &self.spawner) {
+                Ok(tasks) => self.tasks.lock().extend(tasks),
+                Err(e) => self
+                    .logger
+                    .error(format_args!("Subscribing to tf failed with: {}.", e)),
+            }
+            tf
+        })
+    }
+
+    /// Makes the marker `name` shadow the tf frame `frame_id`, looking it up `rate` times per
+    /// second and publishing the marker at its pose. Following pauses while a client drags the
+    /// marker and resumes when it is released. Abort the returned task to stop following. Fails
+    /// with `Error::InvalidRate` unless `rate` is positive and finite.
+    pub fn follow_frame(
+        &self,
+        name: impl AsRef<str>,
+        frame_id: impl AsRef<str>,
+        rate: f64,
+    ) -> Result<JoinHandle<()>> {
+        let period = rate_period(rate)?;
+        let name = self.prefix.name(name.as_ref()).into_owned();
+        let mut frame_id = frame_id.as_ref().to_string();
+        self.prefix.frame(&mut frame_id);
+        let tf = Arc::clone(self.tf_buffer());
+        let server = self.detached();
+        self.spawner.spawn_joinable(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if server.is_dragged(&name) {
+                    continue;
+                }
+                let Some(marker) = server.lookup(&name) else {
+                    continue;
+                };
+                let Some(transform) = tf.lookup(&marker.header.frame_id, &frame_id) else {
+                    continue;
+                };
+                let pose = math::transform_pose(&transform, &math::identity_pose());
+                if pose != marker.pose && server.queue_pose(&name, pose, None) {
+                    server.report("Applying changes", server.apply_changes());
+                }
+            }
+        })
+    }
+
+    /// Publishes the marker `name` as `nav_msgs/Odometry` on `topic`, `rate` times per second,
+    /// so a dragged marker can act as a simulated obstacle or a leader to follow. The twist is
+    /// derived from the pose change since the previous message. The odometry is in the marker's
+    /// header frame, with the marker name as child frame. Abort the returned task to stop. Fails
+    /// with `Error::InvalidRate` unless `rate` is positive and finite.
+    pub fn publish_odometry(
+        &self,
+        name: impl AsRef<str>,
+        topic: &str,
+        rate: f64,
+    ) -> Result<JoinHandle<()>> {
+        let period = rate_period(rate)?;
+        let (publisher, clock) = {
+            let mut node = self.node.lock().unwrap();
+            let publisher = node.create_publisher::<Odometry>(topic, QosProfile::default())?;
+            (publisher, node.get_ros_clock())
+        };
+        let name = self.prefix.name(name.as_ref()).into_owned();
+        let server = self.detached();
+        self.spawner.spawn_joinable(async move {
+            let mut interval = tokio::time::interval(period);
+            let mut previous: Option<(Duration, Pose)> = None;
+            loop {
+                interval.tick().await;
+                let Some(marker) = server.lookup(&name) else {
+                    previous = None;
+                    continue;
+                };
+                let Ok(now) = clock.lock().unwrap().get_now() else {
+                    continue;
+                };
+
+                let twist = match &previous {
+                    Some((then, pose)) if now > *then => {
+                        math::body_twist(pose, &marker.pose, (now - *then).as_secs_f64())
+                    }
+                    _ => Default::default(),
+                };
+                let odometry = Odometry {
+                    header: Header {
+                        stamp: r2r::Clock::to_builtin_time(&now),
+                        frame_id: marker.header.frame_id.clone(),
+                    },
+                    child_frame_id: marker.name.clone(),
+                    pose: PoseWithCovariance {
+                        pose: marker.pose.clone(),
+                        covariance: vec![0.0; 36],
+                    },
+                    twist: TwistWithCovariance {
+                        twist,
+                        covariance: vec![0.0; 36],
+                    },
+                };
+                if let Err(e) = publisher.publish(&odometry) {
+                    server.logger.warn(format_args!(
+                        "Failed to publish odometry of marker '{}': {}",
+                        name, e
+                    ));
+                }
+                previous = Some((now, marker.pose.clone()));
+            }
+        })
+    }
+
+    /// Keeps the marker `name` and a simulated entity in sync. When a client releases the
+    /// marker after dragging it, `set_entity_pose` is called with the new pose, e.g. to call a
+    /// simulator's set-entity-pose service or publish on a ros_gz topic. Poses of the entity
+    /// from `entity_poses` are reflected back onto the marker while nobody drags it. Takes over
+    /// the MOUSE_UP callback of the marker. Returns None if the marker is unknown or there is no
+    /// runtime to bridge on, otherwise abort the returned task to stop bridging.
+    pub fn bridge_sim_pose<F, Fut, S>(
+        &self,
+        name: impl AsRef<str>,
+        set_entity_pose: F,
+        mut entity_poses: S,
+    ) -> Option<JoinHandle<()>>
+    where
+        F: Fn(PoseStamped) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+        S: Stream<Item = PoseStamped> + Send + Unpin + 'static,
+    {
+        let runtime = match self.spawner.runtime() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                self.logger
+                    .error(format_args!("Bridging a simulated entity failed with: {}.", e));
+                return None;
+            }
+        };
+        let (commit_tx, mut commit_rx) = mpsc::unbounded_channel();
+        let on_release: FeedbackCallbackBox = Arc::new(move |feedback: InteractiveMarkerFeedback| {
+            let _ = commit_tx.send(PoseStamped {
+                header: feedback.header,
+                pose: feedback.pose,
+            });
+        });
+        let name = name.as_ref();
+        let mouse_up = InteractiveMarkerFeedback::MOUSE_UP as u8;
+        if !self.set_callback(name, Some(on_release), mouse_up) {
+            return None;
+        }
+
+        let name = self.prefix.name(name).into_owned();
+        let server = self.detached();
+        Some(runtime.spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(pose) = commit_rx.recv() => set_entity_pose(pose).await,
+                    Some(pose) = entity_poses.next() => {
+                        if !server.is_dragged(&name)
+                            && server.set_pose(&name, pose.pose, Some(pose.header))
+                        {
+                            server.report("Applying changes", server.apply_changes());
+                        }
+                    }
+                    else => break,
+                }
+            }
+        }))
+    }
+
+    // Expresses a pose given in another frame in the frame the marker currently has
+    fn transform_to_marker_frame(
+        &self,
+        tf: &tf::TfBuffer,
+        name: &str,
+        pose: Pose,
+        mut header: Header,
+    ) -> (Pose, Option<Header>) {
+        let pending_frame = self
+            .pending_updates
+            .get(name)
+            .and_then(|update_context| update_context.header().map(|h| h.frame_id.clone()));
+        let marker_frame = match pending_frame {
+            Some(frame_id) => frame_id,
+            None => match self.marker_contexts.get(name) {
+                Some(marker_context) => marker_context.header.frame_id.clone(),
+                None => return (pose, Some(header)),
+            },
+        };
+        if marker_frame == header.frame_id {
+            return (pose, Some(header));
+        }
+
+        match tf.lookup(&marker_frame, &header.frame_id) {
+            Some(transform) => {
+                header.frame_id = marker_frame;
+                (math::transform_pose(&transform, &pose), Some(header))
+            }
+            None => {
+                self.logger.warn(format_args!(
+                    "No transform from '{}' to '{}', moving marker '{}' to '{}'.",
+                    header.frame_id, marker_frame, name, header.frame_id
+                ));
+                (pose, Some(header))
+            }
+        }
+    }
+
+    pub fn erase(&self, name: impl AsRef<str>) -> bool {
+        let staged = self.stage_erase(name.as_ref());
+        if staged {
+            self.auto_apply();
+        }
+        staged
+    }
+
+    fn stage_erase(&self, name: &str) -> bool {
+        let name = self.prefix.name(name);
+        let name = name.as_ref();
+        if !self.contains(name) || !self.admit_pending(name) {
+            return false;
+        }
+        self.stage_erase_tree(name);
+        true
+    }
+
+    // Queues the erase of a marker and its descendants. Only the erase of the marker itself is
+    // admitted against the pending limit, so a full queue never leaves orphaned children behind.
+    fn stage_erase_tree(&self, name: &str) {
+        self.pending_updates
+            .insert(self.intern(name), UpdateContext::new(UpdateType::Erase));
+        for child in self.children_of(name) {
+            if self.contains(&child) {
+                self.stage_erase_tree(&child);
+            }
+        }
+    }
+
+    /// Inserts all `markers`, as `insert` does for each of them, without applying in between.
+    pub fn insert_many(&self, markers: impl IntoIterator<Item = InteractiveMarker>) {
+        for marker in markers {
+            self.stage_insert(marker);
+        }
+        self.auto_apply();
+    }
+
+    /// Erases all markers in `names`, as `erase` does for each of them. Returns how many were
+    /// known and staged for erasing.
+    pub fn erase_many(&self, names: &[&str]) -> usize {
+        let erased = names.iter().filter(|name| self.stage_erase(name)).count();
+        if erased > 0 {
+            self.auto_apply();
+        }
+        erased
+    }
+
+    // Publishes right away with ServerConfig::auto_apply
+    fn auto_apply(&self) {
+        if self.config.auto_apply {
+            self.report("Applying changes", self.apply_changes());
+        }
+    }
+
+    pub fn clear(&self) {
+        let names: Vec<MarkerName> = self
+            .marker_contexts
+            .iter()
+            .map(|marker_context| marker_context.key().clone())
+            .collect();
+
+        self.pending_updates.clear();
+        for name in names {
+            self.pending_updates
+                .insert(name, UpdateContext::new(UpdateType::Erase));
+        }
+    }
+
+    pub fn empty(&self) -> bool {
+        self.marker_contexts.is_empty()
+    }
+
+    pub fn size(&self) -> usize {
+        self.marker_contexts.len()
+    }
+
+    // True if the marker is either published or waiting to be inserted
+    fn contains(&self, name: &str) -> bool {
+        self.marker_contexts.contains_key(name) || self.pending_updates.contains_key(name)
+    }
+
+    // Returns the shared name of a known marker, only allocating for names not seen before
+    fn intern(&self, name: &str) -> MarkerName {
+        if let Some(marker_context) = self.marker_contexts.get(name) {
+            return marker_context.key().clone();
+        }
+        if let Some(update_context) = self.pending_updates.get(name) {
+            return update_context.key().clone();
+        }
+        Arc::from(name)
+    }
+
+    // Checks the overall pending limit before a change to `name` is queued. Changes to markers
+    // that already have a pending entry are merged into it and always fit. Erases queued by
+    // clear() are bounded by the number of markers and bypass the limit, as do the changes of
+    // the committing thread.
+    fn admit_pending(&self, name: &str) -> bool {
+        if self.pending_updates.contains_key(name) {
+            return true;
+        }
+        let pending = self.pending_updates.len();
+        if pending < self.config.max_pending_updates || self.is_committing() {
+            return true;
+        }
+
+        let policy = self.config.overflow_policy;
+        let _ = self.events.send(ServerEvent::PendingOverflow {
+            name: name.to_string(),
+            pending,
+            policy,
+        });
+        match policy {
+            OverflowPolicy::Reject => false,
+            OverflowPolicy::ApplyChanges => self.apply_changes().is_ok(),
+        }
+    }
+
+    // Runs `commit`, which queues changes under the apply lock, with the changes of this thread
+    // admitted past the pending limit
+    fn committing<T>(&self, commit: impl FnOnce() -> T) -> T {
+        *self.committing.lock() = Some(std::thread::current().id());
+        let result = commit();
+        *self.committing.lock() = None;
+        result
+    }
+
+    fn is_committing(&self) -> bool {
+        *self.committing.lock() == Some(std::thread::current().id())
+    }
+
+    /// The changes that the next `apply_changes` will publish.
+    pub fn pending_changes(&self) -> Vec<PendingChange> {
+        self.pending_updates
+            .iter()
+            .map(|update_context| {
+                let mut name = update_context.key().to_string();
+                self.prefix.strip_name(&mut name);
+                match &update_context.update_type {
+                    UpdateType::FullUpdate { int_marker, .. } => {
+                        let mut marker = InteractiveMarker::clone(int_marker);
+                        self.prefix.strip_name(&mut marker.name);
+                        PendingChange::Insert { name, marker }
+                    }
+                    UpdateType::PoseUpdate { pose, header } => PendingChange::Pose {
+                        name,
+                        pose: PoseStamped {
+                            header: (**header).clone(),
+                            pose: pose.clone(),
+                        },
+                    },
+                    UpdateType::Erase => PendingChange::Erase { name },
+                }
+            })
+            .collect()
+    }
+
+    /// Drops the pending change of the marker `name`, so the next apply leaves the marker as
+    /// clients have it. A client that dragged the marker keeps showing the dragged pose until
+    /// the marker is updated again. Returns false if nothing was pending for the marker.
+    pub fn cancel_pending(&self, name: impl AsRef<str>) -> bool {
+        self.pending_updates
+            .remove(&*self.prefix.name(name.as_ref()))
+            .is_some()
+    }
+
+    /// Drops all pending changes.
+    pub fn cancel_all_pending(&self) {
+        self.pending_updates.clear();
+    }
+
+    /// Publishes all pending changes in one update and tells what was published.
+    pub fn apply_changes(&self) -> Result<AppliedChanges> {
+        self.apply_changes_matching(|_| true)
+    }
+
+    // Publishes the pending changes of the markers whose unprefixed names match, leaving the
+    // others pending
+    pub(crate) fn apply_changes_matching(
+        &self,
+        matches: impl Fn(&str) -> bool,
+    ) -> Result<AppliedChanges> {
+        let _apply_guard = self.apply_lock.lock();
+        self.apply_locked(matches)
+    }
+
+    /// Stages changes with `stage` and, if it succeeds, publishes them in a single update together
+    /// with any changes already pending, e.g.
+    /// `server.transaction(|tx| { tx.insert(marker).erase("old"); Ok::<_, Error>(()) })`.
+    /// If `stage` returns an error nothing is changed. No other apply can publish a part of the
+    /// transaction, and its changes are admitted past `ServerConfig::max_pending_updates`.
+    pub fn transaction<T, E>(
+        &self,
+        stage: impl FnOnce(&mut Transaction) -> std::result::Result<T, E>,
+    ) -> std::result::Result<T, E>
+    where
+        E: From<Error>,
+    {
+        let mut transaction = Transaction::default();
+        let value = stage(&mut transaction)?;
+
+        let _apply_guard = self.apply_lock.lock();
+        self.committing(|| {
+            for staged in transaction.staged {
+                match staged {
+                    transaction::Staged::Insert(marker) => self.stage_insert(marker),
+                    transaction::Staged::Pose { name, pose, header } => {
+                        self.stage_pose(&name, pose, header);
+                    }
+                    transaction::Staged::Erase(name) => {
+                        self.stage_erase(&name);
+                    }
+                }
+            }
+        });
+        self.apply_locked(|_| true)?;
+        Ok(value)
+    }
+
+    /// Stops publishing changes, e.g. while a scene is rebuilt, so clients never see the
+    /// intermediate states. Applies leave their changes pending until `resume`.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Publishes everything changed while paused in one update and publishes changes again.
+    pub fn resume(&self) -> Result<AppliedChanges> {
+        self.paused.store(false, Ordering::SeqCst);
+        self.apply_changes()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    // apply_changes_matching for callers holding the apply lock
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", name = "apply_changes", skip_all, ret, err)
+    )]
+    fn apply_locked(&self, matches: impl Fn(&str) -> bool) -> Result<AppliedChanges> {
+        if self.is_paused() || !self.is_active() {
+            return Ok(AppliedChanges {
+                seq_num: self.sequence_number.load(Ordering::SeqCst),
+                ..Default::default()
+            });
+        }
+        // Followers are bounded by the leaders already pending, and applying early from here
+        // would take the apply lock again
+        self.committing(|| self.move_followers());
+
+        // Take the pending updates out of the map. Updates queued while this runs are left
+        // for the next apply.
+        let names: Vec<MarkerName> = self
+            .pending_updates
+            .iter()
+            .map(|update_context| update_context.key().clone())
+            .filter(|name| matches(self.prefix.unprefixed_name(name)))
+            .collect();
+        if !names.is_empty() && self.hold_poses(&names) {
+            return Ok(AppliedChanges {
+                seq_num: self.sequence_number.load(Ordering::SeqCst),
+                deferred: names.len(),
+                ..Default::default()
+            });
+        }
+        let pending_updates: Vec<(MarkerName, UpdateContext)> = names
+            .iter()
+            .filter_map(|name| self.pending_updates.remove(name))
+            .collect();
+
+        if pending_updates.is_empty() {
+            return Ok(AppliedChanges {
+                seq_num: self.sequence_number.load(Ordering::SeqCst),
+                ..Default::default()
+            });
+        }
 
-        if let Some(update_context) = pending_updates.get(name) {
+        // Count the update kinds up front so the message vectors are allocated exactly once.
+        let (mut num_full, mut num_poses, mut num_erases) = (0, 0, 0);
+        for (_, update_context) in &pending_updates {
             match update_context.update_type {
-                UpdateType::Erase => None,
-                UpdateType::FullUpdate => Some(update_context.int_marker.clone()),
-                UpdateType::PoseUpdate => {
-                    if let Some(marker_context) = marker_contexts.get(name) {
-                        let mut marker = marker_context.int_marker.clone();
-                        marker.pose = update_context.int_marker.pose.clone();
-                        Some(marker)
+                UpdateType::FullUpdate { .. } => num_full += 1,
+                UpdateType::PoseUpdate { .. } => num_poses += 1,
+                UpdateType::Erase => num_erases += 1,
+            }
+        }
+
+        let mut update = InteractiveMarkerUpdate {
+            type_: InteractiveMarkerUpdate::UPDATE as u8,
+            markers: Vec::with_capacity(num_full),
+            poses: Vec::with_capacity(num_poses),
+            erases: Vec::with_capacity(num_erases),
+            ..Default::default()
+        };
+        let stamp = if num_poses > 0 { self.clock.stamp() } else { None };
+
+        // Consume the pending updates so markers, names and callbacks can be moved instead of cloned.
+        for (name, update_context) in pending_updates {
+            let UpdateContext {
+                update_type,
+                default_feedback_cb,
+                feedback_cbs,
+                forced,
+            } = update_context;
+
+            match update_type {
+                UpdateType::FullUpdate {
+                    int_marker,
+                    appearance_hash,
+                } => {
+                    let header = Arc::new(int_marker.header.clone());
+                    match self.marker_contexts.entry(name) {
+                        Entry::Occupied(mut entry) => {
+                            let marker_context = entry.get_mut();
+                            // Clients already have the controls, so only the pose is resent
+                            if self.config.delta_updates
+                                && marker_context.appearance_hash == appearance_hash
+                            {
+                                update.poses.push(InteractiveMarkerPose {
+                                    header: int_marker.header.clone(),
+                                    pose: int_marker.pose.clone(),
+                                    name: int_marker.name.clone(),
+                                });
+                            } else {
+                                update.markers.push(InteractiveMarker::clone(&int_marker));
+                            }
+                            marker_context.int_marker = int_marker;
+                            marker_context.header = header;
+                            marker_context.appearance_hash = appearance_hash;
+                            marker_context.default_feedback_cb = default_feedback_cb;
+                            marker_context.feedback_cbs = feedback_cbs;
+                        }
+                        Entry::Vacant(entry) => {
+                            update.markers.push(InteractiveMarker::clone(&int_marker));
+                            entry.insert(MarkerContext {
+                                last_feedback: self.clock.now(),
+                                last_client_id: String::new(),
+                                dragging_clients: HashMap::new(),
+                                default_feedback_cb,
+                                feedback_cbs,
+                                int_marker,
+                                header,
+                                appearance_hash,
+                            });
+                        }
+                    }
+                }
+                UpdateType::PoseUpdate { pose, header } => {
+                    if let Some(mut marker_context) = self.marker_contexts.get_mut(&name) {
+                        // Clients already show the marker there, e.g. when a sync loop sets
+                        // the same pose over and over. Corrections are for clients that show
+                        // another pose, so they always go out.
+                        if !forced
+                            && marker_context.int_marker.pose == pose
+                            && (Arc::ptr_eq(&marker_context.header, &header)
+                                || *marker_context.header == *header)
+                        {
+                            continue;
+                        }
+                        let mut pose_header = (*header).clone();
+                        // Poses set without a stamp are stamped with the server's clock
+                        if let Some(stamp) = &stamp {
+                            if pose_header.stamp == Time::default() {
+                                pose_header.stamp = stamp.clone();
+                            }
+                        }
+                        update.poses.push(InteractiveMarkerPose {
+                            header: pose_header,
+                            pose: pose.clone(),
+                            name: name.to_string(),
+                        });
+                        let header_changed = !Arc::ptr_eq(&marker_context.header, &header);
+                        let int_marker = Arc::make_mut(&mut marker_context.int_marker);
+                        int_marker.pose = pose;
+                        if header_changed {
+                            int_marker.header = (*header).clone();
+                            marker_context.header = header;
+                        }
                     } else {
-                        None
+                        self.logger.debug(format_args!(
+                            "Pending pose update for non-existing marker '{}'.",
+                            name
+                        ));
+                    }
+                }
+                UpdateType::Erase => {
+                    self.marker_contexts.remove(&name);
+                    self.pose_modes.remove(&name);
+                    self.surfaces.remove(&name);
+                    self.constraints.remove(&name);
+                    self.pose_constraints.remove(&name);
+                    self.snapping.remove(&name);
+                    self.drag_callbacks.remove(&name);
+                    self.deferred_poses.remove(&name);
+                    self.allowed_clients.remove(&name);
+                    self.histories.remove(&name);
+                    self.leave_groups(&name);
+                    if self.parents.remove(&name).is_some() {
+                        self.detach_follower(&name);
                     }
+                    self.followers.remove(&name);
+                    self.feedback_streams.remove(&name);
+                    self.pose_mirrors.remove(&name);
+                    update.erases.push(name.to_string());
+                }
+            }
+        }
+
+        if update.markers.is_empty() && update.poses.is_empty() && update.erases.is_empty() {
+            return Ok(AppliedChanges {
+                seq_num: self.sequence_number.load(Ordering::SeqCst),
+                ..Default::default()
+            });
+        }
+
+        let mut applied = AppliedChanges {
+            full_updates: update.markers.len(),
+            pose_updates: update.poses.len(),
+            erases: update.erases.len(),
+            ..Default::default()
+        };
+        self.mirror_poses(&update);
+        applied.seq_num = self.send_update(update)?;
+        *self.last_apply.lock() = Some(self.clock.now());
+        self.publish_init()?;
+        Ok(applied)
+    }
+
+    // With max_pose_rate, true if the changes to `names` are all pose updates and poses were
+    // published less than a window ago. The poses then stay pending, where later poses of the
+    // same markers replace them, until flush_held_poses publishes them.
+    fn hold_poses(&self, names: &[MarkerName]) -> bool {
+        // The rate was checked when the server started
+        let Some(Ok(period)) = self.config.max_pose_rate.map(rate_period) else {
+            return false;
+        };
+        let only_poses = names.iter().all(|name| {
+            self.pending_updates.get(name).is_some_and(|update_context| {
+                matches!(update_context.update_type, UpdateType::PoseUpdate { .. })
+            })
+        });
+        let now = Instant::now();
+        let mut last_pose_publish = self.last_pose_publish.lock();
+        let in_window = last_pose_publish
+            .is_some_and(|last| now < last + period);
+        if only_poses && in_window {
+            self.poses_held.store(true, Ordering::SeqCst);
+            return true;
+        }
+        *last_pose_publish = Some(now);
+        false
+    }
+
+    /// Publishes the pose of the marker `name` as a PoseStamped on `topic` whenever an apply
+    /// changes it, whether by feedback or `set_pose`, so other nodes can follow the marker without
+    /// speaking the interactive marker protocol. The topic is transient local, so late
+    /// subscribers get the current pose. Mirroring stops when the marker is erased.
+    pub fn mirror_pose(&self, name: impl AsRef<str>, topic: &str) -> Result<()> {
+        let publisher = self.node.lock().unwrap().create_publisher::<PoseStamped>(
+            topic,
+            QosProfile::default().transient_local().keep_last(1),
+        )?;
+        let name = self.prefix.name(name.as_ref()).into_owned();
+        if let Some(marker_context) = self.marker_contexts.get(name.as_str()) {
+            publish_pose(
+                &self.logger,
+                &publisher,
+                &name,
+                &marker_context.header,
+                &marker_context.int_marker.pose,
+            );
+        }
+        self.pose_mirrors.insert(self.intern(&name), publisher);
+        Ok(())
+    }
+
+    /// Stops mirroring the pose of `name`. Returns false if it was not mirrored.
+    pub fn unmirror_pose(&self, name: impl AsRef<str>) -> bool {
+        self.pose_mirrors
+            .remove(&*self.prefix.name(name.as_ref()))
+            .is_some()
+    }
+
+    fn mirror_poses(&self, update: &InteractiveMarkerUpdate) {
+        if self.pose_mirrors.is_empty() {
+            return;
+        }
+        let poses = update
+            .markers
+            .iter()
+            .map(|marker| (&marker.name, &marker.header, &marker.pose))
+            .chain(
+                update
+                    .poses
+                    .iter()
+                    .map(|pose| (&pose.name, &pose.header, &pose.pose)),
+            );
+        for (name, header, pose) in poses {
+            if let Some(publisher) = self.pose_mirrors.get(name.as_str()) {
+                publish_pose(&self.logger, &publisher, name, header, pose);
+            }
+        }
+    }
+
+    // Makes `follower` move with `leader`, keeping `offset` as its pose relative to the leader
+    pub(crate) fn attach_follower(&self, leader: &str, follower: &str, offset: Pose) {
+        let follower = self.intern(&self.prefix.name(follower));
+        self.detach_follower(&follower);
+        self.followers
+            .entry(self.intern(&self.prefix.name(leader)))
+            .or_default()
+            .push((follower, offset));
+    }
+
+    pub(crate) fn detach_follower(&self, follower: &str) {
+        let follower = &*self.prefix.name(follower);
+        self.followers.retain(|_, followers| {
+            followers.retain(|(name, _)| &**name != follower);
+            !followers.is_empty()
+        });
+    }
+
+    // Queues pose updates for the followers of leaders with a pending pose, so they go out in
+    // the same update as their leader. Followers that lead markers themselves pass the move on,
+    // so whole hierarchies move together.
+    fn move_followers(&self) {
+        if self.followers.is_empty() {
+            return;
+        }
+        let mut leaders: Vec<MarkerName> = self
+            .followers
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|leader| self.pending_updates.contains_key(leader))
+            .collect();
+        while let Some(leader) = leaders.pop() {
+            let Some((pose, header)) = self.pending_pose(&leader) else {
+                continue;
+            };
+            let Some(followers) = self.followers.get(&leader).map(|entry| entry.clone()) else {
+                continue;
+            };
+            let leader = math::transform_of(&pose);
+            for (follower, offset) in followers {
+                if !self.contains(&follower) {
+                    continue;
+                }
+                let pose = math::transform_pose(&leader, &offset);
+                self.queue_pose(&follower, pose, Some(header.clone()));
+                if self.followers.contains_key(&follower) {
+                    leaders.push(follower);
                 }
             }
-        } else if let Some(marker_context) = marker_contexts.get(name) {
-            Some(marker_context.int_marker.clone())
+        }
+    }
+
+    // The pose and header a pending change gives a marker, None if it is erased or unchanged
+    fn pending_pose(&self, name: &str) -> Option<(Pose, Header)> {
+        let update_context = self.pending_updates.get(name)?;
+        match &update_context.update_type {
+            UpdateType::FullUpdate { int_marker, .. } => {
+                Some((int_marker.pose.clone(), int_marker.header.clone()))
+            }
+            UpdateType::PoseUpdate { pose, header } => Some((pose.clone(), (**header).clone())),
+            UpdateType::Erase => None,
+        }
+    }
+
+    /// Attaches the marker `name` to the marker `parent`, so it moves rigidly with the parent,
+    /// keeping its current pose relative to it, and is erased with it. Children can have
+    /// children of their own. A child in another frame is expressed in the parent's frame using
+    /// tf2. `None` detaches the marker again. Returns false if a marker is unknown, no transform
+    /// between their frames is known, or the parent is the marker itself or one of its children.
+    pub fn set_parent(&self, name: impl AsRef<str>, parent: Option<&str>) -> bool {
+        let name = self.prefix.name(name.as_ref()).into_owned();
+        let Some(child) = self.lookup(&name) else {
+            return false;
+        };
+        let Some(parent) = parent else {
+            if self.parents.remove(name.as_str()).is_some() {
+                self.detach_follower(&name);
+            }
+            return true;
+        };
+        let parent = self.prefix.name(parent).into_owned();
+        let Some(parent_marker) = self.lookup(&parent) else {
+            return false;
+        };
+
+        // Refuse cycles, which would move markers forever
+        let mut ancestor = Some(parent.clone());
+        while let Some(current) = ancestor {
+            if current == name {
+                return false;
+            }
+            ancestor = self
+                .parents
+                .get(current.as_str())
+                .map(|parent| parent.to_string());
+        }
+
+        let child_pose = if child.header.frame_id == parent_marker.header.frame_id {
+            child.pose.clone()
         } else {
-            None
+            let Some(transform) = self
+                .tf_buffer()
+                .lookup(&parent_marker.header.frame_id, &child.header.frame_id)
+            else {
+                return false;
+            };
+            math::transform_pose(&transform, &child.pose)
+        };
+        let offset = math::transform_pose(
+            &math::inverse(&math::transform_of(&parent_marker.pose)),
+            &child_pose,
+        );
+        self.attach_follower(&parent, &name, offset);
+        self.parents.insert(self.intern(&name), self.intern(&parent));
+        true
+    }
+
+    /// The marker `name` is attached to with `set_parent`.
+    pub fn parent(&self, name: impl AsRef<str>) -> Option<String> {
+        let mut parent = self
+            .parents
+            .get(&*self.prefix.name(name.as_ref()))?
+            .to_string();
+        self.prefix.strip_name(&mut parent);
+        Some(parent)
+    }
+
+    /// The markers attached to the marker `name` with `set_parent`, sorted.
+    pub fn children(&self, name: impl AsRef<str>) -> Vec<String> {
+        let mut children: Vec<String> = self
+            .children_of(&self.prefix.name(name.as_ref()))
+            .into_iter()
+            .map(|child| {
+                let mut child = child.to_string();
+                self.prefix.strip_name(&mut child);
+                child
+            })
+            .collect();
+        children.sort();
+        children
+    }
+
+    fn children_of(&self, name: &str) -> Vec<MarkerName> {
+        if self.parents.is_empty() {
+            return Vec::new();
+        }
+        self.parents
+            .iter()
+            .filter(|entry| &**entry.value() == name)
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    // Publishes the full marker set on the init and snapshot topics and the poses on the pose
+    // array topic, for those that are enabled
+    fn publish_init(&self) -> Result<()> {
+        if self.config.pose_array_topic {
+            self.publish_pose_array()?;
+        }
+        if !self.config.legacy_init_topic && !self.config.snapshot_topic {
+            return Ok(());
+        }
+        let snapshot = self.marker_snapshot();
+        let init = InteractiveMarkerInit {
+            server_id: self.topic_namespace(),
+            seq_num: snapshot.seq_num,
+            markers: snapshot.markers.clone(),
+        };
+        self.send_outgoing(publisher::Outgoing::Init(init))
+    }
+
+    // The published markers, collected once per update no matter how many clients ask. An update
+    // drops the snapshot only after changing the maps, and waits for a snapshot being built, so
+    // a snapshot that raced with an update is never kept.
+    fn marker_snapshot(&self) -> Arc<Snapshot> {
+        let mut cached = self.snapshot.lock();
+        if let Some(snapshot) = &*cached {
+            return Arc::clone(snapshot);
+        }
+        // Clients of an inactive server see no markers, also when they reinitialize
+        let markers = if self.is_active() {
+            self.marker_contexts
+                .iter()
+                .map(|marker_context| InteractiveMarker::clone(&marker_context.int_marker))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let snapshot = Arc::new(Snapshot {
+            seq_num: self.sequence_number.load(Ordering::SeqCst),
+            markers,
+        });
+        *cached = Some(Arc::clone(&snapshot));
+        snapshot
+    }
+
+    fn publish_pose_array(&self) -> Result<()> {
+        let mut markers: Vec<(MarkerName, Header, Pose)> = self
+            .marker_contexts
+            .iter()
+            .map(|marker_context| {
+                (
+                    marker_context.key().clone(),
+                    (*marker_context.header).clone(),
+                    marker_context.int_marker.pose.clone(),
+                )
+            })
+            .collect();
+        markers.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut header = markers
+            .first()
+            .map(|(_, header, _)| header.clone())
+            .unwrap_or_default();
+        if markers
+            .iter()
+            .any(|(_, marker_header, _)| marker_header.frame_id != header.frame_id)
+        {
+            header.frame_id.clear();
+        }
+        let poses = PoseArray {
+            header,
+            poses: markers.into_iter().map(|(_, _, pose)| pose).collect(),
+        };
+        self.send_outgoing(publisher::Outgoing::Poses(poses))
+    }
+
+    /// Index of the update topic that carries the updates of the marker `name`.
+    pub fn update_shard(&self, name: impl AsRef<str>) -> usize {
+        shard_of(&self.prefix.name(name.as_ref()), self.config.update_shards)
+    }
+
+    // Splits an update over the shards. Every shard gets a message with the same sequence number,
+    // even when nothing changed on it, so each shard on its own has a gapless sequence.
+    fn split_into_shards(&self, update: InteractiveMarkerUpdate) -> Vec<InteractiveMarkerUpdate> {
+        let num_shards = self.config.update_shards;
+        if num_shards <= 1 {
+            return vec![update];
+        }
+
+        let mut shards: Vec<InteractiveMarkerUpdate> = (0..num_shards)
+            .map(|_| InteractiveMarkerUpdate {
+                server_id: update.server_id.clone(),
+                seq_num: update.seq_num,
+                type_: update.type_,
+                ..Default::default()
+            })
+            .collect();
+        for marker in update.markers {
+            shards[shard_of(&marker.name, num_shards)].markers.push(marker);
+        }
+        for pose in update.poses {
+            shards[shard_of(&pose.name, num_shards)].poses.push(pose);
+        }
+        for name in update.erases {
+            shards[shard_of(&name, num_shards)].erases.push(name);
+        }
+        shards
+    }
+
+    /// Processes a feedback message as if it had been received on the feedback topic.
+    pub fn handle_feedback(&self, feedback: InteractiveMarkerFeedback) {
+        self.process_feedback(feedback);
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            name = "feedback",
+            skip_all,
+            fields(
+                marker = %feedback.marker_name,
+                client = %feedback.client_id,
+                event_type = feedback.event_type,
+            )
+        )
+    )]
+    fn process_feedback(&self, mut feedback: InteractiveMarkerFeedback) {
+        // Clients of an inactive server have no markers to interact with
+        if !self.is_active() {
+            return;
+        }
+        self.record_client_activity(&feedback);
+        if feedback.event_type != InteractiveMarkerFeedback::KEEP_ALIVE as u8
+            && !self.client_allowed(&feedback.marker_name, &feedback.client_id)
+        {
+            self.record_feedback_drop(&feedback, FeedbackDropReason::ClientNotAllowed);
+            return;
+        }
+        if self.all_feedback.receiver_count() > 0 {
+            let mut observed = feedback.clone();
+            self.prefix.strip_name(&mut observed.marker_name);
+            let _ = self.all_feedback.send(observed);
+        }
+        let is_pose_update = feedback.event_type == InteractiveMarkerFeedback::POSE_UPDATE as u8;
+        let (mut drag_started, mut drag_ended) = (None, None);
+        let (name, header, callback) = {
+            let Some(mut marker_context) =
+                self.marker_contexts.get_mut(feedback.marker_name.as_str())
+            else {
+                // This should also not happen
+                self.logger.debug(format_args!(
+                    "Received feedback for unknown marker '{}', ignoring.",
+                    feedback.marker_name
+                ));
+                self.record_feedback_drop(&feedback, FeedbackDropReason::UnknownMarker);
+                return;
+            };
+
+            // Unpaired mouse events mean a message was lost before it reached us
+            let now = Instant::now();
+            let lost = if feedback.event_type == InteractiveMarkerFeedback::MOUSE_DOWN as u8 {
+                let drag = Drag {
+                    start_pose: marker_context.int_marker.pose.clone(),
+                    start_header: Arc::clone(&marker_context.header),
+                    started: now,
+                    last_feedback: now,
+                };
+                drag_started = Some(drag.session(&feedback.client_id));
+                marker_context
+                    .dragging_clients
+                    .insert(feedback.client_id.clone(), drag)
+                    .map(|_| FeedbackDropReason::MissingMouseUp)
+            } else if feedback.event_type == InteractiveMarkerFeedback::MOUSE_UP as u8 {
+                drag_ended = marker_context
+                    .dragging_clients
+                    .remove(feedback.client_id.as_str())
+                    .map(|drag| drag.session(&feedback.client_id));
+                drag_ended
+                    .is_none()
+                    .then_some(FeedbackDropReason::MissingMouseDown)
+            } else {
+                if let Some(drag) = marker_context
+                    .dragging_clients
+                    .get_mut(feedback.client_id.as_str())
+                {
+                    drag.last_feedback = now;
+                }
+                None
+            };
+            if let Some(reason) = lost {
+                self.record_feedback_drop(&feedback, reason);
+            }
+
+            marker_context.last_feedback = self.clock.now();
+            // clone_from reuses the buffer, so a client that keeps dragging never reallocates its id
+            marker_context.last_client_id.clone_from(&feedback.client_id);
+
+            let callback = marker_context
+                .feedback_cbs
+                .get(&feedback.event_type)
+                .or(marker_context.default_feedback_cb.as_ref())
+                .cloned();
+            // Drags usually keep the marker's frame, so its header can be shared
+            let header = (is_pose_update && *marker_context.header == feedback.header)
+                .then(|| Arc::clone(&marker_context.header));
+            (marker_context.key().clone(), header, callback)
+        };
+
+        if is_pose_update {
+            // Grid snapping first, so the surface height is taken at the snapped position
+            if let Some(snapping) = self.snapping.get(&name).map(|snapping| *snapping) {
+                snapping.apply(&mut feedback.pose);
+            }
+            self.snap_to_surface(&name, &mut feedback.pose);
+            self.apply_pose_constraints(&name, &mut feedback.pose);
+        }
+
+        let admitted = is_pose_update && self.admit_pending(&name);
+        if is_pose_update && !admitted {
+            self.record_feedback_drop(&feedback, FeedbackDropReason::PendingOverflow);
+        }
+        let authoritative = admitted
+            && self
+                .pose_modes
+                .get(&name)
+                .is_some_and(|mode| *mode == PoseMode::Authoritative);
+        let rejected = admitted && !self.placement_allowed(&name, &feedback.pose);
+        if rejected {
+            let _ = self.events.send(ServerEvent::PlacementRejected {
+                name: feedback.marker_name.clone(),
+                client_id: feedback.client_id.clone(),
+            });
+        }
+        if authoritative || rejected {
+            // The client's pose is not taken over. Unless something already changed the pose,
+            // the stored one is resent to override the client's drag.
+            if !self.pending_updates.contains_key(&name) {
+                let stored = self
+                    .marker_contexts
+                    .get(&name)
+                    .map(|marker_context| marker_context.int_marker.pose.clone());
+                if let Some(pose) = stored {
+                    self.queue_pose(&name, pose, None);
+                }
+            }
+        } else if admitted {
+            // While a drag streams poses between two applies, the pending entry is updated in place
+            match self.pending_updates.get_mut(&name) {
+                Some(mut update_context) => {
+                    update_context.set_pose(&feedback.pose, &feedback.header)
+                }
+                None => {
+                    self.pending_updates.insert(
+                        Arc::clone(&name),
+                        UpdateContext::new(UpdateType::PoseUpdate {
+                            pose: feedback.pose.clone(),
+                            header: header.unwrap_or_else(|| Arc::new(feedback.header.clone())),
+                        }),
+                    );
+                }
+            }
+        }
+        if admitted {
+            // The client shows the pose it dragged to, which snapping, constraints or a
+            // correction may have moved back to the stored pose
+            self.force_pending(&name);
+        }
+
+        // Streams and the callback get the feedback after all other map guards are released,
+        // and it is moved into the callback
+        self.prefix.strip_name(&mut feedback.marker_name);
+        if let Some(mut senders) = self.feedback_streams.get_mut(&name) {
+            senders.retain(|sender| sender.unbounded_send(feedback.clone()).is_ok());
+        }
+        if let Some(session) = &drag_started {
+            self.push_history(
+                &name,
+                PoseStamped {
+                    header: session.start_header.clone(),
+                    pose: session.start_pose.clone(),
+                },
+            );
+        }
+        let last_drag_ended = drag_ended.is_some() && !self.is_dragged(&name);
+        if drag_started.is_some() || drag_ended.is_some() {
+            self.call_drag_callbacks(&name, drag_started, drag_ended, &feedback.pose);
+        }
+        if last_drag_ended {
+            self.queue_deferred_pose(&name);
+        }
+        if let Some(callback) = callback {
+            callback(feedback);
+        }
+    }
+
+    fn call_drag_callbacks(
+        &self,
+        name: &str,
+        started: Option<DragSession>,
+        ended: Option<DragSession>,
+        release_pose: &Pose,
+    ) {
+        let Some(callbacks) = self.drag_callbacks.get(name).map(|callbacks| callbacks.clone())
+        else {
+            return;
+        };
+        if let (Some(session), Some(on_start)) = (started, callbacks.start) {
+            on_start(&session);
+        }
+        if let (Some(session), Some(on_end)) = (ended, callbacks.end) {
+            // The stored pose went through snapping and constraints, unlike the raw release pose
+            let pose = self
+                .lookup(name)
+                .map_or_else(|| release_pose.clone(), |marker| marker.pose.clone());
+            on_end(&session, &pose);
+        }
+    }
+
+    /// Only honors feedback on the marker `name` from `clients`, overriding
+    /// `ServerConfig::allowed_clients`. `Some` with a set restricts the marker to the set, `None`
+    /// allows every client. Returns false if the marker is unknown.
+    pub fn set_allowed_clients(
+        &self,
+        name: impl AsRef<str>,
+        clients: Option<HashSet<String>>,
+    ) -> bool {
+        let name = self.prefix.name(name.as_ref());
+        if !self.contains(&name) {
+            return false;
+        }
+        self.allowed_clients
+            .insert(self.intern(&name), clients.map(Arc::new));
+        true
+    }
+
+    /// Goes back to `ServerConfig::allowed_clients` for the marker `name`.
+    pub fn reset_allowed_clients(&self, name: impl AsRef<str>) {
+        self.allowed_clients
+            .remove(&*self.prefix.name(name.as_ref()));
+    }
+
+    fn client_allowed(&self, name: &str, client_id: &str) -> bool {
+        match self.allowed_clients.get(name) {
+            Some(allowed) => allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(client_id)),
+            None => self
+                .config
+                .allowed_clients
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(client_id)),
+        }
+    }
+
+    fn record_client_activity(&self, feedback: &InteractiveMarkerFeedback) {
+        let now = self.clock.now();
+        match self.client_activity.get_mut(feedback.client_id.as_str()) {
+            Some(mut activity) => activity.record(feedback, now),
+            None => {
+                let mut activity = ClientActivity::new(now);
+                activity.record(feedback, now);
+                self.client_activity
+                    .insert(feedback.client_id.clone(), activity);
+            }
+        }
+    }
+
+    fn record_feedback_drop(&self, feedback: &InteractiveMarkerFeedback, reason: FeedbackDropReason) {
+        self.feedback_drops.record(reason);
+        let _ = self.events.send(ServerEvent::FeedbackDropped {
+            name: feedback.marker_name.clone(),
+            client_id: feedback.client_id.clone(),
+            reason,
+        });
+    }
+
+    /// The published pose of every marker, by name.
+    pub fn poses(&self) -> Vec<(String, PoseStamped)> {
+        self.marker_contexts
+            .iter()
+            .map(|ctx| {
+                let mut name = ctx.int_marker.name.clone();
+                self.prefix.strip_name(&mut name);
+                let pose = PoseStamped {
+                    header: ctx.int_marker.header.clone(),
+                    pose: ctx.int_marker.pose.clone(),
+                };
+                (name, pose)
+            })
+            .collect()
+    }
+
+    pub fn get(&self, name: impl AsRef<str>) -> Option<InteractiveMarker> {
+        let name = self.prefix.name(name.as_ref());
+        let mut marker = Arc::unwrap_or_clone(self.lookup(&name)?);
+        self.prefix.strip_name(&mut marker.name);
+        Some(marker)
+    }
+
+    /// Like `get`, but shares the marker with the server instead of copying it, which is much
+    /// cheaper for markers with many controls or mesh markers.
+    pub fn get_shared(&self, name: impl AsRef<str>) -> Option<Arc<InteractiveMarker>> {
+        let name = self.prefix.name(name.as_ref());
+        let mut marker = self.lookup(&name)?;
+        if self.prefix.unprefixed_name(&marker.name) != marker.name {
+            self.prefix.strip_name(&mut Arc::make_mut(&mut marker).name);
+        }
+        Some(marker)
+    }
+
+    // The marker as clients will see it after the next apply, under its published name. Shared
+    // with the maps unless a pending pose had to be applied to a copy.
+    fn lookup(&self, name: &str) -> Option<Arc<InteractiveMarker>> {
+        // Copy what is needed out of the pending entry before looking at the marker itself,
+        // so no guard on one map is held while locking the other.
+        let pending_pose = match self.pending_updates.get(name) {
+            Some(update_context) => match &update_context.update_type {
+                UpdateType::Erase => return None,
+                UpdateType::FullUpdate { int_marker, .. } => return Some(Arc::clone(int_marker)),
+                UpdateType::PoseUpdate { pose, header } => {
+                    Some((pose.clone(), Arc::clone(header)))
+                }
+            },
+            None => None,
+        };
+
+        let marker_context = self.marker_contexts.get(name)?;
+        let mut marker = Arc::clone(&marker_context.int_marker);
+        // Release the guard before copying the marker
+        drop(marker_context);
+        if let Some((pose, header)) = pending_pose {
+            let marker = Arc::make_mut(&mut marker);
+            marker.pose = pose;
+            marker.header = (*header).clone();
         }
+        Some(marker)
     }
 }
@@ -0,0 +1,73 @@
+// Logging of the server and its widgets through the ROS logging of r2r, under a logger named
+// after the owning node and the topic namespace so the messages can be told apart.
+
+use std::fmt;
+
+/// The least severe messages a server logs, see `ServerConfig::log_level`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Log nothing.
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+pub(crate) struct Logger {
+    name: String,
+    level: LogLevel,
+    // Errors logged so far at any level, so tests can tell that nothing failed quietly
+    #[cfg(test)]
+    pub(crate) errors: std::sync::atomic::AtomicUsize,
+}
+
+impl Logger {
+    pub(crate) fn new(name: String, level: LogLevel) -> Self {
+        Self {
+            name,
+            level,
+            #[cfg(test)]
+            errors: Default::default(),
+        }
+    }
+
+    // The node's logger with the namespace as child, e.g. `my_node.robot1.markers` for the
+    // namespace `/robot1/markers`
+    pub(crate) fn derived_name(node_logger: &str, topic_namespace: &str) -> String {
+        let namespace = topic_namespace.trim_matches('/').replace('/', ".");
+        match (node_logger.is_empty(), namespace.is_empty()) {
+            (_, true) => node_logger.to_string(),
+            (true, false) => namespace,
+            (false, false) => format!("{}.{}", node_logger, namespace),
+        }
+    }
+
+    pub(crate) fn error(&self, message: fmt::Arguments) {
+        #[cfg(test)]
+        self.errors
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        if self.level >= LogLevel::Error {
+            r2r::log_error!(&self.name, "{}", message);
+        }
+    }
+
+    pub(crate) fn warn(&self, message: fmt::Arguments) {
+        if self.level >= LogLevel::Warn {
+            r2r::log_warn!(&self.name, "{}", message);
+        }
+    }
+
+    pub(crate) fn info(&self, message: fmt::Arguments) {
+        if self.level >= LogLevel::Info {
+            r2r::log_info!(&self.name, "{}", message);
+        }
+    }
+
+    pub(crate) fn debug(&self, message: fmt::Arguments) {
+        if self.level >= LogLevel::Debug {
+            r2r::log_debug!(&self.name, "{}", message);
+        }
+    }
+}
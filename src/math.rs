@@ -0,0 +1,255 @@
+// Rigid transform math on the geometry_msgs types, just enough for moving poses between frames.
+
+use r2r::geometry_msgs::msg::{Point, Pose, Quaternion, Transform, Twist, Vector3};
+
+pub(crate) fn identity() -> Transform {
+    Transform {
+        translation: Vector3::default(),
+        rotation: Quaternion {
+            w: 1.0,
+            ..Default::default()
+        },
+    }
+}
+
+pub(crate) fn identity_pose() -> Pose {
+    Pose {
+        position: Point::default(),
+        orientation: Quaternion {
+            w: 1.0,
+            ..Default::default()
+        },
+    }
+}
+
+pub(crate) fn pose_of(transform: &Transform) -> Pose {
+    Pose {
+        position: Point {
+            x: transform.translation.x,
+            y: transform.translation.y,
+            z: transform.translation.z,
+        },
+        orientation: transform.rotation.clone(),
+    }
+}
+
+pub(crate) fn transform_of(pose: &Pose) -> Transform {
+    Transform {
+        translation: Vector3 {
+            x: pose.position.x,
+            y: pose.position.y,
+            z: pose.position.z,
+        },
+        rotation: pose.orientation.clone(),
+    }
+}
+
+pub(crate) fn multiply(a: &Quaternion, b: &Quaternion) -> Quaternion {
+    Quaternion {
+        x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+        y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+        z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+    }
+}
+
+pub(crate) fn conjugate(q: &Quaternion) -> Quaternion {
+    Quaternion {
+        x: -q.x,
+        y: -q.y,
+        z: -q.z,
+        w: q.w,
+    }
+}
+
+pub(crate) fn rotate(q: &Quaternion, v: [f64; 3]) -> [f64; 3] {
+    // v' = v + 2w(u x v) + 2u x (u x v), with u the vector part of q
+    let u = [q.x, q.y, q.z];
+    let t = [
+        2.0 * (u[1] * v[2] - u[2] * v[1]),
+        2.0 * (u[2] * v[0] - u[0] * v[2]),
+        2.0 * (u[0] * v[1] - u[1] * v[0]),
+    ];
+    [
+        v[0] + q.w * t[0] + u[1] * t[2] - u[2] * t[1],
+        v[1] + q.w * t[1] + u[2] * t[0] - u[0] * t[2],
+        v[2] + q.w * t[2] + u[0] * t[1] - u[1] * t[0],
+    ]
+}
+
+// The transform that applies `b` first and then `a`
+pub(crate) fn compose(a: &Transform, b: &Transform) -> Transform {
+    let t = rotate(
+        &a.rotation,
+        [b.translation.x, b.translation.y, b.translation.z],
+    );
+    Transform {
+        translation: Vector3 {
+            x: a.translation.x + t[0],
+            y: a.translation.y + t[1],
+            z: a.translation.z + t[2],
+        },
+        rotation: multiply(&a.rotation, &b.rotation),
+    }
+}
+
+pub(crate) fn inverse(t: &Transform) -> Transform {
+    let rotation = conjugate(&t.rotation);
+    let v = rotate(
+        &rotation,
+        [t.translation.x, t.translation.y, t.translation.z],
+    );
+    Transform {
+        translation: Vector3 {
+            x: -v[0],
+            y: -v[1],
+            z: -v[2],
+        },
+        rotation,
+    }
+}
+
+pub(crate) fn transform_pose(t: &Transform, pose: &Pose) -> Pose {
+    let p = rotate(
+        &t.rotation,
+        [pose.position.x, pose.position.y, pose.position.z],
+    );
+    Pose {
+        position: Point {
+            x: t.translation.x + p[0],
+            y: t.translation.y + p[1],
+            z: t.translation.z + p[2],
+        },
+        orientation: multiply(&t.rotation, &pose.orientation),
+    }
+}
+
+// Constant velocity that moves `from` to `to` in `dt` seconds, expressed in the frame of `to`
+// as in nav_msgs/Odometry.
+pub(crate) fn body_twist(from: &Pose, to: &Pose, dt: f64) -> Twist {
+    let delta = [
+        to.position.x - from.position.x,
+        to.position.y - from.position.y,
+        to.position.z - from.position.z,
+    ];
+    let linear = rotate(&conjugate(&to.orientation), delta);
+
+    // Rotation from `from` to `to` in the body frame, as a rotation vector
+    let mut q = multiply(&conjugate(&from.orientation), &to.orientation);
+    if q.w < 0.0 {
+        q = Quaternion {
+            x: -q.x,
+            y: -q.y,
+            z: -q.z,
+            w: -q.w,
+        };
+    }
+    let sin_half = (q.x * q.x + q.y * q.y + q.z * q.z).sqrt();
+    let scale = if sin_half > 1e-9 {
+        2.0 * sin_half.atan2(q.w) / sin_half
+    } else {
+        2.0
+    };
+
+    Twist {
+        linear: Vector3 {
+            x: linear[0] / dt,
+            y: linear[1] / dt,
+            z: linear[2] / dt,
+        },
+        angular: Vector3 {
+            x: q.x * scale / dt,
+            y: q.y * scale / dt,
+            z: q.z * scale / dt,
+        },
+    }
+}
+
+pub(crate) fn normalize(q: &Quaternion) -> Quaternion {
+    let norm = (q.x * q.x + q.y * q.y + q.z * q.z + q.w * q.w).sqrt();
+    Quaternion {
+        x: q.x / norm,
+        y: q.y / norm,
+        z: q.z / norm,
+        w: q.w / norm,
+    }
+}
+
+// Roll, pitch and yaw of a rotation, applied in that order about the fixed x, y and z axes as in
+// tf2's getRPY
+pub(crate) fn to_rpy(q: &Quaternion) -> [f64; 3] {
+    let roll = (2.0 * (q.w * q.x + q.y * q.z)).atan2(1.0 - 2.0 * (q.x * q.x + q.y * q.y));
+    let pitch = (2.0 * (q.w * q.y - q.z * q.x)).clamp(-1.0, 1.0).asin();
+    let yaw = (2.0 * (q.w * q.z + q.x * q.y)).atan2(1.0 - 2.0 * (q.y * q.y + q.z * q.z));
+    [roll, pitch, yaw]
+}
+
+pub(crate) fn from_rpy([roll, pitch, yaw]: [f64; 3]) -> Quaternion {
+    let (sr, cr) = (roll / 2.0).sin_cos();
+    let (sp, cp) = (pitch / 2.0).sin_cos();
+    let (sy, cy) = (yaw / 2.0).sin_cos();
+    Quaternion {
+        x: sr * cp * cy - cr * sp * sy,
+        y: cr * sp * cy + sr * cp * sy,
+        z: cr * cp * sy - sr * sp * cy,
+        w: cr * cp * cy + sr * sp * sy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn assert_near(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    fn pose(x: f64, y: f64, yaw: f64) -> Pose {
+        Pose {
+            position: Point { x, y, z: 0.0 },
+            orientation: from_rpy([0.0, 0.0, yaw]),
+        }
+    }
+
+    #[test]
+    fn transform_pose_rotates_then_translates() {
+        let t = transform_of(&pose(1.0, 0.0, FRAC_PI_2));
+        let moved = transform_pose(&t, &pose(1.0, 0.0, 0.0));
+        assert_near(moved.position.x, 1.0);
+        assert_near(moved.position.y, 1.0);
+        assert_near(to_rpy(&moved.orientation)[2], FRAC_PI_2);
+    }
+
+    #[test]
+    fn inverse_undoes_a_transform() {
+        let t = transform_of(&pose(1.0, -2.0, 0.7));
+        let back = compose(&inverse(&t), &t);
+        let original = pose(0.3, 0.4, -1.2);
+        let round_trip = transform_pose(&back, &original);
+        assert_near(round_trip.position.x, original.position.x);
+        assert_near(round_trip.position.y, original.position.y);
+        assert_near(to_rpy(&round_trip.orientation)[2], -1.2);
+        assert_eq!(pose_of(&transform_of(&original)), original);
+    }
+
+    #[test]
+    fn rpy_round_trips() {
+        let rpy = [0.1, -0.2, 0.3];
+        for (a, b) in to_rpy(&from_rpy(rpy)).into_iter().zip(rpy) {
+            assert_near(a, b);
+        }
+    }
+
+    #[test]
+    fn body_twist_is_in_the_target_frame() {
+        let twist = body_twist(&pose(0.0, 0.0, FRAC_PI_2), &pose(0.0, 1.0, FRAC_PI_2), 0.5);
+        // Moving along world y while facing it is forward motion
+        assert_near(twist.linear.x, 2.0);
+        assert_near(twist.linear.y, 0.0);
+        assert_near(twist.angular.z, 0.0);
+
+        let turn = body_twist(&identity_pose(), &pose(0.0, 0.0, 0.5), 1.0);
+        assert_near(turn.angular.z, 0.5);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{sync, FeedbackCallbackBox, InteractiveMarkerServer};
+use r2r::visualization_msgs::msg::{InteractiveMarkerFeedback, MenuEntry};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// Handle of a menu entry, also used as the id of its `MenuEntry`.
+pub type EntryHandle = u32;
+
+/// Checkbox shown in front of a menu entry's title.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CheckState {
+    #[default]
+    NoCheckbox,
+    Checked,
+    Unchecked,
+}
+
+struct EntryContext {
+    title: String,
+    command: String,
+    command_type: u8,
+    sub_entries: Vec<EntryHandle>,
+    visible: bool,
+    check_state: CheckState,
+    feedback_cb: Option<FeedbackCallbackBox>,
+}
+
+#[derive(Default)]
+struct Menu {
+    entries: BTreeMap<EntryHandle, EntryContext>,
+    top_level: Vec<EntryHandle>,
+    // Markers the menu was applied to
+    managed_markers: BTreeSet<String>,
+}
+
+/// Builds hierarchical context menus with per-entry callbacks and check states, and applies
+/// them to markers, like `interactive_markers::MenuHandler` of the C++ library. Changes to
+/// entries show up on the markers after `reapply` and `apply_changes`.
+#[derive(Clone, Default)]
+pub struct MenuHandler {
+    menu: Arc<sync::Mutex<Menu>>,
+}
+
+impl MenuHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a top-level entry calling `feedback_cb` when selected.
+    pub fn insert(&self, title: &str, feedback_cb: Option<FeedbackCallbackBox>) -> EntryHandle {
+        let mut menu = self.menu.lock();
+        let handle = Self::add_entry(&mut menu, title, MenuEntry::FEEDBACK as u8, "", feedback_cb);
+        menu.top_level.push(handle);
+        handle
+    }
+
+    /// Adds an entry to the submenu of `parent`. Returns None if there is no such entry.
+    pub fn insert_sub_entry(
+        &self,
+        parent: EntryHandle,
+        title: &str,
+        feedback_cb: Option<FeedbackCallbackBox>,
+    ) -> Option<EntryHandle> {
+        let mut menu = self.menu.lock();
+        if !menu.entries.contains_key(&parent) {
+            return None;
+        }
+        let handle = Self::add_entry(&mut menu, title, MenuEntry::FEEDBACK as u8, "", feedback_cb);
+        menu.entries.get_mut(&parent)?.sub_entries.push(handle);
+        Some(handle)
+    }
+
+    /// Adds an entry that makes the client run `command` (`MenuEntry::ROSRUN` or
+    /// `MenuEntry::ROSLAUNCH`), at the top level or in the submenu of `parent`. Returns None if
+    /// `parent` does not exist.
+    pub fn insert_command(
+        &self,
+        parent: Option<EntryHandle>,
+        title: &str,
+        command_type: u8,
+        command: &str,
+    ) -> Option<EntryHandle> {
+        let mut menu = self.menu.lock();
+        if parent.is_some_and(|parent| !menu.entries.contains_key(&parent)) {
+            return None;
+        }
+        let handle = Self::add_entry(&mut menu, title, command_type, command, None);
+        match parent {
+            Some(parent) => menu.entries.get_mut(&parent)?.sub_entries.push(handle),
+            None => menu.top_level.push(handle),
+        }
+        Some(handle)
+    }
+
+    /// Shows or hides an entry and its submenu. Returns false if there is no such entry.
+    pub fn set_visible(&self, handle: EntryHandle, visible: bool) -> bool {
+        self.with_entry(handle, |entry| entry.visible = visible)
+    }
+
+    /// Returns false if there is no such entry.
+    pub fn set_check_state(&self, handle: EntryHandle, check_state: CheckState) -> bool {
+        self.with_entry(handle, |entry| entry.check_state = check_state)
+    }
+
+    pub fn check_state(&self, handle: EntryHandle) -> Option<CheckState> {
+        let menu = self.menu.lock();
+        menu.entries.get(&handle).map(|entry| entry.check_state)
+    }
+
+    pub fn title(&self, handle: EntryHandle) -> Option<String> {
+        let menu = self.menu.lock();
+        menu.entries.get(&handle).map(|entry| entry.title.clone())
+    }
+
+    /// Sets the menu of the marker `name` and dispatches its MENU_SELECT feedback to the entry
+    /// callbacks. Takes over the MENU_SELECT callback of the marker. Returns false if the marker
+    /// is unknown.
+    pub fn apply(&self, server: &InteractiveMarkerServer, name: &str) -> bool {
+        let Some(mut marker) = server.get(name) else {
+            self.menu.lock().managed_markers.remove(name);
+            return false;
+        };
+        marker.menu_entries = self.menu_entries();
+        server.insert(marker);
+
+        let handler = self.clone();
+        server.set_callback(
+            name,
+            Some(Arc::new(move |feedback: InteractiveMarkerFeedback| {
+                handler.process_feedback(feedback);
+            })),
+            InteractiveMarkerFeedback::MENU_SELECT as u8,
+        );
+        self.menu.lock().managed_markers.insert(name.to_string());
+        true
+    }
+
+    /// Applies the menu again to every marker it was applied to, e.g. after changing check
+    /// states. Returns false if any of the markers no longer exists.
+    pub fn reapply(&self, server: &InteractiveMarkerServer) -> bool {
+        let names: Vec<String> = self.menu.lock().managed_markers.iter().cloned().collect();
+        // Applies to all of them even if some fail
+        let failed = names
+            .iter()
+            .filter(|name| !self.apply(server, name))
+            .count();
+        failed == 0
+    }
+
+    fn add_entry(
+        menu: &mut Menu,
+        title: &str,
+        command_type: u8,
+        command: &str,
+        feedback_cb: Option<FeedbackCallbackBox>,
+    ) -> EntryHandle {
+        // Ids start at 1, a parent_id of 0 marks top-level entries
+        let handle = menu.entries.keys().next_back().map_or(1, |last| last + 1);
+        menu.entries.insert(
+            handle,
+            EntryContext {
+                title: title.to_string(),
+                command: command.to_string(),
+                command_type,
+                sub_entries: Vec::new(),
+                visible: true,
+                check_state: CheckState::NoCheckbox,
+                feedback_cb,
+            },
+        );
+        handle
+    }
+
+    fn with_entry(&self, handle: EntryHandle, f: impl FnOnce(&mut EntryContext)) -> bool {
+        match self.menu.lock().entries.get_mut(&handle) {
+            Some(entry) => {
+                f(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The visible entries, parents before their submenus
+    fn menu_entries(&self) -> Vec<MenuEntry> {
+        let menu = self.menu.lock();
+        let mut entries = Vec::with_capacity(menu.entries.len());
+        let mut stack: Vec<(EntryHandle, EntryHandle)> = menu
+            .top_level
+            .iter()
+            .rev()
+            .map(|&handle| (0, handle))
+            .collect();
+        while let Some((parent_id, handle)) = stack.pop() {
+            let Some(entry) = menu.entries.get(&handle) else {
+                continue;
+            };
+            if !entry.visible {
+                continue;
+            }
+            let title = match entry.check_state {
+                CheckState::NoCheckbox => entry.title.clone(),
+                CheckState::Checked => format!("[x] {}", entry.title),
+                CheckState::Unchecked => format!("[ ] {}", entry.title),
+            };
+            entries.push(MenuEntry {
+                id: handle,
+                parent_id,
+                title,
+                command: entry.command.clone(),
+                command_type: entry.command_type,
+            });
+            stack.extend(entry.sub_entries.iter().rev().map(|&sub| (handle, sub)));
+        }
+        entries
+    }
+
+    fn process_feedback(&self, feedback: InteractiveMarkerFeedback) {
+        let callback = self
+            .menu
+            .lock()
+            .entries
+            .get(&feedback.menu_entry_id)
+            .and_then(|entry| entry.feedback_cb.clone());
+        // Called without the menu locked, so callbacks can change the menu
+        if let Some(callback) = callback {
+            callback(feedback);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{feedback, marker, server};
+    use crate::ServerConfig;
+    use r2r::geometry_msgs::msg::Pose;
+
+    fn ids(entries: &[MenuEntry]) -> Vec<(u32, u32)> {
+        entries
+            .iter()
+            .map(|entry| (entry.id, entry.parent_id))
+            .collect()
+    }
+
+    #[test]
+    fn parents_come_before_their_submenus() {
+        let menu = MenuHandler::new();
+        let file = menu.insert("File", None);
+        let edit = menu.insert("Edit", None);
+        let open = menu.insert_sub_entry(file, "Open", None).unwrap();
+        menu.insert_sub_entry(open, "Recent", None).unwrap();
+        menu.insert_sub_entry(edit, "Undo", None).unwrap();
+        assert_eq!(menu.insert_sub_entry(99, "Orphan", None), None);
+
+        // (id, parent_id) depth first, in insertion order
+        assert_eq!(
+            ids(&menu.menu_entries()),
+            [(1, 0), (3, 1), (4, 3), (2, 0), (5, 2)]
+        );
+    }
+
+    #[test]
+    fn hidden_entries_hide_their_submenu() {
+        let menu = MenuHandler::new();
+        let file = menu.insert("File", None);
+        menu.insert_sub_entry(file, "Open", None).unwrap();
+        let edit = menu.insert("Edit", None);
+        assert!(menu.set_visible(file, false));
+        assert!(!menu.set_visible(99, false));
+        assert_eq!(ids(&menu.menu_entries()), [(edit, 0)]);
+    }
+
+    #[test]
+    fn check_states_are_shown_in_titles() {
+        let menu = MenuHandler::new();
+        let grid = menu.insert("Grid", None);
+        menu.set_check_state(grid, CheckState::Checked);
+        assert_eq!(menu.menu_entries()[0].title, "[x] Grid");
+        menu.set_check_state(grid, CheckState::Unchecked);
+        assert_eq!(menu.menu_entries()[0].title, "[ ] Grid");
+        assert_eq!(menu.check_state(grid), Some(CheckState::Unchecked));
+        assert_eq!(menu.title(grid).as_deref(), Some("Grid"));
+    }
+
+    #[tokio::test]
+    async fn selections_reach_entry_callbacks() {
+        let server = server(ServerConfig::default());
+        server.insert(marker("m"));
+        let menu = MenuHandler::new();
+        // The callback changes the menu, which must not deadlock the dispatch
+        let handler = menu.clone();
+        let toggle = menu.insert(
+            "Toggle",
+            Some(Arc::new(move |feedback: InteractiveMarkerFeedback| {
+                handler.set_check_state(feedback.menu_entry_id, CheckState::Checked);
+            })),
+        );
+        assert!(menu.apply(&server, "m"));
+        server.apply_changes().unwrap();
+        assert_eq!(server.get("m").unwrap().menu_entries.len(), 1);
+
+        let mut select = feedback(
+            "m",
+            InteractiveMarkerFeedback::MENU_SELECT as u8,
+            Pose::default(),
+        );
+        select.menu_entry_id = toggle;
+        server.handle_feedback(select);
+        assert_eq!(menu.check_state(toggle), Some(CheckState::Checked));
+
+        assert!(menu.reapply(&server));
+        server.erase("m");
+        server.apply_changes().unwrap();
+        assert!(!menu.reapply(&server));
+    }
+}
@@ -0,0 +1,48 @@
+use r2r::geometry_msgs::msg::PoseStamped;
+use r2r::visualization_msgs::msg::InteractiveMarker;
+
+/// A change queued for the next `apply_changes`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PendingChange {
+    /// The marker is inserted or replaced.
+    Insert {
+        name: String,
+        marker: InteractiveMarker,
+    },
+    /// The marker is moved, by `set_pose` or a client dragging it.
+    Pose { name: String, pose: PoseStamped },
+    /// The marker is erased.
+    Erase { name: String },
+}
+
+impl PendingChange {
+    pub fn name(&self) -> &str {
+        match self {
+            PendingChange::Insert { name, .. }
+            | PendingChange::Pose { name, .. }
+            | PendingChange::Erase { name } => name,
+        }
+    }
+}
+
+/// What an `apply_changes` published. Markers inserted again with an unchanged appearance are
+/// sent as pose updates when `ServerConfig::delta_updates` is set, and are counted as such.
+/// Pose updates to the pose a marker already has are not published or counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AppliedChanges {
+    /// Sequence number of the published update, or the current one if nothing was pending.
+    pub seq_num: u64,
+    pub full_updates: usize,
+    pub pose_updates: usize,
+    pub erases: usize,
+    /// Pose updates held back by `ServerConfig::max_pose_rate`. They stay pending and are
+    /// published once the window has passed.
+    pub deferred: usize,
+}
+
+impl AppliedChanges {
+    /// True if nothing was published, also when changes were deferred.
+    pub fn is_empty(&self) -> bool {
+        self.full_updates == 0 && self.pose_updates == 0 && self.erases == 0
+    }
+}
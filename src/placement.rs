@@ -0,0 +1,346 @@
+// Adjustments and checks of dragged marker poses before they are committed.
+
+use crate::{math, sync, InteractiveMarkerServer};
+use futures::StreamExt;
+use r2r::geometry_msgs::msg::{Point, Pose};
+use r2r::nav_msgs::msg::OccupancyGrid;
+use r2r::QosProfile;
+use std::sync::Arc;
+
+/// A surface dragged markers are snapped onto, such as terrain or a work surface. The height is
+/// looked up in the marker's frame.
+pub trait SurfaceSnap: Send + Sync {
+    /// Height of the surface at `(x, y)`, or None where the surface is not defined.
+    fn height_at(&self, x: f64, y: f64) -> Option<f64>;
+}
+
+/// A surface given as heights on a regular grid, interpolated bilinearly between cells.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightMap {
+    /// Position of the center of the first cell.
+    pub origin: (f64, f64),
+    /// Size of a cell along both axes.
+    pub resolution: f64,
+    /// Number of cells along x.
+    pub width: usize,
+    /// Heights in row-major order, rows along x.
+    pub heights: Vec<f64>,
+}
+
+impl HeightMap {
+    pub fn new(origin: (f64, f64), resolution: f64, width: usize, heights: Vec<f64>) -> Self {
+        Self {
+            origin,
+            resolution,
+            width,
+            heights,
+        }
+    }
+
+    fn rows(&self) -> usize {
+        self.heights.len().checked_div(self.width).unwrap_or(0)
+    }
+
+    fn cell(&self, column: usize, row: usize) -> f64 {
+        self.heights[row * self.width + column]
+    }
+}
+
+impl SurfaceSnap for HeightMap {
+    fn height_at(&self, x: f64, y: f64) -> Option<f64> {
+        let (columns, rows) = (self.width, self.rows());
+        if columns == 0 || rows == 0 || self.resolution <= 0.0 {
+            return None;
+        }
+        let u = (x - self.origin.0) / self.resolution;
+        let v = (y - self.origin.1) / self.resolution;
+        let (max_u, max_v) = ((columns - 1) as f64, (rows - 1) as f64);
+        // Points up to half a cell outside the centers still belong to the edge cells
+        if u < -0.5 || v < -0.5 || u > max_u + 0.5 || v > max_v + 0.5 {
+            return None;
+        }
+        let (u, v) = (u.clamp(0.0, max_u), v.clamp(0.0, max_v));
+
+        let (c0, r0) = (u.floor() as usize, v.floor() as usize);
+        let (c1, r1) = ((c0 + 1).min(columns - 1), (r0 + 1).min(rows - 1));
+        let (fu, fv) = (u - c0 as f64, v - r0 as f64);
+        let bottom = self.cell(c0, r0) * (1.0 - fu) + self.cell(c1, r0) * fu;
+        let top = self.cell(c0, r1) * (1.0 - fu) + self.cell(c1, r1) * fu;
+        Some(bottom * (1.0 - fv) + top * fv)
+    }
+}
+
+/// Decides whether a marker may be placed at a pose. Dragged poses that are not allowed are
+/// rejected, and the client is moved back to the last allowed pose.
+pub trait PlacementConstraint: Send + Sync {
+    /// Whether the pose, in the marker's frame, is allowed.
+    fn allows(&self, pose: &Pose) -> bool;
+}
+
+/// Rounds dragged poses to clean values. Positions snap to multiples of `grid` along each axis
+/// and roll, pitch and yaw to multiples of `angle` radians, both in the marker's frame. Zero
+/// disables either.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Snapping {
+    pub grid: f64,
+    pub angle: f64,
+}
+
+fn round_to(value: f64, step: f64) -> f64 {
+    (value / step).round() * step
+}
+
+impl Snapping {
+    pub fn new(grid: f64, angle: f64) -> Self {
+        Self { grid, angle }
+    }
+
+    pub(crate) fn apply(&self, pose: &mut Pose) {
+        if self.grid > 0.0 {
+            pose.position.x = round_to(pose.position.x, self.grid);
+            pose.position.y = round_to(pose.position.y, self.grid);
+            pose.position.z = round_to(pose.position.z, self.grid);
+        }
+        if self.angle > 0.0 {
+            let rpy = math::to_rpy(&pose.orientation);
+            pose.orientation = math::from_rpy(rpy.map(|angle| round_to(angle, self.angle)));
+        }
+    }
+}
+
+/// A limit on where a dragged marker can go. Dragged poses are adjusted to satisfy it, and
+/// clients are corrected with the next apply. Positions and angles are in the marker's frame.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PoseConstraint {
+    /// Keeps the position along the axes set to true where it is.
+    LockPosition { x: bool, y: bool, z: bool },
+    /// Keeps the orientation where it is.
+    LockOrientation,
+    /// Clamps the position into an axis-aligned box.
+    Bounds { min: Point, max: Point },
+    /// Clamps roll, pitch and yaw, in radians, into `(min, max)` ranges.
+    RotationLimits {
+        roll: (f64, f64),
+        pitch: (f64, f64),
+        yaw: (f64, f64),
+    },
+}
+
+impl PoseConstraint {
+    // Adjusts the dragged `pose` of a marker currently at `current`
+    pub(crate) fn apply(&self, pose: &mut Pose, current: &Pose) {
+        match self {
+            PoseConstraint::LockPosition { x, y, z } => {
+                if *x {
+                    pose.position.x = current.position.x;
+                }
+                if *y {
+                    pose.position.y = current.position.y;
+                }
+                if *z {
+                    pose.position.z = current.position.z;
+                }
+            }
+            PoseConstraint::LockOrientation => pose.orientation = current.orientation.clone(),
+            PoseConstraint::Bounds { min, max } => {
+                pose.position.x = pose.position.x.clamp(min.x, max.x);
+                pose.position.y = pose.position.y.clamp(min.y, max.y);
+                pose.position.z = pose.position.z.clamp(min.z, max.z);
+            }
+            PoseConstraint::RotationLimits { roll, pitch, yaw } => {
+                let [r, p, y] = math::to_rpy(&pose.orientation);
+                pose.orientation = math::from_rpy([
+                    r.clamp(roll.0, roll.1),
+                    p.clamp(pitch.0, pitch.1),
+                    y.clamp(yaw.0, yaw.1),
+                ]);
+            }
+        }
+    }
+}
+
+/// Keeps markers out of occupied and unknown cells of an occupancy grid, e.g. so navigation
+/// goals cannot be dropped inside walls. The grid is assumed to be in the marker's frame.
+#[derive(Clone)]
+pub struct OccupancyConstraint {
+    grid: Arc<sync::Mutex<Option<OccupancyGrid>>>,
+    /// Cells with an occupancy probability (0-100) at or above this are occupied.
+    pub occupied_threshold: i8,
+    /// Allow placements in unknown cells and outside the grid.
+    pub allow_unknown: bool,
+}
+
+impl OccupancyConstraint {
+    pub fn new(grid: OccupancyGrid) -> Self {
+        Self {
+            grid: Arc::new(sync::Mutex::new(Some(grid))),
+            occupied_threshold: 50,
+            allow_unknown: false,
+        }
+    }
+
+    /// A constraint following the grids published on `topic`, e.g. `/map`. Everything is
+    /// rejected until the first grid arrives, unless unknown cells are allowed. The subscription
+    /// runs among the background tasks of `server` and stops with it.
+    pub fn subscribe(server: &InteractiveMarkerServer, topic: &str) -> crate::Result<Self> {
+        // Maps are usually latched
+        let mut subscriber = server
+            .node
+            .lock()
+            .unwrap()
+            .subscribe::<OccupancyGrid>(topic, QosProfile::default().transient_local())?;
+        let constraint = Self {
+            grid: Arc::new(sync::Mutex::new(None)),
+            occupied_threshold: 50,
+            allow_unknown: false,
+        };
+        let grid = Arc::clone(&constraint.grid);
+        server.spawn_task("occupancy_subscriber", async move {
+            while let Some(message) = subscriber.next().await {
+                *grid.lock() = Some(message);
+            }
+        });
+        Ok(constraint)
+    }
+
+    // Occupancy of the cell containing the position, None if it is unknown or off the grid
+    fn occupancy(&self, x: f64, y: f64) -> Option<i8> {
+        let grid = self.grid.lock();
+        let grid = grid.as_ref()?;
+        let info = &grid.info;
+        let in_grid = math::transform_pose(
+            &math::inverse(&math::transform_of(&info.origin)),
+            &Pose {
+                position: Point { x, y, z: 0.0 },
+                ..math::identity_pose()
+            },
+        );
+        let resolution = info.resolution as f64;
+        if resolution <= 0.0 {
+            return None;
+        }
+        let column = (in_grid.position.x / resolution).floor();
+        let row = (in_grid.position.y / resolution).floor();
+        if column < 0.0 || row < 0.0 || column >= info.width as f64 || row >= info.height as f64 {
+            return None;
+        }
+        let cell = *grid
+            .data
+            .get(row as usize * info.width as usize + column as usize)?;
+        (cell >= 0).then_some(cell)
+    }
+}
+
+impl PlacementConstraint for OccupancyConstraint {
+    fn allows(&self, pose: &Pose) -> bool {
+        match self.occupancy(pose.position.x, pose.position.y) {
+            Some(occupancy) => occupancy < self.occupied_threshold,
+            None => self.allow_unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r2r::nav_msgs::msg::MapMetaData;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn at(x: f64, y: f64) -> Pose {
+        Pose {
+            position: Point { x, y, z: 0.0 },
+            ..math::identity_pose()
+        }
+    }
+
+    #[test]
+    fn height_map_interpolates_between_cells() {
+        // 2 by 2 cells one meter apart, rising along x
+        let map = HeightMap::new((0.0, 0.0), 1.0, 2, vec![0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(map.height_at(0.5, 0.5), Some(0.5));
+        assert_eq!(map.height_at(1.4, 0.0), Some(1.0));
+        assert_eq!(map.height_at(1.6, 0.0), None);
+        assert_eq!(
+            HeightMap::new((0.0, 0.0), 1.0, 0, vec![]).height_at(0.0, 0.0),
+            None
+        );
+    }
+
+    #[test]
+    fn snapping_rounds_positions_and_angles() {
+        let mut pose = Pose {
+            orientation: math::from_rpy([0.0, 0.0, 1.4]),
+            ..at(0.26, -0.74)
+        };
+        Snapping::new(0.5, FRAC_PI_2).apply(&mut pose);
+        assert_eq!((pose.position.x, pose.position.y), (0.5, -0.5));
+        assert!((math::to_rpy(&pose.orientation)[2] - FRAC_PI_2).abs() < 1e-9);
+
+        // Zero steps leave the pose alone
+        let mut unsnapped = at(0.26, 0.0);
+        Snapping::default().apply(&mut unsnapped);
+        assert_eq!(unsnapped, at(0.26, 0.0));
+    }
+
+    #[test]
+    fn pose_constraints_adjust_dragged_poses() {
+        let current = at(1.0, 1.0);
+        let mut pose = at(3.0, -3.0);
+        let lock_x = PoseConstraint::LockPosition {
+            x: true,
+            y: false,
+            z: false,
+        };
+        lock_x.apply(&mut pose, &current);
+        assert_eq!((pose.position.x, pose.position.y), (1.0, -3.0));
+
+        let bounds = PoseConstraint::Bounds {
+            min: Point {
+                x: -2.0,
+                y: -2.0,
+                z: 0.0,
+            },
+            max: Point {
+                x: 2.0,
+                y: 2.0,
+                z: 0.0,
+            },
+        };
+        bounds.apply(&mut pose, &current);
+        assert_eq!(pose.position.y, -2.0);
+
+        pose.orientation = math::from_rpy([0.0, 0.0, 1.0]);
+        let limits = PoseConstraint::RotationLimits {
+            roll: (0.0, 0.0),
+            pitch: (0.0, 0.0),
+            yaw: (-0.5, 0.5),
+        };
+        limits.apply(&mut pose, &current);
+        assert!((math::to_rpy(&pose.orientation)[2] - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn occupancy_rejects_occupied_and_unknown_cells() {
+        // Cells from (-1, -1): free, occupied, unknown, free
+        let grid = OccupancyGrid {
+            info: MapMetaData {
+                resolution: 1.0,
+                width: 2,
+                height: 2,
+                origin: at(-1.0, -1.0),
+                ..Default::default()
+            },
+            data: vec![0, 100, -1, 0],
+            ..Default::default()
+        };
+        let mut constraint = OccupancyConstraint::new(grid);
+        assert!(constraint.allows(&at(-0.5, -0.5)));
+        assert!(!constraint.allows(&at(0.5, -0.5)));
+        assert!(!constraint.allows(&at(-0.5, 0.5)));
+        assert!(!constraint.allows(&at(5.0, 5.0)));
+
+        constraint.allow_unknown = true;
+        assert!(constraint.allows(&at(-0.5, 0.5)));
+        assert!(constraint.allows(&at(5.0, 5.0)));
+    }
+}
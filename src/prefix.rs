@@ -0,0 +1,107 @@
+// Robot prefixes. With `ServerConfig::robot_prefix` set, frame ids and optionally marker names
+// are prefixed as they enter the server, so the same application code can serve markers for
+// several robots. Names and frames that already carry the prefix are left alone, which makes
+// applying it idempotent.
+
+use std::borrow::Cow;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RobotPrefix {
+    // The prefix including its trailing '/', empty when no prefix is configured
+    prefix: String,
+    names: bool,
+}
+
+impl RobotPrefix {
+    pub(crate) fn new(prefix: &str, names: bool) -> Self {
+        let prefix = prefix.trim_end_matches('/');
+        Self {
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("{}/", prefix)
+            },
+            names,
+        }
+    }
+
+    fn apply<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        if self.prefix.is_empty() || value.is_empty() || value.starts_with(&self.prefix) {
+            Cow::Borrowed(value)
+        } else {
+            Cow::Owned(format!("{}{}", self.prefix, value))
+        }
+    }
+
+    // Name of the marker as published, for a name used in the server API
+    pub(crate) fn name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.names {
+            self.apply(name)
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+
+    pub(crate) fn frame(&self, frame_id: &mut String) {
+        if let Cow::Owned(prefixed) = self.apply(frame_id) {
+            *frame_id = prefixed;
+        }
+    }
+
+    // The name the application used for a published name, without allocating
+    pub(crate) fn unprefixed_name<'a>(&self, name: &'a str) -> &'a str {
+        if self.names && !self.prefix.is_empty() {
+            name.strip_prefix(self.prefix.as_str()).unwrap_or(name)
+        } else {
+            name
+        }
+    }
+
+    // Removes the name prefix again, so callbacks and getters see the names the application used
+    pub(crate) fn strip_name(&self, name: &mut String) {
+        if self.names && !self.prefix.is_empty() && name.starts_with(&self.prefix) {
+            name.drain(..self.prefix.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixing_is_idempotent() {
+        let prefix = RobotPrefix::new("robot1/", true);
+        let mut frame = "base_link".to_string();
+        prefix.frame(&mut frame);
+        assert_eq!(frame, "robot1/base_link");
+        prefix.frame(&mut frame);
+        assert_eq!(frame, "robot1/base_link");
+        assert_eq!(prefix.name("robot1/goal"), "robot1/goal");
+    }
+
+    #[test]
+    fn empty_values_and_prefixes_are_left_alone() {
+        let mut frame = String::new();
+        RobotPrefix::new("robot1", false).frame(&mut frame);
+        assert!(frame.is_empty());
+
+        let none = RobotPrefix::new("/", true);
+        assert!(matches!(none.name("goal"), Cow::Borrowed("goal")));
+    }
+
+    #[test]
+    fn names_round_trip() {
+        let prefix = RobotPrefix::new("robot1", true);
+        let mut name = prefix.name("goal").into_owned();
+        assert_eq!(name, "robot1/goal");
+        assert_eq!(prefix.unprefixed_name(&name), "goal");
+        prefix.strip_name(&mut name);
+        assert_eq!(name, "goal");
+
+        // Without name prefixing only frames change
+        let frames_only = RobotPrefix::new("robot1", false);
+        assert_eq!(frames_only.name("goal"), "goal");
+        assert_eq!(frames_only.unprefixed_name("robot1/goal"), "robot1/goal");
+    }
+}
@@ -0,0 +1,88 @@
+use crate::events::ServerEvent;
+use crate::log::Logger;
+use r2r::geometry_msgs::msg::PoseArray;
+use r2r::visualization_msgs::msg::{InteractiveMarkerInit, InteractiveMarkerUpdate};
+use r2r::{Publisher, WrappedTypesupport};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+// Failed publishes are retried with exponential backoff starting at PUBLISH_RETRY_DELAY
+const MAX_PUBLISH_ATTEMPTS: u32 = 5;
+const PUBLISH_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+// Messages handed from the server to the publisher task
+pub(crate) enum Outgoing {
+    Update {
+        shard: usize,
+        update: InteractiveMarkerUpdate,
+    },
+    Init(InteractiveMarkerInit),
+    Poses(PoseArray),
+}
+
+pub(crate) struct Publishers {
+    pub update_pubs: Vec<Publisher<InteractiveMarkerUpdate>>,
+    // Transient local topics that receive the full marker set after every apply
+    pub init_pubs: Vec<Publisher<InteractiveMarkerInit>>,
+    pub pose_array_pub: Option<Publisher<PoseArray>>,
+}
+
+// Publishes queued messages in order, retrying failed publishes so a transient middleware
+// error does not lose an update.
+pub(crate) async fn run(
+    publishers: Publishers,
+    mut outgoing_rx: mpsc::UnboundedReceiver<Outgoing>,
+    events: broadcast::Sender<ServerEvent>,
+    failures: Arc<AtomicU64>,
+    logger: Arc<Logger>,
+) {
+    while let Some(outgoing) = outgoing_rx.recv().await {
+        match outgoing {
+            Outgoing::Update { shard, update } => {
+                let publisher = &publishers.update_pubs[shard];
+                publish_with_retry(publisher, &update, update.seq_num, &events, &failures).await
+            }
+            Outgoing::Init(init) => {
+                for init_pub in &publishers.init_pubs {
+                    publish_with_retry(init_pub, &init, init.seq_num, &events, &failures).await
+                }
+            }
+            Outgoing::Poses(poses) => {
+                if let Some(pose_array_pub) = &publishers.pose_array_pub {
+                    if let Err(e) = pose_array_pub.publish(&poses) {
+                        logger.warn(format_args!("Failed to publish the marker poses: {}", e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn publish_with_retry<T: WrappedTypesupport>(
+    publisher: &Publisher<T>,
+    msg: &T,
+    seq_num: u64,
+    events: &broadcast::Sender<ServerEvent>,
+    failures: &AtomicU64,
+) {
+    let mut attempt = 1;
+    while let Err(e) = publisher.publish(msg) {
+        if attempt == MAX_PUBLISH_ATTEMPTS {
+            failures.fetch_add(1, Ordering::Relaxed);
+            let _ = events.send(ServerEvent::PublishFailed {
+                seq_num,
+                error: e.to_string(),
+            });
+            return;
+        }
+        let _ = events.send(ServerEvent::PublishRetry {
+            seq_num,
+            attempt,
+            error: e.to_string(),
+        });
+        tokio::time::sleep(PUBLISH_RETRY_DELAY * 2u32.pow(attempt - 1)).await;
+        attempt += 1;
+    }
+}
@@ -0,0 +1,167 @@
+// Recording of feedback to JSON lines files and replaying it into a server.
+
+use crate::InteractiveMarkerServer;
+use futures::StreamExt;
+use r2r::visualization_msgs::msg::InteractiveMarkerFeedback;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// A feedback message with the time it arrived, in seconds since the recording started.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFeedback {
+    pub time: f64,
+    pub feedback: InteractiveMarkerFeedback,
+}
+
+/// Writes every feedback message a server receives to a file, one JSON object per line, so an
+/// interaction session can be replayed with `FeedbackPlayer`. Recording stops when the recorder
+/// is stopped or dropped.
+pub struct FeedbackRecorder {
+    task: JoinHandle<()>,
+}
+
+impl FeedbackRecorder {
+    pub fn start(
+        server: &InteractiveMarkerServer,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(std::fs::File::create(path)?);
+        let mut feedback = server.all_feedback();
+        let logger = Arc::clone(&server.logger);
+        let runtime = server.spawner.runtime().map_err(std::io::Error::other)?;
+        let task = runtime.spawn(async move {
+            let start = Instant::now();
+            while let Some(feedback) = feedback.next().await {
+                let recorded = RecordedFeedback {
+                    time: start.elapsed().as_secs_f64(),
+                    feedback,
+                };
+                // Flushed per message, so a recording cut short by a crash is still readable
+                let written = serde_json::to_writer(&mut file, &recorded)
+                    .map_err(std::io::Error::from)
+                    .and_then(|()| writeln!(file))
+                    .and_then(|()| file.flush());
+                if let Err(e) = written {
+                    logger.warn(format_args!("Failed to record feedback: {}", e));
+                    return;
+                }
+            }
+        });
+        Ok(Self { task })
+    }
+
+    /// Stops recording, like dropping the recorder.
+    pub fn stop(self) {}
+}
+
+impl Drop for FeedbackRecorder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Replays feedback recorded by `FeedbackRecorder` into a server, through the same processing
+/// as feedback from clients, e.g. for regression tests and demos.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FeedbackPlayer {
+    pub recording: Vec<RecordedFeedback>,
+}
+
+impl FeedbackPlayer {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut recording = Vec::new();
+        for line in BufReader::new(std::fs::File::open(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                recording.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(Self { recording })
+    }
+
+    /// Injects the recorded feedback into `server` with the recorded timing divided by `speed`,
+    /// so 2.0 plays twice as fast. Abort the returned task to stop playing. Fails with
+    /// `Error::InvalidRate` unless `speed` is positive and finite, and with `Error::NoRuntime`
+    /// for a server of `new_with_runner` outside a tokio runtime.
+    pub fn play(
+        &self,
+        server: &InteractiveMarkerServer,
+        speed: f64,
+    ) -> crate::Result<JoinHandle<()>> {
+        if !(speed > 0.0 && speed.is_finite()) {
+            return Err(crate::Error::InvalidRate(speed));
+        }
+        let recording = self.recording.clone();
+        let runtime = server.spawner.runtime()?;
+        let server = server.clone();
+        Ok(runtime.spawn(async move {
+            let start = tokio::time::Instant::now();
+            for recorded in recording {
+                let at = Duration::from_secs_f64(recorded.time.max(0.0) / speed);
+                tokio::time::sleep_until(start + at).await;
+                let mut feedback = recorded.feedback;
+                // Recorded names are the unprefixed ones callbacks see
+                if let Cow::Owned(name) = server.prefix.name(&feedback.marker_name) {
+                    feedback.marker_name = name;
+                }
+                server.handle_feedback(feedback);
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drag, marker, pose_at, server};
+    use crate::{Error, ServerConfig};
+
+    #[tokio::test]
+    async fn recordings_replay_into_another_server() {
+        let recorded = server(ServerConfig::default());
+        recorded.insert(marker("m"));
+        recorded.apply_changes().unwrap();
+        let path = std::env::temp_dir().join(format!("feedback_{}.jsonl", std::process::id()));
+        let recorder = FeedbackRecorder::start(&recorded, &path).unwrap();
+        // Let the recorder subscribe before the feedback arrives
+        tokio::task::yield_now().await;
+
+        recorded.handle_feedback(drag("m", pose_at(1.0)));
+        let mut player = FeedbackPlayer::default();
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            player = FeedbackPlayer::load(&path).unwrap();
+            if !player.recording.is_empty() {
+                break;
+            }
+        }
+        recorder.stop();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(player.recording.len(), 1);
+        assert_eq!(player.recording[0].feedback.marker_name, "m");
+
+        let replayed = server(ServerConfig::default());
+        replayed.insert(marker("m"));
+        replayed.apply_changes().unwrap();
+        player.play(&replayed, 100.0).unwrap().await.unwrap();
+        replayed.apply_changes().unwrap();
+        assert_eq!(replayed.get("m").unwrap().pose.position.x, 1.0);
+    }
+
+    #[tokio::test]
+    async fn invalid_speeds_are_rejected() {
+        let server = server(ServerConfig::default());
+        let player = FeedbackPlayer::default();
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                player.play(&server, speed),
+                Err(Error::InvalidRate(_))
+            ));
+        }
+    }
+}
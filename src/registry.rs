@@ -0,0 +1,120 @@
+use crate::{sync, FeedbackDrops, InteractiveMarkerServer, Result, ServerConfig};
+use r2r::visualization_msgs::msg::InteractiveMarker;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Counts summed over all servers of a registry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegistryStats {
+    pub servers: usize,
+    /// Published markers, not counting pending inserts.
+    pub markers: usize,
+    pub feedback_drops: FeedbackDrops,
+}
+
+/// Several servers on different topic namespaces sharing one node, for applications that expose
+/// several marker panels. Servers are kept by namespace.
+#[derive(Clone)]
+pub struct ServerRegistry {
+    node: Arc<Mutex<r2r::Node>>,
+    servers: Arc<sync::Mutex<BTreeMap<String, InteractiveMarkerServer>>>,
+}
+
+impl ServerRegistry {
+    pub fn new(node: Arc<Mutex<r2r::Node>>) -> Self {
+        Self {
+            node,
+            servers: Arc::new(sync::Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// The server on `topic_namespace`, created with the default configuration if there is none.
+    pub fn add(&self, topic_namespace: &str) -> Result<InteractiveMarkerServer> {
+        self.add_with_config(topic_namespace, ServerConfig::default())
+    }
+
+    /// The server on `topic_namespace`, created with `config` if there is none. An existing
+    /// server keeps its configuration.
+    pub fn add_with_config(
+        &self,
+        topic_namespace: &str,
+        config: ServerConfig,
+    ) -> Result<InteractiveMarkerServer> {
+        let mut servers = self.servers.lock();
+        if let Some(server) = servers.get(topic_namespace) {
+            return Ok(server.clone());
+        }
+        let server = InteractiveMarkerServer::new_with_config(
+            topic_namespace,
+            Arc::clone(&self.node),
+            config,
+        )?;
+        servers.insert(topic_namespace.to_string(), server.clone());
+        Ok(server)
+    }
+
+    pub fn get(&self, topic_namespace: &str) -> Option<InteractiveMarkerServer> {
+        self.servers.lock().get(topic_namespace).cloned()
+    }
+
+    /// Removes the server on `topic_namespace` from the registry and returns it.
+    pub fn remove(&self, topic_namespace: &str) -> Option<InteractiveMarkerServer> {
+        self.servers.lock().remove(topic_namespace)
+    }
+
+    /// Namespaces of all servers, in order.
+    pub fn namespaces(&self) -> Vec<String> {
+        self.servers.lock().keys().cloned().collect()
+    }
+
+    /// Every server having a marker called `name`, with its namespace and the marker.
+    pub fn find_marker(&self, name: &str) -> Vec<(String, InteractiveMarker)> {
+        self.snapshot()
+            .into_iter()
+            .filter_map(|(namespace, server)| server.get(name).map(|marker| (namespace, marker)))
+            .collect()
+    }
+
+    /// Applies the pending changes of every server. All servers are applied even if one fails,
+    /// and the first error is returned.
+    pub fn apply_changes_all(&self) -> Result<()> {
+        let mut result = Ok(());
+        for (_, server) in self.snapshot() {
+            if let Err(e) = server.apply_changes() {
+                server
+                    .logger
+                    .error(format_args!("Applying changes failed with: {}.", e));
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn stats(&self) -> RegistryStats {
+        let servers = self.snapshot();
+        let mut stats = RegistryStats {
+            servers: servers.len(),
+            ..Default::default()
+        };
+        for (_, server) in servers {
+            stats.markers += server.size();
+            let drops = server.feedback_drops();
+            stats.feedback_drops.unknown_marker += drops.unknown_marker;
+            stats.feedback_drops.pending_overflow += drops.pending_overflow;
+            stats.feedback_drops.suspected_lost += drops.suspected_lost;
+            stats.feedback_drops.client_not_allowed += drops.client_not_allowed;
+        }
+        stats
+    }
+
+    // Cloned out so no server is called while the registry is locked
+    fn snapshot(&self) -> Vec<(String, InteractiveMarkerServer)> {
+        self.servers
+            .lock()
+            .iter()
+            .map(|(namespace, server)| (namespace.clone(), server.clone()))
+            .collect()
+    }
+}
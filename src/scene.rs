@@ -0,0 +1,93 @@
+use crate::{InteractiveMarkerServer, MarkerName};
+use r2r::visualization_msgs::msg::InteractiveMarker;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The markers of a server, with their poses, controls and menus, for saving and restoring it.
+/// Callbacks are code and are not part of a scene. This is a snapshot of the marker messages;
+/// scenes written by hand use the declarative `SceneDescription` format instead.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub markers: Vec<InteractiveMarker>,
+}
+
+impl Scene {
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(self)
+    }
+
+    /// Parses YAML written by `to_yaml`. Hand-written YAML is read by
+    /// `SceneDescription::from_yaml`.
+    pub fn from_yaml(yaml: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+// Files ending in .json are JSON, everything else is YAML
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+impl InteractiveMarkerServer {
+    /// The published markers, sorted by name.
+    pub fn to_scene(&self) -> Scene {
+        // Names first, so get is not called while a shard of the marker map is locked
+        let names: Vec<MarkerName> = self
+            .marker_contexts
+            .iter()
+            .map(|marker_context| marker_context.key().clone())
+            .collect();
+        let mut markers: Vec<InteractiveMarker> =
+            names.iter().filter_map(|name| self.get(name)).collect();
+        markers.sort_by(|a, b| a.name.cmp(&b.name));
+        Scene { markers }
+    }
+
+    /// Inserts the markers of a scene, replacing markers with the same names, and publishes
+    /// them. Callbacks of replaced markers are kept.
+    pub fn load_scene(&self, scene: Scene) -> crate::Result<()> {
+        for marker in scene.markers {
+            self.insert(marker);
+        }
+        self.apply_changes()?;
+        Ok(())
+    }
+
+    /// Writes the published markers to a YAML file, or a JSON file if `path` ends in `.json`.
+    pub fn save_scene(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let scene = self.to_scene();
+        let text = if is_json(path) {
+            scene.to_json()?
+        } else {
+            scene.to_yaml()?
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Loads a file written by `save_scene` with `load_scene`.
+    pub fn load_scene_file(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let scene = if is_json(path) {
+            Scene::from_json(&text)?
+        } else {
+            Scene::from_yaml(&text)?
+        };
+        self.load_scene(scene)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+// Where the background tasks of a server run. By default they are spawned on the ambient tokio
+// runtime, or on the runtime given to `new_with_handle`. A server made with `new_with_runner`
+// instead hands them to the future it returned, so the application decides where and when they
+// are polled.
+
+use crate::{Error, Result};
+use futures::future::{self, AbortHandle, BoxFuture};
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, Stream};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Poll;
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// A background task of a server, e.g. the feedback subscriber or the
+/// `get_interactive_markers` service. Tasks only finish when they are aborted, the server shuts
+/// down or their ROS endpoint fails, so a finished task of a running server means that part of
+/// it has stopped working.
+#[derive(Clone)]
+pub struct BackgroundTask {
+    name: &'static str,
+    abort: AbortHandle,
+    done: watch::Receiver<bool>,
+}
+
+impl BackgroundTask {
+    /// What the task does, e.g. `feedback_subscriber`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        // The sender is also dropped when the runtime drops the task without finishing it
+        *self.done.borrow() || self.done.has_changed().is_err()
+    }
+
+    /// Waits until the task has finished.
+    pub async fn finished(&self) {
+        let mut done = self.done.clone();
+        let _ = done.wait_for(|done| *done).await;
+    }
+}
+
+pub(crate) enum Spawner {
+    Ambient,
+    Handle(Handle),
+    Runner(mpsc::UnboundedSender<BoxFuture<'static, ()>>),
+}
+
+impl Spawner {
+    // Starts `task`, named `name` in the returned handle
+    pub(crate) fn spawn(
+        &self,
+        name: &'static str,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) -> BackgroundTask {
+        let (task, abort) = future::abortable(task);
+        let (done_tx, done) = watch::channel(false);
+        let task = task.map(move |_| {
+            let _ = done_tx.send(true);
+        });
+        match self {
+            Spawner::Ambient => {
+                tokio::task::spawn(task);
+            }
+            Spawner::Handle(runtime) => {
+                runtime.spawn(task);
+            }
+            // Without a runner nothing would poll the task anyway
+            Spawner::Runner(tasks) => {
+                let _ = tasks.send(task.boxed());
+            }
+        }
+        BackgroundTask { name, abort, done }
+    }
+
+    // Starts `task` without a handle to it, e.g. the future of an async feedback callback
+    pub(crate) fn spawn_detached(&self, task: impl Future<Output = ()> + Send + 'static) {
+        match self {
+            Spawner::Ambient => {
+                tokio::task::spawn(task);
+            }
+            Spawner::Handle(runtime) => {
+                runtime.spawn(task);
+            }
+            Spawner::Runner(tasks) => {
+                let _ = tasks.send(task.boxed());
+            }
+        }
+    }
+
+    // The runtime for tasks handed to the application as JoinHandles. A runner has none of its
+    // own, so these run on the ambient runtime, if there is one.
+    pub(crate) fn runtime(&self) -> Result<Handle> {
+        match self {
+            Spawner::Handle(runtime) => Ok(runtime.clone()),
+            Spawner::Ambient | Spawner::Runner(_) => {
+                Handle::try_current().map_err(|_| Error::NoRuntime)
+            }
+        }
+    }
+
+    pub(crate) fn spawn_joinable(
+        &self,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<JoinHandle<()>> {
+        Ok(self.runtime()?.spawn(task))
+    }
+}
+
+// A spawner handing tasks to the returned future, which polls all of them until the spawner is
+// dropped and every task has finished
+pub(crate) fn runner() -> (Spawner, impl Future<Output = ()> + Send + 'static) {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<BoxFuture<'static, ()>>();
+    let mut tasks = FuturesUnordered::new();
+    let mut closed = false;
+    let run = future::poll_fn(move |cx| {
+        while !closed {
+            match receiver.poll_recv(cx) {
+                Poll::Ready(Some(task)) => tasks.push(task),
+                Poll::Ready(None) => closed = true,
+                Poll::Pending => break,
+            }
+        }
+        while let Poll::Ready(Some(())) = Pin::new(&mut tasks).poll_next(cx) {}
+        if closed && tasks.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    });
+    (Spawner::Runner(sender), run)
+}
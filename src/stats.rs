@@ -0,0 +1,187 @@
+use r2r::visualization_msgs::msg::InteractiveMarkerFeedback;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// Why a feedback message was dropped or is suspected to be lost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedbackDropReason {
+    /// The feedback named a marker the server does not know.
+    UnknownMarker,
+    /// The pose update did not fit in the pending updates.
+    PendingOverflow,
+    /// A MOUSE_UP arrived without a MOUSE_DOWN, so the MOUSE_DOWN was probably lost in transport.
+    MissingMouseDown,
+    /// A second MOUSE_DOWN arrived without a MOUSE_UP in between, so the MOUSE_UP was probably lost.
+    MissingMouseUp,
+    /// The client is not allowed to interact with the marker.
+    ClientNotAllowed,
+}
+
+/// Counts of dropped and suspected lost feedback messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeedbackDrops {
+    /// Feedback ignored because the marker is unknown.
+    pub unknown_marker: u64,
+    /// Pose updates rejected because the pending updates were full.
+    pub pending_overflow: u64,
+    /// Messages presumed lost before reaching the server, detected from unpaired
+    /// MOUSE_DOWN/MOUSE_UP events. Usually a sign of a too small feedback queue depth.
+    pub suspected_lost: u64,
+    /// Feedback ignored because the client is not allowed to interact with the marker.
+    pub client_not_allowed: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct DropCounters {
+    unknown_marker: AtomicU64,
+    pending_overflow: AtomicU64,
+    suspected_lost: AtomicU64,
+    client_not_allowed: AtomicU64,
+}
+
+impl DropCounters {
+    pub(crate) fn record(&self, reason: FeedbackDropReason) {
+        let counter = match reason {
+            FeedbackDropReason::UnknownMarker => &self.unknown_marker,
+            FeedbackDropReason::PendingOverflow => &self.pending_overflow,
+            FeedbackDropReason::MissingMouseDown | FeedbackDropReason::MissingMouseUp => {
+                &self.suspected_lost
+            }
+            FeedbackDropReason::ClientNotAllowed => &self.client_not_allowed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> FeedbackDrops {
+        FeedbackDrops {
+            unknown_marker: self.unknown_marker.load(Ordering::Relaxed),
+            pending_overflow: self.pending_overflow.load(Ordering::Relaxed),
+            suspected_lost: self.suspected_lost.load(Ordering::Relaxed),
+            client_not_allowed: self.client_not_allowed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counters of a server for monitoring, returned by `InteractiveMarkerServer::stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Published markers, not counting pending inserts.
+    pub markers: usize,
+    /// Markers with a pending change.
+    pub pending: usize,
+    /// Feedback received from all clients, by event type.
+    pub feedback: FeedbackCounts,
+    pub feedback_drops: FeedbackDrops,
+    /// Updates handed to the publisher, counting an update split by `max_update_bytes` once
+    /// per part.
+    pub updates_published: u64,
+    /// Updates and init messages dropped after every publish attempt failed.
+    pub publish_failures: u64,
+    /// When an apply last published changes.
+    pub last_apply: Option<SystemTime>,
+    /// When each marker last received feedback, by name. Markers without feedback are left out.
+    pub last_interaction: HashMap<String, SystemTime>,
+}
+
+/// Feedback messages by event type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeedbackCounts {
+    pub keep_alives: u64,
+    pub pose_updates: u64,
+    pub menu_selects: u64,
+    pub button_clicks: u64,
+    pub mouse_downs: u64,
+    pub mouse_ups: u64,
+}
+
+impl FeedbackCounts {
+    pub fn total(&self) -> u64 {
+        self.keep_alives
+            + self.pose_updates
+            + self.menu_selects
+            + self.button_clicks
+            + self.mouse_downs
+            + self.mouse_ups
+    }
+
+    pub(crate) fn add(&mut self, activity: &ClientActivity) {
+        self.keep_alives += activity.keep_alives;
+        self.pose_updates += activity.pose_updates;
+        self.menu_selects += activity.menu_selects;
+        self.button_clicks += activity.button_clicks;
+        self.mouse_downs += activity.mouse_downs;
+        self.mouse_ups += activity.mouse_ups;
+    }
+}
+
+/// Feedback received from one client, identified by its client_id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientActivity {
+    pub keep_alives: u64,
+    pub pose_updates: u64,
+    pub menu_selects: u64,
+    pub button_clicks: u64,
+    pub mouse_downs: u64,
+    pub mouse_ups: u64,
+    /// Approximate serialized size of all feedback from the client.
+    pub feedback_bytes: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+impl ClientActivity {
+    pub(crate) fn new(now: SystemTime) -> Self {
+        Self {
+            keep_alives: 0,
+            pose_updates: 0,
+            menu_selects: 0,
+            button_clicks: 0,
+            mouse_downs: 0,
+            mouse_ups: 0,
+            feedback_bytes: 0,
+            first_seen: now,
+            last_seen: now,
+        }
+    }
+
+    pub(crate) fn record(&mut self, feedback: &InteractiveMarkerFeedback, now: SystemTime) {
+        let counter = match feedback.event_type {
+            t if t == InteractiveMarkerFeedback::KEEP_ALIVE as u8 => Some(&mut self.keep_alives),
+            t if t == InteractiveMarkerFeedback::POSE_UPDATE as u8 => Some(&mut self.pose_updates),
+            t if t == InteractiveMarkerFeedback::MENU_SELECT as u8 => Some(&mut self.menu_selects),
+            t if t == InteractiveMarkerFeedback::BUTTON_CLICK as u8 => {
+                Some(&mut self.button_clicks)
+            }
+            t if t == InteractiveMarkerFeedback::MOUSE_DOWN as u8 => Some(&mut self.mouse_downs),
+            t if t == InteractiveMarkerFeedback::MOUSE_UP as u8 => Some(&mut self.mouse_ups),
+            _ => None,
+        };
+        if let Some(counter) = counter {
+            *counter += 1;
+        }
+        self.feedback_bytes += feedback_size(feedback) as u64;
+        self.last_seen = now;
+    }
+}
+
+// CDR size of a feedback message, ignoring alignment padding. Strings are a length, the bytes
+// and a terminating nul.
+fn feedback_size(feedback: &InteractiveMarkerFeedback) -> usize {
+    const STAMP: usize = 8;
+    const EVENT_TYPE: usize = 1;
+    const POSE: usize = 7 * 8;
+    const MENU_ENTRY_ID: usize = 4;
+    const MOUSE_POINT: usize = 3 * 8 + 1;
+    let string = |s: &str| 4 + s.len() + 1;
+
+    STAMP
+        + string(&feedback.header.frame_id)
+        + string(&feedback.client_id)
+        + string(&feedback.marker_name)
+        + string(&feedback.control_name)
+        + EVENT_TYPE
+        + POSE
+        + MENU_ENTRY_ID
+        + MOUSE_POINT
+}
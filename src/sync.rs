@@ -0,0 +1,27 @@
+// Internal synchronization primitives. With the `parking_lot` feature (default) these are the
+// parking_lot types; otherwise std locks are wrapped so that a panic while holding a lock does
+// not poison it for the rest of the server.
+
+#[cfg(feature = "parking_lot")]
+pub(crate) use parking_lot::Mutex;
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) use self::std_sync::Mutex;
+
+#[cfg(not(feature = "parking_lot"))]
+mod std_sync {
+    use std::sync::{MutexGuard, PoisonError};
+
+    #[derive(Debug, Default)]
+    pub(crate) struct Mutex<T>(std::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub(crate) fn new(value: T) -> Self {
+            Self(std::sync::Mutex::new(value))
+        }
+
+        pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+}
@@ -0,0 +1,602 @@
+// Tests of server behavior that needs a node, run against a local ROS context. The helpers are
+// shared with the tests of the widgets.
+
+use crate::publisher::Outgoing;
+use crate::*;
+use r2r::geometry_msgs::msg::{Point, Pose};
+use r2r::visualization_msgs::msg::{
+    InteractiveMarker, InteractiveMarkerFeedback, InteractiveMarkerUpdate,
+};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+pub(crate) fn node() -> Arc<Mutex<r2r::Node>> {
+    let context = r2r::Context::create().unwrap();
+    Arc::new(Mutex::new(
+        r2r::Node::create(context, "interactive_markers_test", "").unwrap(),
+    ))
+}
+
+pub(crate) fn server(config: ServerConfig) -> InteractiveMarkerServer {
+    InteractiveMarkerServer::new_with_config("test", node(), config).unwrap()
+}
+
+pub(crate) fn marker(name: &str) -> InteractiveMarker {
+    InteractiveMarkerBuilder::new(name)
+        .frame("base_link")
+        .build()
+}
+
+pub(crate) fn pose_at(x: f64) -> Pose {
+    Pose {
+        position: Point {
+            x,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+pub(crate) fn drag(name: &str, pose: Pose) -> InteractiveMarkerFeedback {
+    feedback(name, InteractiveMarkerFeedback::POSE_UPDATE as u8, pose)
+}
+
+// Feedback of `event_type` from the client "rviz"
+pub(crate) fn feedback(name: &str, event_type: u8, pose: Pose) -> InteractiveMarkerFeedback {
+    InteractiveMarkerFeedback {
+        client_id: "rviz".to_string(),
+        marker_name: name.to_string(),
+        event_type,
+        pose,
+        ..Default::default()
+    }
+}
+
+// Takes over the messages the server hands to its publisher task, so tests can inspect them
+pub(crate) fn capture(server: &InteractiveMarkerServer) -> mpsc::UnboundedReceiver<Outgoing> {
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+    server.endpoints.lock().outgoing_tx = outgoing_tx;
+    outgoing_rx
+}
+
+// The updates captured since the last call
+pub(crate) fn sent_updates(
+    outgoing: &mut mpsc::UnboundedReceiver<Outgoing>,
+) -> Vec<InteractiveMarkerUpdate> {
+    std::iter::from_fn(|| outgoing.try_recv().ok())
+        .filter_map(|outgoing| match outgoing {
+            Outgoing::Update { update, .. } => Some(update),
+            _ => None,
+        })
+        .collect()
+}
+
+// Selection of the menu entry `entry_id` of a marker
+pub(crate) fn select(name: &str, entry_id: u32) -> InteractiveMarkerFeedback {
+    let mut select = feedback(
+        name,
+        InteractiveMarkerFeedback::MENU_SELECT as u8,
+        Pose::default(),
+    );
+    select.menu_entry_id = entry_id;
+    select
+}
+
+#[tokio::test]
+async fn authoritative_drag_is_corrected() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+    server.set_pose_mode("m", PoseMode::Authoritative);
+    let stored = server.get("m").unwrap().pose;
+
+    server.handle_feedback(drag("m", pose_at(1.0)));
+    let applied = server.apply_changes().unwrap();
+
+    // The stored pose did not change, but the dragging client must be sent back to it
+    assert_eq!(applied.pose_updates, 1);
+    assert_eq!(server.get("m").unwrap().pose, stored);
+}
+
+#[tokio::test]
+async fn unchanged_set_pose_is_skipped() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+
+    let stored = server.get("m").unwrap().pose;
+    server.set_pose("m", stored, None);
+    assert!(server.apply_changes().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn overflowing_followers_do_not_deadlock_apply() {
+    let server = server(ServerConfig {
+        max_pending_updates: 2,
+        overflow_policy: OverflowPolicy::ApplyChanges,
+        ..Default::default()
+    });
+    for name in ["leader", "follower", "other"] {
+        server.insert(marker(name));
+    }
+    server.apply_changes().unwrap();
+    server.set_parent("follower", Some("leader"));
+    server.set_pose("leader", pose_at(1.0), None);
+    server.set_pose("other", pose_at(1.0), None);
+
+    // The follower's pose overflows the pending limit while the apply holds its lock
+    let (done_tx, done) = std::sync::mpsc::channel();
+    let applying = server.clone();
+    std::thread::spawn(move || done_tx.send(applying.apply_changes().unwrap()));
+    let applied = done
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .expect("apply_changes deadlocked");
+    assert_eq!(applied.pose_updates, 3);
+    assert_eq!(server.get("follower").unwrap().pose.position.x, 1.0);
+}
+
+#[tokio::test]
+async fn identical_reinsert_is_published_by_default() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+
+    server.insert(marker("m"));
+    assert!(!server.apply_changes().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn delta_updates_send_reinserts_as_poses() {
+    let server = server(ServerConfig {
+        delta_updates: true,
+        ..Default::default()
+    });
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+
+    let mut moved = marker("m");
+    moved.pose = pose_at(1.0);
+    server.insert(moved);
+    assert_eq!(server.apply_changes().unwrap().pose_updates, 1);
+}
+
+#[tokio::test]
+async fn reinserts_are_full_updates_by_default() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+
+    let mut moved = marker("m");
+    moved.pose = pose_at(1.0);
+    server.insert(moved);
+    assert_eq!(server.apply_changes().unwrap().full_updates, 1);
+}
+
+#[tokio::test]
+async fn republish_on_reconnect_is_opt_in() {
+    let server = server(ServerConfig::default());
+    assert!(!server
+        .background_tasks()
+        .iter()
+        .any(|task| task.name() == "republish_on_reconnect"));
+}
+
+#[tokio::test]
+async fn dropping_after_shutdown_is_quiet() {
+    let server = server(ServerConfig {
+        snapshot_topic: true,
+        pose_array_topic: true,
+        ..Default::default()
+    });
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+    let logger = Arc::clone(&server.logger);
+
+    server.shutdown().unwrap();
+    server.shutdown().unwrap();
+    drop(server);
+    assert_eq!(logger.errors.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn invalid_max_pose_rate_is_rejected() {
+    for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        let config = ServerConfig {
+            max_pose_rate: Some(rate),
+            ..Default::default()
+        };
+        let result = InteractiveMarkerServer::new_with_config("test", node(), config);
+        assert!(matches!(result, Err(Error::InvalidRate(_))));
+    }
+}
+
+#[tokio::test]
+async fn zero_drag_timeout_keeps_watchdog_running() {
+    let server = server(ServerConfig {
+        drag_timeout: Some(std::time::Duration::ZERO),
+        ..Default::default()
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+    let watchdog = server
+        .background_tasks()
+        .into_iter()
+        .find(|task| task.name() == "drag_watchdog")
+        .unwrap();
+    assert!(!watchdog.is_finished());
+}
+
+#[tokio::test]
+async fn zero_follow_rate_is_rejected() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+
+    assert!(matches!(
+        server.follow_frame("m", "map", 0.0),
+        Err(Error::InvalidRate(_))
+    ));
+}
+
+#[tokio::test]
+async fn zero_publish_rates_are_rejected() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+
+    assert!(matches!(
+        server.publish_odometry("m", "odom", 0.0),
+        Err(Error::InvalidRate(_))
+    ));
+    assert!(matches!(
+        widgets::Teleop::new(
+            &server,
+            "teleop",
+            "base_link",
+            Pose::default(),
+            "cmd_vel",
+            0.0
+        ),
+        Err(Error::InvalidRate(_))
+    ));
+}
+
+#[test]
+fn runner_without_runtime_fails_joinable_tasks() {
+    let (server, _run) =
+        InteractiveMarkerServer::new_with_runner("test", node(), ServerConfig::default()).unwrap();
+
+    assert!(matches!(
+        server.follow_frame("m", "map", 10.0),
+        Err(Error::NoRuntime)
+    ));
+}
+
+#[test]
+fn client_runs_on_given_runtime() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client =
+        InteractiveMarkerClient::new_with_handle("test", node(), runtime.handle().clone()).unwrap();
+
+    assert_eq!(client.state(), ClientState::Idle);
+}
+
+#[tokio::test]
+async fn occupancy_subscriber_is_a_background_task() {
+    let server = server(ServerConfig::default());
+    placement::OccupancyConstraint::subscribe(&server, "/map").unwrap();
+
+    assert!(server
+        .background_tasks()
+        .iter()
+        .any(|task| task.name() == "occupancy_subscriber"));
+}
+
+#[cfg(feature = "serde")]
+#[tokio::test]
+async fn scene_has_published_markers_sorted() {
+    let server = server(ServerConfig::default());
+    for name in ["b", "a", "c"] {
+        server.insert(marker(name));
+    }
+    server.apply_changes().unwrap();
+    server.insert(marker("pending"));
+
+    let names: Vec<String> = server
+        .to_scene()
+        .markers
+        .into_iter()
+        .map(|marker| marker.name)
+        .collect();
+    assert_eq!(names, ["a", "b", "c"]);
+}
+
+#[tokio::test]
+async fn pending_changes_can_be_inspected_and_cancelled() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("moved"));
+    server.insert(marker("erased"));
+    server.apply_changes().unwrap();
+
+    server.insert(marker("inserted"));
+    assert!(server.set_pose("moved", pose_at(1.0), None));
+    assert!(server.erase("erased"));
+    let mut pending = server.pending_changes();
+    pending.sort_by(|a, b| a.name().cmp(b.name()));
+    assert!(matches!(&pending[0], PendingChange::Erase { name } if name == "erased"));
+    assert!(matches!(
+        &pending[1],
+        PendingChange::Insert { name, marker } if name == "inserted" && marker.name == "inserted"
+    ));
+    assert!(matches!(
+        &pending[2],
+        PendingChange::Pose { name, pose } if name == "moved" && pose.pose == pose_at(1.0)
+    ));
+
+    assert!(server.cancel_pending("inserted"));
+    assert!(server.cancel_pending("moved"));
+    assert!(server.cancel_pending("erased"));
+    assert!(!server.cancel_pending("erased"));
+    assert!(server.pending_changes().is_empty());
+    assert!(server.apply_changes().unwrap().is_empty());
+
+    assert!(server.get("inserted").is_none());
+    assert_eq!(server.get("moved").unwrap().pose, marker("moved").pose);
+    // A cancelled erase leaves the marker published
+    assert!(server.get("erased").is_some());
+    assert_eq!(server.size(), 2);
+}
+
+#[tokio::test]
+async fn pose_history_is_bounded_and_undoes_drags() {
+    let server = server(ServerConfig {
+        pose_history_depth: 2,
+        ..Default::default()
+    });
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+    let x = |name: &str| server.get(name).unwrap().pose.position.x;
+
+    for step in 1..=3 {
+        assert!(server.set_pose("m", pose_at(step as f64), None));
+        server.apply_changes().unwrap();
+    }
+    let history: Vec<f64> = server
+        .history("m")
+        .iter()
+        .map(|pose| pose.pose.position.x)
+        .collect();
+    assert_eq!(history, [1.0, 2.0]);
+
+    for event_type in [
+        InteractiveMarkerFeedback::MOUSE_DOWN,
+        InteractiveMarkerFeedback::POSE_UPDATE,
+        InteractiveMarkerFeedback::MOUSE_UP,
+    ] {
+        server.handle_feedback(feedback("m", event_type as u8, pose_at(5.0)));
+    }
+    server.apply_changes().unwrap();
+    assert_eq!(x("m"), 5.0);
+    assert!(server.undo("m"));
+    server.apply_changes().unwrap();
+    assert_eq!(x("m"), 3.0);
+    assert!(server.redo("m"));
+    server.apply_changes().unwrap();
+    assert_eq!(x("m"), 5.0);
+
+    // A new pose clears what was undone
+    assert!(server.undo("m"));
+    assert!(server.set_pose("m", pose_at(7.0), None));
+    assert!(!server.redo("m"));
+
+    // An erased marker starts over when inserted again
+    assert!(server.erase("m"));
+    server.apply_changes().unwrap();
+    assert!(server.history("m").is_empty());
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+    assert!(!server.undo("m"));
+}
+
+#[tokio::test]
+async fn paused_changes_are_published_together_on_resume() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("moved"));
+    let seq_num = server.apply_changes().unwrap().seq_num;
+    let published = server.stats().updates_published;
+
+    server.pause();
+    assert!(server.is_paused());
+    server.insert(marker("a"));
+    assert!(server.apply_changes().unwrap().is_empty());
+    assert!(server.set_pose("moved", pose_at(1.0), None));
+    assert!(server.apply_changes().unwrap().is_empty());
+    assert_eq!(server.stats().updates_published, published);
+
+    let applied = server.resume().unwrap();
+    assert!(!server.is_paused());
+    assert_eq!(applied.seq_num, seq_num + 1);
+    assert_eq!((applied.full_updates, applied.pose_updates), (1, 1));
+    assert_eq!(server.stats().updates_published, published + 1);
+}
+
+#[tokio::test]
+async fn inactive_servers_show_nothing_until_activated() {
+    let server = server(ServerConfig::default());
+    let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = Arc::clone(&calls);
+    server.insert(marker("b"));
+    server.insert_with_callback(
+        &marker("a"),
+        Some(Arc::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })),
+        InteractiveMarkerFeedback::POSE_UPDATE as u8,
+    );
+    server.apply_changes().unwrap();
+    let mut outgoing = capture(&server);
+
+    server.deactivate().unwrap();
+    assert!(!server.is_active());
+    let updates = sent_updates(&mut outgoing);
+    assert_eq!(updates.len(), 1);
+    let mut erases = updates[0].erases.clone();
+    erases.sort();
+    assert_eq!(erases, ["a", "b"]);
+    // The service and reinitializing clients get no markers either
+    assert!(server.marker_snapshot().markers.is_empty());
+
+    server.handle_feedback(drag("a", pose_at(1.0)));
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+    assert!(server.pending_changes().is_empty());
+    assert!(server.set_pose("b", pose_at(2.0), None));
+    assert!(server.apply_changes().unwrap().is_empty());
+    assert!(sent_updates(&mut outgoing).is_empty());
+    assert_eq!(server.size(), 2);
+
+    server.activate().unwrap();
+    let updates = sent_updates(&mut outgoing);
+    assert_eq!(updates[0].markers.len(), 2);
+    assert_eq!(updates[1].poses[0].pose, pose_at(2.0));
+    assert_eq!(server.marker_snapshot().markers.len(), 2);
+    server.handle_feedback(drag("a", pose_at(1.0)));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn keep_alives_carry_the_sequence_number_while_publishing() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("a"));
+    let seq_num = server.apply_changes().unwrap().seq_num;
+    let mut outgoing = capture(&server);
+    let mut last_seq_num = None;
+
+    // The first period saw an update
+    server.send_keep_alive(&mut last_seq_num);
+    assert!(sent_updates(&mut outgoing).is_empty());
+    server.send_keep_alive(&mut last_seq_num);
+    let updates = sent_updates(&mut outgoing);
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].type_, InteractiveMarkerUpdate::KEEP_ALIVE as u8);
+    assert_eq!(updates[0].seq_num, seq_num);
+
+    server.pause();
+    server.send_keep_alive(&mut last_seq_num);
+    assert!(sent_updates(&mut outgoing).is_empty());
+    server.resume().unwrap();
+    server.deactivate().unwrap();
+    sent_updates(&mut outgoing);
+    for _ in 0..2 {
+        server.send_keep_alive(&mut last_seq_num);
+    }
+    assert!(sent_updates(&mut outgoing).is_empty());
+}
+
+#[tokio::test]
+async fn sequence_numbers_continue_from_the_seed_until_reset() {
+    let server = server(ServerConfig {
+        initial_sequence_number: 41,
+        ..Default::default()
+    });
+    assert_eq!(server.apply_changes().unwrap().seq_num, 41);
+    server.insert(marker("a"));
+    assert_eq!(server.apply_changes().unwrap().seq_num, 42);
+
+    let mut outgoing = capture(&server);
+    server.reset_sequence().unwrap();
+    let updates = sent_updates(&mut outgoing);
+    assert_eq!(updates.len(), 1);
+    assert_eq!((updates[0].seq_num, updates[0].markers.len()), (1, 1));
+    assert!(server.set_pose("a", pose_at(1.0), None));
+    assert_eq!(server.apply_changes().unwrap().seq_num, 2);
+}
+
+#[tokio::test]
+async fn erasing_a_parent_erases_children_despite_a_full_queue() {
+    let server = server(ServerConfig {
+        max_pending_updates: 1,
+        overflow_policy: OverflowPolicy::Reject,
+        ..Default::default()
+    });
+    for name in ["parent", "child", "grandchild", "label"] {
+        server.insert(marker(name));
+        server.apply_changes().unwrap();
+    }
+    assert!(server.set_parent("child", Some("parent")));
+    assert!(server.set_parent("grandchild", Some("child")));
+    server.attach_follower("parent", "label", Pose::default());
+
+    assert!(server.erase("parent"));
+    assert_eq!(server.apply_changes().unwrap().erases, 3);
+    assert!(server.get("child").is_none() && server.get("grandchild").is_none());
+    assert!(server.followers.is_empty());
+    assert!(server.parents.is_empty());
+    assert!(server.get("label").is_some());
+}
+
+#[tokio::test]
+async fn duplicate_menu_numbers_copies() {
+    let server = server(ServerConfig::default());
+    server.insert(marker("m"));
+    server.apply_changes().unwrap();
+    assert!(server.add_duplicate_menu_entry("m", pose_at(1.0)));
+    server.apply_changes().unwrap();
+    let entry_id = server.get("m").unwrap().menu_entries[0].id;
+
+    server.handle_feedback(select("m", entry_id));
+    server.handle_feedback(select("m", entry_id));
+    assert_eq!(server.get("m_copy2").unwrap().pose.position.x, 1.0);
+    server.handle_feedback(select("m_copy1", entry_id));
+    assert!(server.get("m_copy1_copy1").is_some());
+}
+
+#[tokio::test]
+async fn rate_limited_poses_are_reported_as_deferred() {
+    let server = server(ServerConfig {
+        max_pose_rate: Some(0.1),
+        ..Default::default()
+    });
+    server.insert(marker("m"));
+    assert_eq!(server.apply_changes().unwrap().deferred, 0);
+
+    assert!(server.set_pose("m", pose_at(1.0), None));
+    let applied = server.apply_changes().unwrap();
+    assert_eq!(applied.deferred, 1);
+    // Nothing went out, the pose is still pending
+    assert!(applied.is_empty());
+    assert_eq!(server.pending_changes().len(), 1);
+}
+
+#[tokio::test]
+async fn only_the_committing_thread_bypasses_the_pending_limit() {
+    let server = server(ServerConfig {
+        max_pending_updates: 1,
+        overflow_policy: OverflowPolicy::Reject,
+        ..Default::default()
+    });
+    server.insert(marker("a"));
+
+    server.committing(|| {
+        std::thread::scope(|scope| {
+            scope.spawn(|| server.insert(marker("unrelated")));
+        });
+        server.insert(marker("committed"));
+    });
+    let mut names: Vec<String> = server
+        .pending_changes()
+        .iter()
+        .map(|change| change.name().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a", "committed"]);
+
+    // Transactions commit as a whole, also past the limit
+    server
+        .transaction(|tx| {
+            tx.insert(marker("b")).insert(marker("c"));
+            Ok::<_, Error>(())
+        })
+        .unwrap();
+    assert_eq!(server.size(), 4);
+}
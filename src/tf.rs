@@ -0,0 +1,104 @@
+// A minimal tf2 buffer. Keeps the latest transform of every frame published on /tf and
+// /tf_static and chains them to look up transforms between frames. There is no history, so
+// lookups always use the latest known transforms regardless of stamps.
+
+use crate::math;
+use crate::spawn::{BackgroundTask, Spawner};
+use crate::sync;
+use futures::StreamExt;
+use r2r::geometry_msgs::msg::{Transform, TransformStamped};
+use r2r::tf2_msgs::msg::TFMessage;
+use r2r::QosProfile;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Longest parent chain followed before a lookup gives up, guards against cycles
+const MAX_CHAIN_LENGTH: usize = 64;
+
+#[derive(Default)]
+pub(crate) struct TfBuffer {
+    // Child frame to (parent frame, transform from child to parent)
+    frames: sync::Mutex<HashMap<String, (String, Transform)>>,
+}
+
+fn frame_name(frame_id: &str) -> &str {
+    frame_id.trim_start_matches('/')
+}
+
+impl TfBuffer {
+    // Subscribes to /tf and /tf_static and keeps the buffer up to date from them
+    pub(crate) fn subscribe(
+        self: &Arc<Self>,
+        node: &Arc<Mutex<r2r::Node>>,
+        spawner: &Spawner,
+    ) -> r2r::Result<Vec<BackgroundTask>> {
+        let tf_sub = node
+            .lock()
+            .unwrap()
+            .subscribe::<TFMessage>("/tf", QosProfile::default())?;
+        let mut tf_static_qos = QosProfile::default().transient_local();
+        tf_static_qos.depth = 100;
+        let tf_static_sub = node
+            .lock()
+            .unwrap()
+            .subscribe::<TFMessage>("/tf_static", tf_static_qos)?;
+
+        Ok([tf_sub, tf_static_sub]
+            .into_iter()
+            .map(|mut sub| {
+                let buffer = Arc::clone(self);
+                spawner.spawn("tf_subscription", async move {
+                    while let Some(message) = sub.next().await {
+                        buffer.insert(message.transforms);
+                    }
+                })
+            })
+            .collect())
+    }
+
+    pub(crate) fn insert(&self, transforms: Vec<TransformStamped>) {
+        let mut frames = self.frames.lock();
+        for transform in transforms {
+            frames.insert(
+                frame_name(&transform.child_frame_id).to_string(),
+                (
+                    frame_name(&transform.header.frame_id).to_string(),
+                    transform.transform,
+                ),
+            );
+        }
+    }
+
+    // The transform taking poses expressed in `source` to `target`, if both are in the same tree
+    pub(crate) fn lookup(&self, target: &str, source: &str) -> Option<Transform> {
+        let frames = self.frames.lock();
+        let (target_root, root_from_target) = Self::to_root(&frames, frame_name(target))?;
+        let (source_root, root_from_source) = Self::to_root(&frames, frame_name(source))?;
+        if target_root != source_root {
+            return None;
+        }
+        Some(math::compose(
+            &math::inverse(&root_from_target),
+            &root_from_source,
+        ))
+    }
+
+    // Follows the parents of a frame up to the root of its tree
+    fn to_root<'a>(
+        frames: &'a HashMap<String, (String, Transform)>,
+        frame: &'a str,
+    ) -> Option<(&'a str, Transform)> {
+        let mut current = frame;
+        let mut root_from_frame = math::identity();
+        for _ in 0..MAX_CHAIN_LENGTH {
+            match frames.get(current) {
+                Some((parent, parent_from_current)) => {
+                    root_from_frame = math::compose(parent_from_current, &root_from_frame);
+                    current = parent;
+                }
+                None => return Some((current, root_from_frame)),
+            }
+        }
+        None
+    }
+}
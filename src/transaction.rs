@@ -0,0 +1,99 @@
+use r2r::geometry_msgs::msg::Pose;
+use r2r::std_msgs::msg::Header;
+use r2r::visualization_msgs::msg::InteractiveMarker;
+
+pub(crate) enum Staged {
+    Insert(InteractiveMarker),
+    Pose {
+        name: String,
+        pose: Pose,
+        header: Option<Header>,
+    },
+    Erase(String),
+}
+
+/// Changes staged by `InteractiveMarkerServer::transaction`. Nothing reaches the server until
+/// the transaction commits, so a failing transaction leaves the server untouched.
+#[derive(Default)]
+pub struct Transaction {
+    pub(crate) staged: Vec<Staged>,
+}
+
+impl Transaction {
+    /// Stages `InteractiveMarkerServer::insert`.
+    pub fn insert(&mut self, marker: InteractiveMarker) -> &mut Self {
+        self.staged.push(Staged::Insert(marker));
+        self
+    }
+
+    /// Stages `InteractiveMarkerServer::set_pose`. Markers inserted earlier in the same
+    /// transaction can be moved, poses of unknown markers are ignored on commit.
+    pub fn set_pose(&mut self, name: &str, pose: Pose, header: Option<Header>) -> &mut Self {
+        self.staged.push(Staged::Pose {
+            name: name.to_string(),
+            pose,
+            header,
+        });
+        self
+    }
+
+    /// Stages `InteractiveMarkerServer::erase`.
+    pub fn erase(&mut self, name: &str) -> &mut Self {
+        self.staged.push(Staged::Erase(name.to_string()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{marker, pose_at, server};
+    use crate::{Error, ServerConfig};
+
+    #[tokio::test]
+    async fn commits_in_a_single_update() {
+        let server = server(ServerConfig::default());
+        server.insert(marker("old"));
+        let seq_num = server.apply_changes().unwrap().seq_num;
+        let published = server.stats().updates_published;
+
+        server
+            .transaction(|tx| {
+                tx.insert(marker("a"))
+                    .insert(marker("b"))
+                    .set_pose("a", pose_at(1.0), None)
+                    .erase("old");
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        assert_eq!(server.stats().updates_published, published + 1);
+        assert_eq!(server.apply_changes().unwrap().seq_num, seq_num + 1);
+        assert_eq!(server.get("a").unwrap().pose, pose_at(1.0));
+        assert!(server.get("b").is_some());
+        assert!(server.get("old").is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_transactions_publish_nothing() {
+        let server = server(ServerConfig::default());
+        let seq_num = server.apply_changes().unwrap().seq_num;
+
+        let result = server.transaction(|tx| {
+            tx.insert(marker("a"));
+            Err::<(), _>(Error::PublisherStopped)
+        });
+        assert!(result.is_err());
+        let mut dropped = Transaction::default();
+        dropped.insert(marker("b"));
+        drop(dropped);
+
+        assert!(server.pending_changes().is_empty());
+        assert_eq!(server.stats().updates_published, 0);
+        assert_eq!(server.apply_changes().unwrap().seq_num, seq_num);
+        assert!(server.empty());
+    }
+}
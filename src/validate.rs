@@ -0,0 +1,161 @@
+use r2r::geometry_msgs::msg::{Pose, Quaternion};
+use r2r::visualization_msgs::msg::{InteractiveMarker, InteractiveMarkerControl};
+use std::collections::HashSet;
+
+/// Something about a marker that makes clients drop or misdraw it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationIssue {
+    EmptyName,
+    /// The marker's pose contains NaN or infinite values.
+    NonFinitePose,
+    /// The orientation of `control` or the pose of one of its markers contains NaN or infinite
+    /// values.
+    NonFiniteControl {
+        control: String,
+    },
+    /// Several controls are named `control`, so feedback cannot tell them apart.
+    DuplicateControlName {
+        control: String,
+    },
+    UnknownInteractionMode {
+        control: String,
+        mode: u8,
+    },
+    UnknownOrientationMode {
+        control: String,
+        mode: u8,
+    },
+    /// `independent_marker_orientation` is set on a control that is not VIEW_FACING, where it
+    /// has no meaning.
+    IndependentOrientationNotViewFacing {
+        control: String,
+    },
+}
+
+fn finite_orientation(q: &Quaternion) -> bool {
+    [q.x, q.y, q.z, q.w].iter().all(|v| v.is_finite())
+}
+
+fn finite_pose(pose: &Pose) -> bool {
+    let p = &pose.position;
+    [p.x, p.y, p.z].iter().all(|v| v.is_finite()) && finite_orientation(&pose.orientation)
+}
+
+/// Checks a marker for problems that clients like RViz silently drop it for. Returns every
+/// issue found.
+pub fn validate(marker: &InteractiveMarker) -> Result<(), Vec<ValidationIssue>> {
+    let mut issues = Vec::new();
+    if marker.name.is_empty() {
+        issues.push(ValidationIssue::EmptyName);
+    }
+    if !finite_pose(&marker.pose) {
+        issues.push(ValidationIssue::NonFinitePose);
+    }
+
+    let mut names = HashSet::new();
+    for control in &marker.controls {
+        let name = || control.name.clone();
+        if !names.insert(control.name.as_str()) {
+            issues.push(ValidationIssue::DuplicateControlName { control: name() });
+        }
+        if !finite_orientation(&control.orientation)
+            || !control.markers.iter().all(|m| finite_pose(&m.pose))
+        {
+            issues.push(ValidationIssue::NonFiniteControl { control: name() });
+        }
+        if control.interaction_mode > InteractiveMarkerControl::MOVE_ROTATE_3D as u8 {
+            issues.push(ValidationIssue::UnknownInteractionMode {
+                control: name(),
+                mode: control.interaction_mode,
+            });
+        }
+        if control.orientation_mode > InteractiveMarkerControl::VIEW_FACING as u8 {
+            issues.push(ValidationIssue::UnknownOrientationMode {
+                control: name(),
+                mode: control.orientation_mode,
+            });
+        } else if control.independent_marker_orientation
+            && control.orientation_mode != InteractiveMarkerControl::VIEW_FACING as u8
+        {
+            issues.push(ValidationIssue::IndependentOrientationNotViewFacing { control: name() });
+        }
+    }
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(issues)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> InteractiveMarker {
+        let mut marker = InteractiveMarker {
+            name: "m".to_string(),
+            controls: vec![InteractiveMarkerControl {
+                name: "move".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        marker.pose.orientation.w = 1.0;
+        marker
+    }
+
+    #[test]
+    fn valid_markers_pass() {
+        assert_eq!(validate(&valid()), Ok(()));
+    }
+
+    #[test]
+    fn every_issue_is_reported() {
+        let mut marker = valid();
+        marker.name.clear();
+        marker.pose.position.x = f64::NAN;
+        let mut duplicate = marker.controls[0].clone();
+        duplicate.interaction_mode = 200;
+        duplicate.orientation.w = f64::INFINITY;
+        marker.controls.push(duplicate);
+
+        let control = || "move".to_string();
+        assert_eq!(
+            validate(&marker),
+            Err(vec![
+                ValidationIssue::EmptyName,
+                ValidationIssue::NonFinitePose,
+                ValidationIssue::DuplicateControlName { control: control() },
+                ValidationIssue::NonFiniteControl { control: control() },
+                ValidationIssue::UnknownInteractionMode {
+                    control: control(),
+                    mode: 200
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn independent_orientation_needs_view_facing() {
+        let mut marker = valid();
+        marker.controls[0].independent_marker_orientation = true;
+        assert_eq!(
+            validate(&marker),
+            Err(vec![ValidationIssue::IndependentOrientationNotViewFacing {
+                control: "move".to_string()
+            }])
+        );
+
+        marker.controls[0].orientation_mode = InteractiveMarkerControl::VIEW_FACING as u8;
+        assert_eq!(validate(&marker), Ok(()));
+        marker.controls[0].orientation_mode = 9;
+        assert_eq!(
+            validate(&marker),
+            Err(vec![ValidationIssue::UnknownOrientationMode {
+                control: "move".to_string(),
+                mode: 9
+            }])
+        );
+    }
+}
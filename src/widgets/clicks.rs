@@ -0,0 +1,115 @@
+use crate::{InteractiveMarkerServer, Result};
+use r2r::geometry_msgs::msg::PointStamped;
+use r2r::visualization_msgs::msg::InteractiveMarkerFeedback;
+use r2r::{Publisher, QosProfile};
+use std::sync::Arc;
+
+/// Publishes the point a user clicked on a BUTTON control as a `PointStamped`, like RViz's
+/// "Publish Point" tool but attached to specific markers.
+#[derive(Clone)]
+pub struct ClickedPoints {
+    server: InteractiveMarkerServer,
+    publisher: Publisher<PointStamped>,
+    #[cfg(test)]
+    published: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl ClickedPoints {
+    pub fn new(server: &InteractiveMarkerServer, topic: &str) -> Result<Self> {
+        let publisher = server
+            .node
+            .lock()
+            .unwrap()
+            .create_publisher::<PointStamped>(topic, QosProfile::default())?;
+        Ok(Self {
+            server: server.clone(),
+            publisher,
+            #[cfg(test)]
+            published: Arc::default(),
+        })
+    }
+
+    /// Publishes clicks on the BUTTON controls of the marker `name`. Takes over the
+    /// BUTTON_CLICK callback of the marker. Returns false if the marker is unknown.
+    pub fn attach(&self, name: &str) -> bool {
+        let publisher = self.publisher.clone();
+        let logger = Arc::clone(&self.server.logger);
+        #[cfg(test)]
+        let published = Arc::clone(&self.published);
+        self.server.set_callback(
+            name,
+            Some(Arc::new(move |feedback: InteractiveMarkerFeedback| {
+                let Some(point) = clicked_point(&feedback) else {
+                    return;
+                };
+                if let Err(e) = publisher.publish(&point) {
+                    logger.warn(format_args!(
+                        "Failed to publish the point clicked on '{}': {}",
+                        feedback.marker_name, e
+                    ));
+                }
+                #[cfg(test)]
+                published.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            })),
+            InteractiveMarkerFeedback::BUTTON_CLICK as u8,
+        )
+    }
+}
+
+// The clicked point in the frame of the feedback. Clicks that did not hit any geometry have no
+// point.
+fn clicked_point(feedback: &InteractiveMarkerFeedback) -> Option<PointStamped> {
+    feedback.mouse_point_valid.then(|| PointStamped {
+        header: feedback.header.clone(),
+        point: feedback.mouse_point.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{feedback, marker, server};
+    use crate::ServerConfig;
+    use r2r::geometry_msgs::msg::Pose;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn attaches_to_known_markers_only() {
+        let server = server(ServerConfig::default());
+        server.insert(marker("button"));
+        server.apply_changes().unwrap();
+        let clicks = ClickedPoints::new(&server, "clicked_point").unwrap();
+
+        assert!(clicks.attach("button"));
+        assert!(!clicks.attach("unknown"));
+    }
+
+    #[tokio::test]
+    async fn publishes_clicks_that_hit_geometry() {
+        let server = server(ServerConfig::default());
+        server.insert(marker("button"));
+        server.apply_changes().unwrap();
+        let clicks = ClickedPoints::new(&server, "clicked_point").unwrap();
+        assert!(clicks.attach("button"));
+
+        let mut click = feedback(
+            "button",
+            InteractiveMarkerFeedback::BUTTON_CLICK as u8,
+            Pose::default(),
+        );
+        click.header.frame_id = "base_link".to_string();
+        click.mouse_point.x = 0.5;
+
+        // A click that missed the geometry is ignored
+        assert!(clicked_point(&click).is_none());
+        server.handle_feedback(click.clone());
+        assert_eq!(clicks.published.load(Ordering::SeqCst), 0);
+
+        click.mouse_point_valid = true;
+        let point = clicked_point(&click).unwrap();
+        assert_eq!(point.header, click.header);
+        assert_eq!(point.point.x, 0.5);
+        server.handle_feedback(click);
+        assert_eq!(clicks.published.load(Ordering::SeqCst), 1);
+    }
+}
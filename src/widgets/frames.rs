@@ -0,0 +1,282 @@
+use crate::helpers::make_6dof_controls;
+use crate::{math, sync, InteractiveMarkerServer};
+use r2r::geometry_msgs::msg::{Pose, Quaternion, Transform, TransformStamped, Vector3};
+use r2r::std_msgs::msg::Header;
+use r2r::tf2_msgs::msg::TFMessage;
+use r2r::visualization_msgs::msg::{InteractiveMarker, InteractiveMarkerFeedback};
+use r2r::{Publisher, QosProfile};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const FRAME_MARKER_SCALE: f32 = 0.3;
+
+/// A static transform, like the `FrameData` of the examples.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub parent_frame_id: String,
+    pub child_frame_id: String,
+    pub transform: Transform,
+}
+
+/// Edits static transforms with 6-DOF markers. Every frame gets a marker `frame/<child>` in its
+/// parent frame, and dragging it updates the transform broadcast on /tf_static live, which makes
+/// the server a calibration and alignment tool.
+#[derive(Clone)]
+pub struct FrameEditor {
+    server: InteractiveMarkerServer,
+    // Frames by child frame id
+    frames: Arc<sync::Mutex<BTreeMap<String, Frame>>>,
+    publisher: Publisher<TFMessage>,
+    clock: Arc<Mutex<r2r::Clock>>,
+}
+
+impl FrameEditor {
+    pub fn new(
+        server: &InteractiveMarkerServer,
+        frames: impl IntoIterator<Item = Frame>,
+    ) -> crate::Result<Self> {
+        let (publisher, clock) = {
+            let mut node = server.node.lock().unwrap();
+            let publisher = node.create_publisher::<TFMessage>(
+                "/tf_static",
+                QosProfile::default().transient_local(),
+            )?;
+            (publisher, node.get_ros_clock())
+        };
+        let editor = Self {
+            server: server.clone(),
+            frames: Arc::new(sync::Mutex::new(BTreeMap::new())),
+            publisher,
+            clock,
+        };
+        for frame in frames {
+            editor.insert_frame(frame);
+        }
+        editor.server.apply_changes()?;
+        editor.broadcast();
+        Ok(editor)
+    }
+
+    /// Adds a frame, or replaces the frame with the same child frame id, and broadcasts it.
+    pub fn set_frame(&self, frame: Frame) -> crate::Result<()> {
+        self.insert_frame(frame);
+        self.server.apply_changes()?;
+        self.broadcast();
+        Ok(())
+    }
+
+    /// Stops editing and broadcasting a frame. Returns false if there is no such frame.
+    pub fn remove_frame(&self, child_frame_id: &str) -> crate::Result<bool> {
+        if self.frames.lock().remove(child_frame_id).is_none() {
+            return Ok(false);
+        }
+        self.server.erase(marker_name(child_frame_id));
+        self.server.apply_changes()?;
+        self.broadcast();
+        Ok(true)
+    }
+
+    /// The frames as currently edited.
+    pub fn frames(&self) -> Vec<Frame> {
+        self.frames.lock().values().cloned().collect()
+    }
+
+    /// Writes the frames to a YAML file. Every entry has the arguments of
+    /// `static_transform_publisher` as keys (`x`, `y`, `z`, `qx`, `qy`, `qz`, `qw`, `frame_id`,
+    /// `child_frame_id`), so launch files can turn it back into static transforms.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let entries: Vec<StaticTransform> = self
+            .frames
+            .lock()
+            .values()
+            .map(StaticTransform::from)
+            .collect();
+        std::fs::write(path, serde_yaml::to_string(&entries)?)?;
+        Ok(())
+    }
+
+    /// Replaces all frames with the ones in a YAML file in the format written by `save`.
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let entries: Vec<StaticTransform> = serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+        let old = std::mem::take(&mut *self.frames.lock());
+        for child_frame_id in old.keys() {
+            self.server.erase(marker_name(child_frame_id));
+        }
+        for entry in entries {
+            self.insert_frame(entry.into());
+        }
+        self.server.apply_changes()?;
+        self.broadcast();
+        Ok(())
+    }
+
+    fn insert_frame(&self, frame: Frame) {
+        let name = marker_name(&frame.child_frame_id);
+        let mut marker = InteractiveMarker {
+            name: name.clone(),
+            description: frame.child_frame_id.clone(),
+            pose: math::pose_of(&frame.transform),
+            scale: FRAME_MARKER_SCALE,
+            controls: make_6dof_controls(false),
+            ..Default::default()
+        };
+        marker.header.frame_id = frame.parent_frame_id.clone();
+        self.server.insert(marker);
+
+        let editor = self.clone();
+        let child_frame_id = frame.child_frame_id.clone();
+        self.server.set_callback(
+            &name,
+            Some(Arc::new(move |feedback: InteractiveMarkerFeedback| {
+                editor.move_frame(&child_frame_id, &feedback.pose);
+            })),
+            InteractiveMarkerFeedback::POSE_UPDATE as u8,
+        );
+        self.frames
+            .lock()
+            .insert(frame.child_frame_id.clone(), frame);
+    }
+
+    fn move_frame(&self, child_frame_id: &str, pose: &Pose) {
+        match self.frames.lock().get_mut(child_frame_id) {
+            Some(frame) => frame.transform = math::transform_of(pose),
+            None => return,
+        }
+        self.server.report("Applying changes", self.server.apply_changes());
+        self.broadcast();
+    }
+
+    // /tf_static is latched per publisher, so every broadcast carries all frames
+    fn broadcast(&self) {
+        let stamp = match self.clock.lock().unwrap().get_now() {
+            Ok(now) => r2r::Clock::to_builtin_time(&now),
+            Err(_) => Default::default(),
+        };
+        let transforms = self
+            .frames
+            .lock()
+            .values()
+            .map(|frame| TransformStamped {
+                header: Header {
+                    stamp: stamp.clone(),
+                    frame_id: frame.parent_frame_id.clone(),
+                },
+                child_frame_id: frame.child_frame_id.clone(),
+                transform: frame.transform.clone(),
+            })
+            .collect();
+        if let Err(e) = self.publisher.publish(&TFMessage { transforms }) {
+            self.server
+                .logger
+                .warn(format_args!("Failed to broadcast the edited frames: {}", e));
+        }
+    }
+}
+
+fn marker_name(child_frame_id: &str) -> String {
+    format!("frame/{}", child_frame_id)
+}
+
+// A frame as the arguments of static_transform_publisher
+#[derive(Serialize, Deserialize)]
+struct StaticTransform {
+    x: f64,
+    y: f64,
+    z: f64,
+    qx: f64,
+    qy: f64,
+    qz: f64,
+    qw: f64,
+    frame_id: String,
+    child_frame_id: String,
+}
+
+impl From<&Frame> for StaticTransform {
+    fn from(frame: &Frame) -> Self {
+        let (translation, rotation) = (&frame.transform.translation, &frame.transform.rotation);
+        Self {
+            x: translation.x,
+            y: translation.y,
+            z: translation.z,
+            qx: rotation.x,
+            qy: rotation.y,
+            qz: rotation.z,
+            qw: rotation.w,
+            frame_id: frame.parent_frame_id.clone(),
+            child_frame_id: frame.child_frame_id.clone(),
+        }
+    }
+}
+
+impl From<StaticTransform> for Frame {
+    fn from(entry: StaticTransform) -> Self {
+        Self {
+            parent_frame_id: entry.frame_id,
+            child_frame_id: entry.child_frame_id,
+            transform: Transform {
+                translation: Vector3 {
+                    x: entry.x,
+                    y: entry.y,
+                    z: entry.z,
+                },
+                rotation: Quaternion {
+                    x: entry.qx,
+                    y: entry.qy,
+                    z: entry.qz,
+                    w: entry.qw,
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drag, pose_at, server};
+    use crate::ServerConfig;
+
+    fn camera() -> Frame {
+        Frame {
+            parent_frame_id: "base_link".to_string(),
+            child_frame_id: "camera".to_string(),
+            transform: math::identity(),
+        }
+    }
+
+    #[tokio::test]
+    async fn dragging_moves_the_frame() {
+        let server = server(ServerConfig::default());
+        let editor = FrameEditor::new(&server, [camera()]).unwrap();
+        assert_eq!(
+            server.get("frame/camera").unwrap().header.frame_id,
+            "base_link"
+        );
+
+        server.handle_feedback(drag("frame/camera", pose_at(0.5)));
+        assert_eq!(editor.frames()[0].transform.translation.x, 0.5);
+
+        assert!(editor.remove_frame("camera").unwrap());
+        assert!(!editor.remove_frame("camera").unwrap());
+        assert!(server.get("frame/camera").is_none());
+    }
+
+    #[tokio::test]
+    async fn frames_are_saved_as_static_transforms() {
+        let server = server(ServerConfig::default());
+        let editor = FrameEditor::new(&server, [camera()]).unwrap();
+        let path = std::env::temp_dir().join(format!("frames_{}.yaml", std::process::id()));
+        editor.save(&path).unwrap();
+        let yaml = std::fs::read_to_string(&path).unwrap();
+        assert!(yaml.contains("frame_id: base_link"));
+        assert!(yaml.contains("qw: 1.0"));
+
+        editor.remove_frame("camera").unwrap();
+        editor.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(editor.frames(), [camera()]);
+        assert!(server.get("frame/camera").is_some());
+    }
+}
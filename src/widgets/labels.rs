@@ -0,0 +1,190 @@
+use super::{drawing, identity, text};
+use crate::{math, InteractiveMarkerServer, Result};
+use r2r::geometry_msgs::msg::{Point, Pose};
+use r2r::visualization_msgs::msg::InteractiveMarker;
+
+// Space between the controls and the label, relative to the marker scale
+const MARGIN: f64 = 0.1;
+// Text height relative to the marker scale
+const TEXT_SCALE: f64 = 0.2;
+
+/// Where a label is placed relative to its marker.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LabelPlacement {
+    /// Above the controls.
+    Above,
+    /// Beside the controls, along the marker's y axis.
+    Side,
+    /// Above the controls, but hidden while the marker's scale is below the given value.
+    AboveUnlessSmallerThan(f32),
+}
+
+/// Text labels shown next to markers. A label is a separate marker `<name>/label` that follows
+/// its marker in the same updates, placed clear of the marker's scale and control extents. Its
+/// text can change at runtime without resending the marker's controls.
+#[derive(Clone)]
+pub struct Labels {
+    server: InteractiveMarkerServer,
+}
+
+impl Labels {
+    pub fn new(server: &InteractiveMarkerServer) -> Self {
+        Self {
+            server: server.clone(),
+        }
+    }
+
+    /// Shows `text` next to the marker `name`, replacing an existing label. Returns false if the
+    /// marker is unknown.
+    pub fn set(&self, name: &str, text: &str, placement: LabelPlacement) -> Result<bool> {
+        let Some(marker) = self.server.get(name) else {
+            return Ok(false);
+        };
+        let label_name = label_name(name);
+        if let LabelPlacement::AboveUnlessSmallerThan(min_scale) = placement {
+            if marker.scale < min_scale {
+                return self.remove(name);
+            }
+        }
+
+        let offset = offset(&marker, placement);
+        let height = TEXT_SCALE * scale_of(&marker);
+        let mut label = drawing(
+            &label_name,
+            &marker.header.frame_id,
+            vec![self::text(Point::default(), height, text.to_string())],
+        );
+        label.pose = math::transform_pose(&math::transform_of(&marker.pose), &offset);
+        self.server.insert(label);
+        self.server.attach_follower(name, &label_name, offset);
+        self.server.apply_changes()?;
+        Ok(true)
+    }
+
+    /// Changes the text of an existing label. Only the label is resent, not the marker.
+    /// Returns false if the marker has no label.
+    pub fn set_text(&self, name: &str, text: &str) -> Result<bool> {
+        let Some(mut label) = self.server.get(label_name(name)) else {
+            return Ok(false);
+        };
+        for control in &mut label.controls {
+            for marker in &mut control.markers {
+                marker.text = text.to_string();
+            }
+        }
+        self.server.insert(label);
+        self.server.apply_changes()?;
+        Ok(true)
+    }
+
+    /// Removes the label of a marker. Returns false if it has none.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let label_name = label_name(name);
+        self.server.detach_follower(&label_name);
+        if !self.server.erase(&label_name) {
+            return Ok(false);
+        }
+        self.server.apply_changes()?;
+        Ok(true)
+    }
+}
+
+fn label_name(name: &str) -> String {
+    format!("{}/label", name)
+}
+
+fn scale_of(marker: &InteractiveMarker) -> f64 {
+    if marker.scale > 0.0 {
+        marker.scale as f64
+    } else {
+        1.0
+    }
+}
+
+// Distance from the marker origin to the farthest point of its control markers, at least half
+// the marker scale
+fn extent(marker: &InteractiveMarker) -> f64 {
+    marker
+        .controls
+        .iter()
+        .flat_map(|control| &control.markers)
+        .map(|m| {
+            let p = &m.pose.position;
+            let size = m.scale.x.max(m.scale.y).max(m.scale.z);
+            (p.x * p.x + p.y * p.y + p.z * p.z).sqrt() + size / 2.0
+        })
+        .fold(scale_of(marker) / 2.0, f64::max)
+}
+
+fn offset(marker: &InteractiveMarker, placement: LabelPlacement) -> Pose {
+    let distance = extent(marker) + MARGIN * scale_of(marker);
+    let mut position = Point::default();
+    match placement {
+        LabelPlacement::Above | LabelPlacement::AboveUnlessSmallerThan(_) => position.z = distance,
+        LabelPlacement::Side => position.y = distance,
+    }
+    Pose {
+        position,
+        orientation: identity(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{marker, pose_at, server};
+    use crate::ServerConfig;
+
+    #[test]
+    fn labels_are_placed_clear_of_the_controls() {
+        let marker = InteractiveMarker {
+            scale: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(offset(&marker, LabelPlacement::Above).position.z, 0.6);
+        assert_eq!(offset(&marker, LabelPlacement::Side).position.y, 0.6);
+
+        // A zero scale counts as 1, like in RViz
+        let unscaled = InteractiveMarker::default();
+        assert_eq!(scale_of(&unscaled), 1.0);
+    }
+
+    #[tokio::test]
+    async fn labels_follow_their_marker() {
+        let server = server(ServerConfig::default());
+        server.insert(marker("m"));
+        server.apply_changes().unwrap();
+        let labels = Labels::new(&server);
+        assert!(labels.set("m", "hello", LabelPlacement::Above).unwrap());
+        assert!(!labels
+            .set("unknown", "hello", LabelPlacement::Above)
+            .unwrap());
+
+        server.set_pose("m", pose_at(1.0), None);
+        server.apply_changes().unwrap();
+        let label = server.get("m/label").unwrap();
+        assert_eq!(label.pose.position.x, 1.0);
+        assert!(label.pose.position.z > 0.0);
+
+        assert!(labels.set_text("m", "bye").unwrap());
+        assert_eq!(
+            server.get("m/label").unwrap().controls[0].markers[0].text,
+            "bye"
+        );
+        assert!(labels.remove("m").unwrap());
+        assert!(!labels.set_text("m", "again").unwrap());
+    }
+
+    #[tokio::test]
+    async fn small_markers_hide_their_label() {
+        let server = server(ServerConfig::default());
+        server.insert(marker("m"));
+        server.apply_changes().unwrap();
+        let labels = Labels::new(&server);
+        labels.set("m", "hello", LabelPlacement::Above).unwrap();
+
+        let placement = LabelPlacement::AboveUnlessSmallerThan(f32::MAX);
+        labels.set("m", "hello", placement).unwrap();
+        assert!(server.get("m/label").is_none());
+    }
+}
@@ -0,0 +1,316 @@
+use super::{color, drawing, lines, sphere_handle, text};
+use crate::{sync, InteractiveMarkerServer, Result};
+use r2r::geometry_msgs::msg::Point;
+use r2r::std_msgs::msg::Float64;
+use r2r::visualization_msgs::msg::InteractiveMarkerFeedback;
+use r2r::{Publisher, QosProfile};
+use std::sync::Arc;
+
+const HANDLE_DIAMETER: f64 = 0.1;
+const LINE_WIDTH: f64 = 0.02;
+const TEXT_HEIGHT: f64 = 0.1;
+
+fn sub(a: &Point, b: &Point) -> [f64; 3] {
+    [a.x - b.x, a.y - b.y, a.z - b.z]
+}
+
+fn norm(v: [f64; 3]) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+fn above(point: &Point) -> Point {
+    Point {
+        z: point.z + TEXT_HEIGHT,
+        ..point.clone()
+    }
+}
+
+// What a measurement shows for the current handle positions
+struct Reading {
+    value: f64,
+    // Pairs of points connected by lines
+    lines: Vec<Point>,
+    label_position: Point,
+    label: String,
+}
+
+// Draggable handles with derived lines and a label. Moving a handle redraws the lines and
+// label, and the handle pose and the drawing are published together in one apply.
+#[derive(Clone)]
+struct Measurement<const N: usize> {
+    server: InteractiveMarkerServer,
+    name: String,
+    frame_id: String,
+    handles: [&'static str; N],
+    points: Arc<sync::Mutex<[Point; N]>>,
+    measure: fn(&[Point; N]) -> Reading,
+    publisher: Publisher<Float64>,
+}
+
+impl<const N: usize> Measurement<N> {
+    fn new(
+        server: &InteractiveMarkerServer,
+        name: &str,
+        frame_id: &str,
+        handles: [&'static str; N],
+        points: [Point; N],
+        measure: fn(&[Point; N]) -> Reading,
+        topic: &str,
+    ) -> Result<Self> {
+        let publisher = server
+            .node
+            .lock()
+            .unwrap()
+            .create_publisher::<Float64>(topic, QosProfile::default())?;
+        let measurement = Self {
+            server: server.clone(),
+            name: name.to_string(),
+            frame_id: frame_id.to_string(),
+            handles,
+            points: Arc::new(sync::Mutex::new(points.clone())),
+            measure,
+            publisher,
+        };
+
+        for (index, position) in points.into_iter().enumerate() {
+            let handle_name = measurement.handle_name(index);
+            server.insert(sphere_handle(
+                &handle_name,
+                frame_id,
+                position,
+                HANDLE_DIAMETER,
+                color(0.2, 0.6, 1.0, 1.0),
+            ));
+            let measurement_clone = measurement.clone();
+            server.set_callback(
+                &handle_name,
+                Some(Arc::new(move |feedback: InteractiveMarkerFeedback| {
+                    measurement_clone.move_handle(index, feedback.pose.position);
+                })),
+                InteractiveMarkerFeedback::POSE_UPDATE as u8,
+            );
+        }
+        measurement.redraw()?;
+        Ok(measurement)
+    }
+
+    fn value(&self) -> f64 {
+        (self.measure)(&self.points.lock()).value
+    }
+
+    fn remove(&self) -> Result<()> {
+        for index in 0..N {
+            self.server.erase(self.handle_name(index));
+        }
+        self.server.erase(self.label_name());
+        self.server.apply_changes()?;
+        Ok(())
+    }
+
+    fn handle_name(&self, index: usize) -> String {
+        format!("{}/{}", self.name, self.handles[index])
+    }
+
+    fn label_name(&self) -> String {
+        format!("{}/label", self.name)
+    }
+
+    fn move_handle(&self, index: usize, position: Point) {
+        self.points.lock()[index] = position;
+        self.server.report("Applying changes", self.redraw());
+    }
+
+    // Redraws the lines and label and applies them together with the pending handle pose
+    fn redraw(&self) -> Result<()> {
+        let reading = (self.measure)(&self.points.lock());
+        self.server.insert(drawing(
+            &self.label_name(),
+            &self.frame_id,
+            vec![
+                lines(reading.lines, LINE_WIDTH, color(1.0, 1.0, 0.0, 1.0)),
+                text(reading.label_position, TEXT_HEIGHT, reading.label),
+            ],
+        ));
+        self.server.apply_changes()?;
+
+        if let Err(e) = self.publisher.publish(&Float64 { data: reading.value }) {
+            self.server.logger.warn(format_args!(
+                "Failed to publish the measurement '{}': {}",
+                self.name, e
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn measure_distance([start, end]: &[Point; 2]) -> Reading {
+    let distance = norm(sub(end, start));
+    Reading {
+        value: distance,
+        lines: vec![start.clone(), end.clone()],
+        label_position: above(&Point {
+            x: (start.x + end.x) / 2.0,
+            y: (start.y + end.y) / 2.0,
+            z: (start.z + end.z) / 2.0,
+        }),
+        label: format!("{:.3} m", distance),
+    }
+}
+
+fn measure_angle([vertex, a, b]: &[Point; 3]) -> Reading {
+    let (u, v) = (sub(a, vertex), sub(b, vertex));
+    let cross = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let dot = u[0] * v[0] + u[1] * v[1] + u[2] * v[2];
+    // atan2 stays accurate for angles near 0 and pi, unlike acos of the normalized dot product
+    let angle = norm(cross).atan2(dot);
+    Reading {
+        value: angle,
+        lines: vec![vertex.clone(), a.clone(), vertex.clone(), b.clone()],
+        label_position: above(vertex),
+        label: format!("{:.1}°", angle.to_degrees()),
+    }
+}
+
+/// Measures the distance between two draggable spheres. The spheres are connected by a line
+/// labeled with the live distance, which is also published as a `Float64` in meters.
+#[derive(Clone)]
+pub struct DistanceMeasurement(Measurement<2>);
+
+impl DistanceMeasurement {
+    /// Adds the markers `<name>/start`, `<name>/end` and `<name>/label` to the server and
+    /// publishes the distance on `topic`.
+    pub fn new(
+        server: &InteractiveMarkerServer,
+        name: &str,
+        frame_id: &str,
+        start: Point,
+        end: Point,
+        topic: &str,
+    ) -> Result<Self> {
+        Measurement::new(
+            server,
+            name,
+            frame_id,
+            ["start", "end"],
+            [start, end],
+            measure_distance,
+            topic,
+        )
+        .map(Self)
+    }
+
+    pub fn distance(&self) -> f64 {
+        self.0.value()
+    }
+
+    /// Erases the markers of the measurement.
+    pub fn remove(&self) -> Result<()> {
+        self.0.remove()
+    }
+}
+
+/// Measures the angle between two rays from a common vertex, all three draggable spheres. The
+/// rays are drawn as lines, labeled with the live angle in degrees, and the angle is published
+/// as a `Float64` in radians.
+#[derive(Clone)]
+pub struct AngleMeasurement(Measurement<3>);
+
+impl AngleMeasurement {
+    /// Adds the markers `<name>/vertex`, `<name>/a`, `<name>/b` and `<name>/label` to the server
+    /// and publishes the angle on `topic`.
+    pub fn new(
+        server: &InteractiveMarkerServer,
+        name: &str,
+        frame_id: &str,
+        vertex: Point,
+        a: Point,
+        b: Point,
+        topic: &str,
+    ) -> Result<Self> {
+        Measurement::new(
+            server,
+            name,
+            frame_id,
+            ["vertex", "a", "b"],
+            [vertex, a, b],
+            measure_angle,
+            topic,
+        )
+        .map(Self)
+    }
+
+    /// The angle in radians, between 0 and pi.
+    pub fn angle(&self) -> f64 {
+        self.0.value()
+    }
+
+    /// Erases the markers of the measurement.
+    pub fn remove(&self) -> Result<()> {
+        self.0.remove()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drag, pose_at, server};
+    use crate::ServerConfig;
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    fn point(x: f64, y: f64) -> Point {
+        Point { x, y, z: 0.0 }
+    }
+
+    #[test]
+    fn angles_cover_zero_to_pi() {
+        let right = measure_angle(&[point(0.0, 0.0), point(1.0, 0.0), point(0.0, 2.0)]);
+        assert!((right.value - FRAC_PI_2).abs() < 1e-12);
+        assert_eq!(right.label, "90.0°");
+
+        let straight = measure_angle(&[point(0.0, 0.0), point(1.0, 0.0), point(-1.0, 0.0)]);
+        assert_eq!(straight.value, PI);
+        let none = measure_angle(&[point(0.0, 0.0), point(1.0, 0.0), point(2.0, 0.0)]);
+        assert_eq!(none.value, 0.0);
+    }
+
+    #[test]
+    fn distance_is_labeled_between_the_points() {
+        let reading = measure_distance(&[point(0.0, 0.0), point(3.0, 4.0)]);
+        assert_eq!(reading.value, 5.0);
+        assert_eq!(reading.label, "5.000 m");
+        assert_eq!(
+            reading.label_position,
+            Point {
+                x: 1.5,
+                y: 2.0,
+                z: TEXT_HEIGHT
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn dragging_a_handle_updates_the_distance() {
+        let server = server(ServerConfig::default());
+        let measurement = DistanceMeasurement::new(
+            &server,
+            "ruler",
+            "map",
+            point(0.0, 0.0),
+            point(1.0, 0.0),
+            "distance",
+        )
+        .unwrap();
+        assert!(server.get("ruler/label").is_some());
+
+        server.handle_feedback(drag("ruler/end", pose_at(2.5)));
+        assert_eq!(measurement.distance(), 2.5);
+
+        measurement.remove().unwrap();
+        assert!(server.get("ruler/start").is_none());
+        assert!(server.get("ruler/label").is_none());
+    }
+}
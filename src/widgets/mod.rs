@@ -0,0 +1,174 @@
+//! Ready-made components built from interactive markers. Each widget owns a set of markers in an
+//! `InteractiveMarkerServer` and publishes what the user edits with them.
+
+use crate::helpers::Axis;
+use r2r::geometry_msgs::msg::{Point, Point32, Polygon, Pose, Quaternion, Vector3};
+use r2r::std_msgs::msg::ColorRGBA;
+use r2r::visualization_msgs::msg::{InteractiveMarker, InteractiveMarkerControl, Marker};
+
+mod clicks;
+mod frames;
+mod labels;
+mod measure;
+mod polygon;
+mod selection;
+mod teleop;
+mod waypoints;
+mod zones;
+
+pub use clicks::ClickedPoints;
+pub use frames::{Frame, FrameEditor};
+pub use labels::{LabelPlacement, Labels};
+pub use measure::{AngleMeasurement, DistanceMeasurement};
+pub use polygon::PolygonEditor;
+pub use selection::Selection;
+pub use teleop::Teleop;
+pub use waypoints::WaypointEditor;
+pub use zones::{KeepOutZones, Zone};
+
+pub(crate) fn color(r: f32, g: f32, b: f32, a: f32) -> ColorRGBA {
+    ColorRGBA { r, g, b, a }
+}
+
+pub(crate) fn identity() -> Quaternion {
+    Quaternion {
+        w: 1.0,
+        ..Default::default()
+    }
+}
+
+// A sphere that can be dragged in the XY plane of its frame
+pub(crate) fn sphere_handle(
+    name: &str,
+    frame_id: &str,
+    position: Point,
+    diameter: f64,
+    color: ColorRGBA,
+) -> InteractiveMarker {
+    let mut sphere = Marker {
+        type_: Marker::SPHERE as i32,
+        color,
+        ..Default::default()
+    };
+    sphere.pose.orientation = identity();
+    sphere.scale.x = diameter;
+    sphere.scale.y = diameter;
+    sphere.scale.z = diameter;
+
+    let control = InteractiveMarkerControl {
+        name: "move_plane".to_string(),
+        // The plane normal is the control's x axis, turned onto z to get the XY plane
+        orientation: Axis::Z.orientation(),
+        interaction_mode: InteractiveMarkerControl::MOVE_PLANE as u8,
+        always_visible: true,
+        markers: vec![sphere],
+        ..Default::default()
+    };
+
+    let mut marker = InteractiveMarker {
+        name: name.to_string(),
+        pose: Pose {
+            position,
+            orientation: identity(),
+        },
+        scale: diameter as f32,
+        controls: vec![control],
+        ..Default::default()
+    };
+    marker.header.frame_id = frame_id.to_string();
+    marker
+}
+
+// A non-interactive marker at the origin of its frame showing `markers`, for geometry derived
+// from other markers
+pub(crate) fn drawing(name: &str, frame_id: &str, markers: Vec<Marker>) -> InteractiveMarker {
+    let control = InteractiveMarkerControl {
+        interaction_mode: InteractiveMarkerControl::NONE as u8,
+        always_visible: true,
+        markers,
+        ..Default::default()
+    };
+    let mut marker = InteractiveMarker {
+        name: name.to_string(),
+        pose: Pose {
+            orientation: identity(),
+            ..Default::default()
+        },
+        scale: 1.0,
+        controls: vec![control],
+        ..Default::default()
+    };
+    marker.header.frame_id = frame_id.to_string();
+    marker
+}
+
+pub(crate) fn lines(points: Vec<Point>, width: f64, color: ColorRGBA) -> Marker {
+    Marker {
+        type_: Marker::LINE_LIST as i32,
+        pose: Pose {
+            orientation: identity(),
+            ..Default::default()
+        },
+        scale: Vector3 {
+            x: width,
+            ..Default::default()
+        },
+        color,
+        points,
+        ..Default::default()
+    }
+}
+
+pub(crate) fn text(position: Point, height: f64, text: String) -> Marker {
+    Marker {
+        type_: Marker::TEXT_VIEW_FACING as i32,
+        pose: Pose {
+            position,
+            orientation: identity(),
+        },
+        scale: Vector3 {
+            z: height,
+            ..Default::default()
+        },
+        color: color(1.0, 1.0, 1.0, 1.0),
+        text,
+        ..Default::default()
+    }
+}
+
+// A polygon message, narrowing the vertices to single precision
+pub(crate) fn polygon(vertices: &[Point]) -> Polygon {
+    Polygon {
+        points: vertices
+            .iter()
+            .map(|vertex| Point32 {
+                x: vertex.x as f32,
+                y: vertex.y as f32,
+                z: vertex.z as f32,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_handle_moves_in_the_plane() {
+        let position = Point {
+            x: 1.0,
+            ..Default::default()
+        };
+        let handle = sphere_handle("h", "map", position.clone(), 0.2, color(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(handle.header.frame_id, "map");
+        assert_eq!(handle.pose.position, position);
+        assert_eq!(handle.scale, 0.2);
+        let control = &handle.controls[0];
+        assert_eq!(
+            control.interaction_mode,
+            InteractiveMarkerControl::MOVE_PLANE as u8
+        );
+        assert_eq!(control.markers[0].scale.x, 0.2);
+    }
+}
@@ -0,0 +1,347 @@
+use super::{color, drawing, identity, polygon, sphere_handle};
+use crate::{events, sync, FeedbackEvent, InteractiveMarkerServer, Result};
+use futures::Stream;
+use r2r::geometry_msgs::msg::{Point, Polygon, PolygonStamped};
+use r2r::std_msgs::msg::Header;
+use r2r::visualization_msgs::msg::{Marker, MenuEntry};
+use r2r::{Publisher, QosProfile};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+const VERTEX_DIAMETER: f64 = 0.15;
+const OUTLINE_WIDTH: f64 = 0.03;
+// Called with the vertices of every committed polygon
+pub(crate) type CommitHook = Arc<dyn Fn(&[Point]) + Send + Sync>;
+
+// A polygon needs three vertices, so the menu does not delete below that
+const MIN_VERTICES: usize = 3;
+
+const INSERT_VERTEX: u32 = 1;
+const DELETE_VERTEX: u32 = 2;
+
+/// Edits a polygon in the XY plane of a frame, e.g. a keep-out zone or a region of interest.
+/// Every vertex is a sphere marker `<name>/<index>` that is dragged in the plane, and has a menu
+/// to insert a vertex halfway to the next one or to delete it. The outline is drawn in the
+/// marker `<name>/outline`. Whenever a vertex is released or the vertices change, the polygon is
+/// published as a `PolygonStamped` and sent to the streams of `updates`.
+#[derive(Clone)]
+pub struct PolygonEditor {
+    server: InteractiveMarkerServer,
+    name: String,
+    frame_id: String,
+    vertices: Arc<sync::Mutex<Vec<Point>>>,
+    publisher: Publisher<PolygonStamped>,
+    updates: broadcast::Sender<Polygon>,
+    on_commit: Option<CommitHook>,
+}
+
+impl PolygonEditor {
+    pub fn new(
+        server: &InteractiveMarkerServer,
+        name: &str,
+        frame_id: &str,
+        vertices: impl IntoIterator<Item = Point>,
+        topic: &str,
+    ) -> Result<Self> {
+        let publisher = server
+            .node
+            .lock()
+            .unwrap()
+            .create_publisher::<PolygonStamped>(topic, QosProfile::default())?;
+        Self::with_publisher(
+            server,
+            name,
+            frame_id,
+            vertices.into_iter().collect(),
+            publisher,
+            None,
+        )
+    }
+
+    // An editor on an existing publisher, so that KeepOutZones shares one topic for all zones
+    pub(crate) fn with_publisher(
+        server: &InteractiveMarkerServer,
+        name: &str,
+        frame_id: &str,
+        vertices: Vec<Point>,
+        publisher: Publisher<PolygonStamped>,
+        on_commit: Option<CommitHook>,
+    ) -> Result<Self> {
+        let editor = Self {
+            server: server.clone(),
+            name: name.to_string(),
+            frame_id: frame_id.to_string(),
+            vertices: Arc::new(sync::Mutex::new(Vec::new())),
+            publisher,
+            updates: events::channel(),
+            on_commit,
+        };
+        editor.set_vertices(vertices)?;
+        Ok(editor)
+    }
+
+    pub(crate) fn frame_id(&self) -> &str {
+        &self.frame_id
+    }
+
+    pub fn vertices(&self) -> Vec<Point> {
+        self.vertices.lock().clone()
+    }
+
+    pub fn polygon(&self) -> Polygon {
+        polygon(&self.vertices.lock())
+    }
+
+    /// Stream of the polygon after every committed change. Updates missed by a lagging stream
+    /// are skipped, the next one carries the complete polygon again.
+    pub fn updates(&self) -> impl Stream<Item = Polygon> + Send + Unpin {
+        events::stream(self.updates.subscribe())
+    }
+
+    /// Replaces all vertices.
+    pub fn set_vertices(&self, vertices: Vec<Point>) -> Result<()> {
+        self.edit(|current| {
+            *current = vertices;
+            true
+        })
+    }
+
+    /// Inserts a vertex at `index`, or at the end if `index` is past it.
+    pub fn insert_vertex(&self, index: usize, vertex: Point) -> Result<()> {
+        self.edit(|vertices| {
+            vertices.insert(index.min(vertices.len()), vertex);
+            true
+        })
+    }
+
+    /// Removes the vertex at `index`. Returns false if there is no such vertex.
+    pub fn remove_vertex(&self, index: usize) -> Result<bool> {
+        let mut removed = false;
+        self.edit(|vertices| {
+            removed = index < vertices.len();
+            if removed {
+                vertices.remove(index);
+            }
+            removed
+        })?;
+        Ok(removed)
+    }
+
+    /// Removes the vertex markers and the outline from the server.
+    pub fn clear(&self) -> Result<()> {
+        self.set_vertices(Vec::new())?;
+        self.server.erase(self.outline_name());
+        self.server.apply_changes()?;
+        Ok(())
+    }
+
+    fn vertex_name(&self, index: usize) -> String {
+        format!("{}/{}", self.name, index)
+    }
+
+    fn outline_name(&self) -> String {
+        format!("{}/outline", self.name)
+    }
+
+    // Changes the vertices and, if `change` returns true, rebuilds all markers and commits the
+    // polygon. Vertex markers are named by index, so inserts and deletes rename them.
+    fn edit(&self, change: impl FnOnce(&mut Vec<Point>) -> bool) -> Result<()> {
+        let (old_len, vertices) = {
+            let mut vertices = self.vertices.lock();
+            let old_len = vertices.len();
+            if !change(&mut vertices) {
+                return Ok(());
+            }
+            (old_len, vertices.clone())
+        };
+
+        for index in vertices.len()..old_len {
+            self.server.erase(self.vertex_name(index));
+        }
+        for (index, vertex) in vertices.iter().enumerate() {
+            self.insert_vertex_marker(index, vertex.clone());
+        }
+        self.insert_outline(&vertices);
+        self.server.apply_changes()?;
+        self.commit(&vertices);
+        Ok(())
+    }
+
+    fn insert_vertex_marker(&self, index: usize, vertex: Point) {
+        let name = self.vertex_name(index);
+        let mut marker = sphere_handle(
+            &name,
+            &self.frame_id,
+            vertex,
+            VERTEX_DIAMETER,
+            color(0.2, 0.4, 1.0, 1.0),
+        );
+        marker.menu_entries = [
+            (INSERT_VERTEX, "Insert vertex"),
+            (DELETE_VERTEX, "Delete vertex"),
+        ]
+        .iter()
+        .map(|&(id, title)| MenuEntry {
+            id,
+            title: title.to_string(),
+            command_type: MenuEntry::FEEDBACK as u8,
+            ..Default::default()
+        })
+        .collect();
+        self.server.insert(marker);
+
+        let editor = self.clone();
+        self.server
+            .set_event_callback(&name, move |event, feedback| match event {
+                FeedbackEvent::PoseUpdate { pose, .. } => {
+                    editor.move_vertex(index, pose.position, false)
+                }
+                FeedbackEvent::MouseUp => {
+                    editor.move_vertex(index, feedback.pose.position.clone(), true)
+                }
+                FeedbackEvent::MenuSelect { entry_id } => {
+                    editor.server.report("Applying changes", editor.menu_select(index, entry_id))
+                }
+                _ => {}
+            });
+    }
+
+    fn insert_outline(&self, vertices: &[Point]) {
+        let mut outline = Marker {
+            type_: Marker::LINE_STRIP as i32,
+            color: color(0.2, 0.4, 1.0, 1.0),
+            points: vertices.to_vec(),
+            ..Default::default()
+        };
+        outline.pose.orientation = identity();
+        outline.scale.x = OUTLINE_WIDTH;
+        if let Some(first) = vertices.first() {
+            outline.points.push(first.clone());
+        }
+        self.server
+            .insert(drawing(&self.outline_name(), &self.frame_id, vec![outline]));
+    }
+
+    // Redraws the outline while a vertex is dragged, and commits the polygon once it is released
+    fn move_vertex(&self, index: usize, position: Point, release: bool) {
+        let vertices = {
+            let mut vertices = self.vertices.lock();
+            let Some(vertex) = vertices.get_mut(index) else {
+                return;
+            };
+            *vertex = position;
+            vertices.clone()
+        };
+        self.insert_outline(&vertices);
+        self.server.report("Applying changes", self.server.apply_changes());
+        if release {
+            self.commit(&vertices);
+        }
+    }
+
+    fn menu_select(&self, index: usize, entry_id: u32) -> Result<()> {
+        self.edit(|vertices| {
+            if index >= vertices.len() {
+                return false;
+            }
+            match entry_id {
+                INSERT_VERTEX => {
+                    let (a, b) = (&vertices[index], &vertices[(index + 1) % vertices.len()]);
+                    let midpoint = Point {
+                        x: (a.x + b.x) / 2.0,
+                        y: (a.y + b.y) / 2.0,
+                        z: (a.z + b.z) / 2.0,
+                    };
+                    vertices.insert(index + 1, midpoint);
+                }
+                DELETE_VERTEX if vertices.len() > MIN_VERTICES => {
+                    vertices.remove(index);
+                }
+                _ => return false,
+            }
+            true
+        })
+    }
+
+    fn commit(&self, vertices: &[Point]) {
+        let polygon = polygon(vertices);
+        let stamped = PolygonStamped {
+            header: Header {
+                frame_id: self.frame_id.clone(),
+                ..Default::default()
+            },
+            polygon: polygon.clone(),
+        };
+        if let Err(e) = self.publisher.publish(&stamped) {
+            self.server.logger.warn(format_args!(
+                "Failed to publish the polygon '{}': {}",
+                self.name, e
+            ));
+        }
+        let _ = self.updates.send(polygon);
+        if let Some(on_commit) = &self.on_commit {
+            on_commit(vertices);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drag, feedback, pose_at, select, server};
+    use crate::ServerConfig;
+    use futures::{FutureExt, StreamExt};
+    use r2r::visualization_msgs::msg::InteractiveMarkerFeedback;
+
+    fn triangle() -> Vec<Point> {
+        let point = |x, y| Point { x, y, z: 0.0 };
+        vec![point(0.0, 0.0), point(2.0, 0.0), point(0.0, 2.0)]
+    }
+
+    #[tokio::test]
+    async fn menus_keep_a_polygon() {
+        let server = server(ServerConfig::default());
+        let editor = PolygonEditor::new(&server, "area", "map", triangle(), "area").unwrap();
+
+        // Deleting would leave two vertices
+        server.handle_feedback(select("area/0", DELETE_VERTEX));
+        assert_eq!(editor.vertices().len(), 3);
+
+        // The last vertex inserts halfway back to the first
+        server.handle_feedback(select("area/2", INSERT_VERTEX));
+        assert_eq!(
+            editor.vertices()[3],
+            Point {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0
+            }
+        );
+        assert!(server.get("area/3").is_some());
+        server.handle_feedback(select("area/3", DELETE_VERTEX));
+        assert_eq!(editor.vertices(), triangle());
+        assert!(server.get("area/3").is_none());
+    }
+
+    #[tokio::test]
+    async fn polygons_are_committed_on_release() {
+        let server = server(ServerConfig::default());
+        let editor = PolygonEditor::new(&server, "area", "map", triangle(), "area").unwrap();
+        let mut updates = editor.updates();
+
+        server.handle_feedback(drag("area/1", pose_at(3.0)));
+        assert_eq!(editor.vertices()[1].x, 3.0);
+        assert!(updates.next().now_or_never().is_none());
+        let outline = server.get("area/outline").unwrap();
+        assert_eq!(outline.controls[0].markers[0].points.len(), 4);
+
+        server.handle_feedback(feedback(
+            "area/1",
+            InteractiveMarkerFeedback::MOUSE_UP as u8,
+            pose_at(3.0),
+        ));
+        let polygon = updates.next().now_or_never().flatten().unwrap();
+        assert_eq!(polygon.points[1].x, 3.0);
+        assert_eq!(polygon, editor.polygon());
+    }
+}
@@ -0,0 +1,300 @@
+use super::{color, identity};
+use crate::{math, sync, InteractiveMarkerServer, Result};
+use r2r::geometry_msgs::msg::Pose;
+use r2r::visualization_msgs::msg::{
+    InteractiveMarkerControl, InteractiveMarkerFeedback, Marker, MenuEntry,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const HIGHLIGHT_CONTROL: &str = "selection_highlight";
+
+// Menu entries added to a member of the selection
+struct Member {
+    group: String,
+    toggle_entry: u32,
+    select_group_entry: u32,
+}
+
+#[derive(Default)]
+struct State {
+    members: HashMap<String, Member>,
+    // Selected markers and the pose they were last moved to
+    selected: HashMap<String, Pose>,
+}
+
+/// A selection of markers. Clicking a member (on a BUTTON control, or through its "Toggle
+/// selection" menu entry) toggles it, and "Select all in group" selects every member of its
+/// group. Dragging a selected marker moves all selected markers along rigidly in the same apply.
+/// Selected markers are highlighted. Takes over the BUTTON_CLICK, MENU_SELECT and POSE_UPDATE
+/// callbacks of its members.
+#[derive(Clone)]
+pub struct Selection {
+    server: InteractiveMarkerServer,
+    state: Arc<sync::Mutex<State>>,
+}
+
+impl Selection {
+    pub fn new(server: &InteractiveMarkerServer) -> Self {
+        Self {
+            server: server.clone(),
+            state: Default::default(),
+        }
+    }
+
+    /// Makes the marker `name` a member of `group`. Returns false if the marker is unknown.
+    pub fn add(&self, name: &str, group: &str) -> Result<bool> {
+        let server = &self.server;
+        let Some(mut marker) = server.get(name) else {
+            return Ok(false);
+        };
+
+        let first_id = marker.menu_entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+        let member = Member {
+            group: group.to_string(),
+            toggle_entry: first_id,
+            select_group_entry: first_id + 1,
+        };
+        for (id, title) in [
+            (member.toggle_entry, "Toggle selection"),
+            (member.select_group_entry, "Select all in group"),
+        ] {
+            marker.menu_entries.push(MenuEntry {
+                id,
+                parent_id: 0,
+                title: title.to_string(),
+                command_type: MenuEntry::FEEDBACK as u8,
+                ..Default::default()
+            });
+        }
+        server.insert(marker);
+        self.state.lock().members.insert(name.to_string(), member);
+
+        let selection = self.clone();
+        let on_feedback: crate::FeedbackCallbackBox = Arc::new(move |feedback| {
+            selection.handle_feedback(feedback);
+        });
+        for event_type in [
+            InteractiveMarkerFeedback::BUTTON_CLICK,
+            InteractiveMarkerFeedback::MENU_SELECT,
+            InteractiveMarkerFeedback::POSE_UPDATE,
+        ] {
+            server.set_callback(name, Some(on_feedback.clone()), event_type as u8);
+        }
+        server.apply_changes()?;
+        Ok(true)
+    }
+
+    /// Names of the selected markers.
+    pub fn selected(&self) -> Vec<String> {
+        self.state.lock().selected.keys().cloned().collect()
+    }
+
+    /// Selects or deselects a member. Returns false if `name` is not a member.
+    pub fn set_selected(&self, name: &str, selected: bool) -> Result<bool> {
+        if !self.state.lock().members.contains_key(name) {
+            return Ok(false);
+        }
+        self.select(name, selected);
+        self.server.apply_changes()?;
+        Ok(true)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        let selected = self.selected();
+        for name in &selected {
+            self.select(name, false);
+        }
+        self.server.apply_changes()?;
+        Ok(())
+    }
+
+    fn handle_feedback(&self, feedback: InteractiveMarkerFeedback) {
+        let name = feedback.marker_name.as_str();
+        if feedback.event_type == InteractiveMarkerFeedback::POSE_UPDATE as u8 {
+            self.drag(name, &feedback.pose);
+            return;
+        }
+
+        let (toggle, group) = {
+            let state = self.state.lock();
+            let Some(member) = state.members.get(name) else {
+                return;
+            };
+            if feedback.event_type == InteractiveMarkerFeedback::BUTTON_CLICK as u8
+                || feedback.menu_entry_id == member.toggle_entry
+            {
+                (!state.selected.contains_key(name), None)
+            } else if feedback.menu_entry_id == member.select_group_entry {
+                (false, Some(member.group.clone()))
+            } else {
+                return;
+            }
+        };
+
+        match group {
+            Some(group) => {
+                let members: Vec<String> = self
+                    .state
+                    .lock()
+                    .members
+                    .iter()
+                    .filter(|(_, member)| member.group == group)
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                for member in members {
+                    self.select(&member, true);
+                }
+            }
+            None => self.select(name, toggle),
+        }
+        self.server.report("Applying changes", self.server.apply_changes());
+    }
+
+    // Moves the other selected markers by the motion of the dragged one since its last pose
+    fn drag(&self, name: &str, pose: &Pose) {
+        let moves: Vec<(String, Pose)> = {
+            let mut state = self.state.lock();
+            let Some(previous) = state.selected.get_mut(name) else {
+                return;
+            };
+            let motion = math::compose(
+                &math::transform_of(pose),
+                &math::inverse(&math::transform_of(previous)),
+            );
+            *previous = pose.clone();
+            state
+                .selected
+                .iter_mut()
+                .filter(|(other, _)| other.as_str() != name)
+                .map(|(other, other_pose)| {
+                    *other_pose = math::transform_pose(&motion, other_pose);
+                    (other.clone(), other_pose.clone())
+                })
+                .collect()
+        };
+        let server = &self.server;
+        for (other, pose) in moves {
+            server.set_pose(other, pose, None);
+        }
+        // The dragged marker's own pose is already pending, so everything goes out together
+        server.report("Applying changes", server.apply_changes());
+    }
+
+    // Updates the selection and the highlight of one marker, without applying
+    fn select(&self, name: &str, selected: bool) {
+        let server = &self.server;
+        let Some(mut marker) = server.get(name) else {
+            return;
+        };
+        {
+            let mut state = self.state.lock();
+            if selected == state.selected.contains_key(name) {
+                return;
+            }
+            if selected {
+                state.selected.insert(name.to_string(), marker.pose.clone());
+            } else {
+                state.selected.remove(name);
+            }
+        }
+
+        marker
+            .controls
+            .retain(|control| control.name != HIGHLIGHT_CONTROL);
+        if selected {
+            marker.controls.push(highlight(marker.scale as f64));
+        }
+        server.insert(marker);
+    }
+}
+
+// A translucent sphere around the marker
+fn highlight(scale: f64) -> InteractiveMarkerControl {
+    let diameter = if scale > 0.0 { scale * 1.2 } else { 1.2 };
+    let mut sphere = Marker {
+        type_: Marker::SPHERE as i32,
+        color: color(1.0, 1.0, 0.0, 0.3),
+        ..Default::default()
+    };
+    sphere.pose.orientation = identity();
+    sphere.scale.x = diameter;
+    sphere.scale.y = diameter;
+    sphere.scale.z = diameter;
+    InteractiveMarkerControl {
+        name: HIGHLIGHT_CONTROL.to_string(),
+        interaction_mode: InteractiveMarkerControl::NONE as u8,
+        always_visible: true,
+        markers: vec![sphere],
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drag, feedback, marker, pose_at, server};
+    use crate::ServerConfig;
+
+    fn sorted(mut names: Vec<String>) -> Vec<String> {
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn clicks_toggle_and_menus_select_groups() {
+        let server = server(ServerConfig::default());
+        let selection = Selection::new(&server);
+        for (name, group) in [("a", "left"), ("b", "left"), ("c", "right")] {
+            server.insert(marker(name));
+            assert!(selection.add(name, group).unwrap());
+        }
+        assert!(!selection.add("unknown", "left").unwrap());
+
+        let click = || {
+            feedback(
+                "c",
+                InteractiveMarkerFeedback::BUTTON_CLICK as u8,
+                Pose::default(),
+            )
+        };
+        server.handle_feedback(click());
+        assert_eq!(selection.selected(), ["c"]);
+        assert!(server
+            .get("c")
+            .unwrap()
+            .controls
+            .iter()
+            .any(|control| control.name == HIGHLIGHT_CONTROL));
+        server.handle_feedback(click());
+        assert!(selection.selected().is_empty());
+
+        let mut select_group = feedback(
+            "a",
+            InteractiveMarkerFeedback::MENU_SELECT as u8,
+            Pose::default(),
+        );
+        select_group.menu_entry_id = server.get("a").unwrap().menu_entries[1].id;
+        server.handle_feedback(select_group);
+        assert_eq!(sorted(selection.selected()), ["a", "b"]);
+
+        selection.clear().unwrap();
+        assert!(selection.selected().is_empty());
+    }
+
+    #[tokio::test]
+    async fn selected_markers_move_together() {
+        let server = server(ServerConfig::default());
+        let selection = Selection::new(&server);
+        for name in ["a", "b", "c"] {
+            server.insert(marker(name));
+            selection.add(name, "all").unwrap();
+        }
+        selection.set_selected("a", true).unwrap();
+        selection.set_selected("b", true).unwrap();
+
+        server.handle_feedback(drag("a", pose_at(1.0)));
+        assert_eq!(server.get("b").unwrap().pose.position.x, 1.0);
+        assert_eq!(server.get("c").unwrap().pose.position.x, 0.0);
+    }
+}
@@ -0,0 +1,247 @@
+use super::{color, identity};
+use crate::helpers::{make_plane_control, make_rotate_controls, Axis};
+use crate::{math, sync, FeedbackEvent, InteractiveMarkerServer, Result};
+use r2r::geometry_msgs::msg::{Pose, Twist, Vector3};
+use r2r::visualization_msgs::msg::{InteractiveMarker, InteractiveMarkerControl, Marker};
+use r2r::{Publisher, QosProfile};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+const TELEOP_SCALE: f32 = 0.5;
+
+struct Gains {
+    linear: f64,
+    angular: f64,
+}
+
+/// Drives a robot with a marker, like the interactive marker teleop of RViz. While the marker is
+/// dragged away from its rest pose, the displacement is published as a `Twist` on a topic at a
+/// fixed rate: the offset along the axes of the rest pose times the linear gain as linear
+/// velocity, and the rotation from the rest orientation times the angular gain as angular
+/// velocity. On release, the marker springs back to the rest pose and a zero twist is published.
+#[derive(Clone)]
+pub struct Teleop {
+    server: InteractiveMarkerServer,
+    name: String,
+    rest_pose: Pose,
+    gains: Arc<sync::Mutex<Gains>>,
+    dragging: Arc<AtomicBool>,
+    publisher: Publisher<Twist>,
+    task: Arc<JoinHandle<()>>,
+}
+
+impl Teleop {
+    /// Adds the marker `name` at `rest_pose` in `frame_id`, usually the robot's base frame, and
+    /// publishes twists on `topic` `rate` times per second while it is dragged. Both gains start
+    /// at 1. Fails with `Error::InvalidRate` unless `rate` is positive and finite.
+    pub fn new(
+        server: &InteractiveMarkerServer,
+        name: &str,
+        frame_id: &str,
+        rest_pose: Pose,
+        topic: &str,
+        rate: f64,
+    ) -> Result<Self> {
+        let period = crate::rate_period(rate)?;
+        let publisher = server
+            .node
+            .lock()
+            .unwrap()
+            .create_publisher::<Twist>(topic, QosProfile::default())?;
+        let gains = Arc::new(sync::Mutex::new(Gains {
+            linear: 1.0,
+            angular: 1.0,
+        }));
+        let dragging = Arc::new(AtomicBool::new(false));
+
+        let task = {
+            let runtime = server.spawner.runtime()?;
+            let server = server.clone();
+            let name = name.to_string();
+            let rest_pose = rest_pose.clone();
+            let gains = Arc::clone(&gains);
+            let dragging = Arc::clone(&dragging);
+            let publisher = publisher.clone();
+            runtime.spawn(async move {
+                let mut interval = tokio::time::interval(period);
+                loop {
+                    interval.tick().await;
+                    if !dragging.load(Ordering::SeqCst) {
+                        continue;
+                    }
+                    let Some(marker) = server.get(&name) else {
+                        continue;
+                    };
+                    let twist = twist(&rest_pose, &marker.pose, &gains.lock());
+                    if let Err(e) = publisher.publish(&twist) {
+                        server.logger.warn(format_args!(
+                            "Failed to publish the teleop twist of '{}': {}",
+                            name, e
+                        ));
+                    }
+                }
+            })
+        };
+
+        let teleop = Self {
+            server: server.clone(),
+            name: name.to_string(),
+            rest_pose,
+            gains,
+            dragging,
+            publisher,
+            task: Arc::new(task),
+        };
+        teleop.insert_marker(frame_id);
+        server.apply_changes()?;
+        Ok(teleop)
+    }
+
+    /// Sets the gains turning the displacement into a twist, in 1/s for both.
+    pub fn set_gains(&self, linear: f64, angular: f64) {
+        *self.gains.lock() = Gains { linear, angular };
+    }
+
+    /// Stops publishing and removes the marker.
+    pub fn remove(&self) -> Result<()> {
+        self.task.abort();
+        self.server.erase(&self.name);
+        self.server.apply_changes()?;
+        Ok(())
+    }
+
+    fn insert_marker(&self, frame_id: &str) {
+        let mut disc = Marker {
+            type_: Marker::CYLINDER as i32,
+            color: color(0.2, 0.6, 1.0, 0.6),
+            ..Default::default()
+        };
+        disc.pose.orientation = identity();
+        disc.scale = Vector3 {
+            x: TELEOP_SCALE as f64,
+            y: TELEOP_SCALE as f64,
+            z: 0.02,
+        };
+        let mut controls = vec![InteractiveMarkerControl {
+            markers: vec![disc],
+            always_visible: true,
+            ..make_plane_control(Axis::Z, false)
+        }];
+        controls.extend(make_rotate_controls(false));
+
+        let mut marker = InteractiveMarker {
+            name: self.name.clone(),
+            pose: self.rest_pose.clone(),
+            scale: TELEOP_SCALE,
+            controls,
+            ..Default::default()
+        };
+        marker.header.frame_id = frame_id.to_string();
+        self.server.insert(marker);
+
+        let teleop = self.clone();
+        self.server
+            .set_event_callback(&self.name, move |event, _| match event {
+                FeedbackEvent::MouseDown => teleop.dragging.store(true, Ordering::SeqCst),
+                FeedbackEvent::MouseUp => teleop.release(),
+                _ => {}
+            });
+    }
+
+    fn release(&self) {
+        self.dragging.store(false, Ordering::SeqCst);
+        if self
+            .server
+            .set_pose(&self.name, self.rest_pose.clone(), None)
+        {
+            self.server.report("Applying changes", self.server.apply_changes());
+        }
+        if let Err(e) = self.publisher.publish(&Twist::default()) {
+            self.server.logger.warn(format_args!(
+                "Failed to stop the teleop of '{}': {}",
+                self.name, e
+            ));
+        }
+    }
+}
+
+// The offset of `pose` from `rest`, both in the marker's frame, scaled into a twist
+fn twist(rest: &Pose, pose: &Pose, gains: &Gains) -> Twist {
+    let delta = [
+        pose.position.x - rest.position.x,
+        pose.position.y - rest.position.y,
+        pose.position.z - rest.position.z,
+    ];
+    let linear = math::rotate(&math::conjugate(&rest.orientation), delta);
+    let angular = math::body_twist(rest, pose, 1.0).angular;
+    Twist {
+        linear: Vector3 {
+            x: linear[0] * gains.linear,
+            y: linear[1] * gains.linear,
+            z: linear[2] * gains.linear,
+        },
+        angular: Vector3 {
+            x: angular.x * gains.angular,
+            y: angular.y * gains.angular,
+            z: angular.z * gains.angular,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drag, feedback, pose_at, server};
+    use crate::ServerConfig;
+    use r2r::visualization_msgs::msg::InteractiveMarkerFeedback;
+    use std::f64::consts::FRAC_PI_2;
+
+    #[test]
+    fn displacement_is_taken_in_the_rest_frame() {
+        let rest = Pose {
+            orientation: math::from_rpy([0.0, 0.0, FRAC_PI_2]),
+            ..Default::default()
+        };
+        let mut pose = rest.clone();
+        pose.position.y = 0.5;
+        pose.orientation = math::from_rpy([0.0, 0.0, FRAC_PI_2 + 0.1]);
+        let gains = Gains {
+            linear: 2.0,
+            angular: 3.0,
+        };
+
+        let twist = twist(&rest, &pose, &gains);
+        assert!((twist.linear.x - 1.0).abs() < 1e-9);
+        assert!(twist.linear.y.abs() < 1e-9);
+        assert!((twist.angular.z - 0.3).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn released_marker_springs_back() {
+        let server = server(ServerConfig::default());
+        let rest = math::identity_pose();
+        let teleop = Teleop::new(
+            &server,
+            "teleop",
+            "base_link",
+            rest.clone(),
+            "cmd_vel",
+            10.0,
+        )
+        .unwrap();
+
+        let event = |event_type| feedback("teleop", event_type, pose_at(0.5));
+        server.handle_feedback(event(InteractiveMarkerFeedback::MOUSE_DOWN as u8));
+        server.handle_feedback(drag("teleop", pose_at(0.5)));
+        server.apply_changes().unwrap();
+        assert!(teleop.dragging.load(Ordering::SeqCst));
+
+        server.handle_feedback(event(InteractiveMarkerFeedback::MOUSE_UP as u8));
+        assert!(!teleop.dragging.load(Ordering::SeqCst));
+        assert_eq!(server.get("teleop").unwrap().pose, rest);
+
+        teleop.remove().unwrap();
+        assert!(server.get("teleop").is_none());
+    }
+}
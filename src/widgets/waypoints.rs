@@ -0,0 +1,345 @@
+use super::{color, drawing, identity};
+use crate::helpers::make_6dof_controls;
+use crate::{events, sync, FeedbackEvent, InteractiveMarkerServer, Result};
+use futures::Stream;
+use r2r::geometry_msgs::msg::{Point, Pose, Vector3};
+use r2r::visualization_msgs::msg::{
+    InteractiveMarker, InteractiveMarkerControl, Marker, MenuEntry,
+};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+const WAYPOINT_SCALE: f32 = 0.3;
+const WAYPOINT_DIAMETER: f64 = 0.1;
+const PATH_WIDTH: f64 = 0.03;
+// How far a waypoint inserted before the first or after the last one is placed from it
+const EXTEND_DISTANCE: f64 = 0.5;
+
+const INSERT_BEFORE: u32 = 1;
+const INSERT_AFTER: u32 = 2;
+const DELETE: u32 = 3;
+const MOVE_EARLIER: u32 = 4;
+const MOVE_LATER: u32 = 5;
+
+const MENU: [(u32, &str); 5] = [
+    (INSERT_BEFORE, "Insert before"),
+    (INSERT_AFTER, "Insert after"),
+    (DELETE, "Delete"),
+    (MOVE_EARLIER, "Move earlier"),
+    (MOVE_LATER, "Move later"),
+];
+
+/// Edits a path of 6-DOF waypoints. Every waypoint is a marker `<name>/<index>` that can be
+/// dragged and rotated, and has a menu to insert a waypoint before or after it, delete it or
+/// move it along the path. The waypoints are connected by a line in the marker `<name>/path`.
+/// Every change to the path is sent to the streams of `updates`.
+#[derive(Clone)]
+pub struct WaypointEditor {
+    server: InteractiveMarkerServer,
+    name: String,
+    frame_id: String,
+    waypoints: Arc<sync::Mutex<Vec<Pose>>>,
+    updates: broadcast::Sender<Vec<Pose>>,
+}
+
+impl WaypointEditor {
+    pub fn new(
+        server: &InteractiveMarkerServer,
+        name: &str,
+        frame_id: &str,
+        waypoints: impl IntoIterator<Item = Pose>,
+    ) -> Result<Self> {
+        let editor = Self {
+            server: server.clone(),
+            name: name.to_string(),
+            frame_id: frame_id.to_string(),
+            waypoints: Arc::new(sync::Mutex::new(Vec::new())),
+            updates: events::channel(),
+        };
+        editor.set_waypoints(waypoints.into_iter().collect())?;
+        Ok(editor)
+    }
+
+    /// The waypoints in path order.
+    pub fn waypoints(&self) -> Vec<Pose> {
+        self.waypoints.lock().clone()
+    }
+
+    /// Stream of the waypoints after every change, whether made by dragging, from the menus or
+    /// through this API. Updates missed by a lagging stream are skipped, the next one carries
+    /// the complete path again.
+    pub fn updates(&self) -> impl Stream<Item = Vec<Pose>> + Send + Unpin {
+        events::stream(self.updates.subscribe())
+    }
+
+    /// Replaces all waypoints.
+    pub fn set_waypoints(&self, waypoints: Vec<Pose>) -> Result<()> {
+        self.edit(|current| {
+            *current = waypoints;
+            true
+        })
+    }
+
+    /// Appends a waypoint at the end of the path.
+    pub fn push(&self, pose: Pose) -> Result<()> {
+        self.edit(|waypoints| {
+            waypoints.push(pose);
+            true
+        })
+    }
+
+    /// Inserts a waypoint at `index`, or at the end if `index` is past it.
+    pub fn insert(&self, index: usize, pose: Pose) -> Result<()> {
+        self.edit(|waypoints| {
+            waypoints.insert(index.min(waypoints.len()), pose);
+            true
+        })
+    }
+
+    /// Removes the waypoint at `index`. Returns false if there is no such waypoint.
+    pub fn remove(&self, index: usize) -> Result<bool> {
+        let mut removed = false;
+        self.edit(|waypoints| {
+            removed = index < waypoints.len();
+            if removed {
+                waypoints.remove(index);
+            }
+            removed
+        })?;
+        Ok(removed)
+    }
+
+    /// Removes the waypoint markers and the path from the server.
+    pub fn clear(&self) -> Result<()> {
+        self.set_waypoints(Vec::new())?;
+        self.server.erase(self.path_name());
+        self.server.apply_changes()?;
+        Ok(())
+    }
+
+    fn waypoint_name(&self, index: usize) -> String {
+        format!("{}/{}", self.name, index)
+    }
+
+    fn path_name(&self) -> String {
+        format!("{}/path", self.name)
+    }
+
+    // Changes the list and, if `change` returns true, rebuilds all markers and notifies the
+    // streams. Waypoint markers are named by index, so any change but a drag renames them.
+    fn edit(&self, change: impl FnOnce(&mut Vec<Pose>) -> bool) -> Result<()> {
+        let (old_len, waypoints) = {
+            let mut waypoints = self.waypoints.lock();
+            let old_len = waypoints.len();
+            if !change(&mut waypoints) {
+                return Ok(());
+            }
+            (old_len, waypoints.clone())
+        };
+
+        for index in waypoints.len()..old_len {
+            self.server.erase(self.waypoint_name(index));
+        }
+        for (index, pose) in waypoints.iter().enumerate() {
+            self.insert_waypoint(index, pose.clone());
+        }
+        self.insert_path(&waypoints);
+        self.server.apply_changes()?;
+        let _ = self.updates.send(waypoints);
+        Ok(())
+    }
+
+    fn insert_waypoint(&self, index: usize, pose: Pose) {
+        let name = self.waypoint_name(index);
+        let mut sphere = Marker {
+            type_: Marker::SPHERE as i32,
+            color: color(0.1, 0.8, 0.2, 1.0),
+            ..Default::default()
+        };
+        sphere.pose.orientation = identity();
+        sphere.scale = Vector3 {
+            x: WAYPOINT_DIAMETER,
+            y: WAYPOINT_DIAMETER,
+            z: WAYPOINT_DIAMETER,
+        };
+        let mut controls = vec![InteractiveMarkerControl {
+            name: "menu".to_string(),
+            orientation: identity(),
+            interaction_mode: InteractiveMarkerControl::MENU as u8,
+            always_visible: true,
+            markers: vec![sphere],
+            ..Default::default()
+        }];
+        controls.extend(make_6dof_controls(false));
+
+        let mut marker = InteractiveMarker {
+            name: name.clone(),
+            description: (index + 1).to_string(),
+            pose,
+            scale: WAYPOINT_SCALE,
+            controls,
+            menu_entries: MENU
+                .iter()
+                .map(|&(id, title)| MenuEntry {
+                    id,
+                    title: title.to_string(),
+                    command_type: MenuEntry::FEEDBACK as u8,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        marker.header.frame_id = self.frame_id.clone();
+        self.server.insert(marker);
+
+        let editor = self.clone();
+        self.server
+            .set_event_callback(&name, move |event, _| match event {
+                FeedbackEvent::PoseUpdate { pose, .. } => editor.move_waypoint(index, pose),
+                FeedbackEvent::MenuSelect { entry_id } => {
+                    editor.server.report("Applying changes", editor.menu_select(index, entry_id))
+                }
+                _ => {}
+            });
+    }
+
+    fn insert_path(&self, waypoints: &[Pose]) {
+        let mut line = Marker {
+            type_: Marker::LINE_STRIP as i32,
+            color: color(0.1, 0.8, 0.2, 1.0),
+            points: waypoints.iter().map(|pose| pose.position.clone()).collect(),
+            ..Default::default()
+        };
+        line.pose.orientation = identity();
+        line.scale.x = PATH_WIDTH;
+        self.server
+            .insert(drawing(&self.path_name(), &self.frame_id, vec![line]));
+    }
+
+    // A drag only changes one pose, so the markers keep their names and only the path is redrawn
+    fn move_waypoint(&self, index: usize, pose: Pose) {
+        let waypoints = {
+            let mut waypoints = self.waypoints.lock();
+            let Some(waypoint) = waypoints.get_mut(index) else {
+                return;
+            };
+            *waypoint = pose;
+            waypoints.clone()
+        };
+        self.insert_path(&waypoints);
+        self.server.report("Applying changes", self.server.apply_changes());
+        let _ = self.updates.send(waypoints);
+    }
+
+    fn menu_select(&self, index: usize, entry_id: u32) -> Result<()> {
+        self.edit(|waypoints| {
+            if index >= waypoints.len() {
+                return false;
+            }
+            match entry_id {
+                INSERT_BEFORE => {
+                    let pose = between(waypoints, index, index.checked_sub(1), -1.0);
+                    waypoints.insert(index, pose);
+                }
+                INSERT_AFTER => {
+                    let next = (index + 1 < waypoints.len()).then_some(index + 1);
+                    let pose = between(waypoints, index, next, 1.0);
+                    waypoints.insert(index + 1, pose);
+                }
+                DELETE => {
+                    waypoints.remove(index);
+                }
+                MOVE_EARLIER if index > 0 => waypoints.swap(index, index - 1),
+                MOVE_LATER if index + 1 < waypoints.len() => waypoints.swap(index, index + 1),
+                _ => return false,
+            }
+            true
+        })
+    }
+}
+
+// A pose halfway between the waypoints at `index` and `other`, or without `other`, one
+// EXTEND_DISTANCE along x in `direction` from the waypoint at `index`. Takes the orientation
+// of the waypoint at `index`.
+fn between(waypoints: &[Pose], index: usize, other: Option<usize>, direction: f64) -> Pose {
+    let pose = &waypoints[index];
+    let position = match other {
+        Some(other) => {
+            let (a, b) = (&pose.position, &waypoints[other].position);
+            Point {
+                x: (a.x + b.x) / 2.0,
+                y: (a.y + b.y) / 2.0,
+                z: (a.z + b.z) / 2.0,
+            }
+        }
+        None => Point {
+            x: pose.position.x + direction * EXTEND_DISTANCE,
+            ..pose.position.clone()
+        },
+    };
+    Pose {
+        position,
+        orientation: pose.orientation.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drag, pose_at, select, server};
+    use crate::ServerConfig;
+    use futures::{FutureExt, StreamExt};
+
+    fn xs(editor: &WaypointEditor) -> Vec<f64> {
+        editor
+            .waypoints()
+            .iter()
+            .map(|pose| pose.position.x)
+            .collect()
+    }
+
+    #[test]
+    fn inserted_poses_go_between_or_beyond() {
+        let waypoints = [pose_at(0.0), pose_at(1.0)];
+        assert_eq!(between(&waypoints, 0, Some(1), 1.0).position.x, 0.5);
+        assert_eq!(between(&waypoints, 1, None, 1.0).position.x, 1.5);
+        assert_eq!(between(&waypoints, 0, None, -1.0).position.x, -0.5);
+    }
+
+    #[tokio::test]
+    async fn menus_edit_the_path() {
+        let server = server(ServerConfig::default());
+        let editor =
+            WaypointEditor::new(&server, "path", "map", [pose_at(0.0), pose_at(1.0)]).unwrap();
+        assert!(server.get("path/1").is_some());
+
+        server.handle_feedback(select("path/0", INSERT_AFTER));
+        assert_eq!(xs(&editor), [0.0, 0.5, 1.0]);
+        server.handle_feedback(select("path/2", MOVE_EARLIER));
+        assert_eq!(xs(&editor), [0.0, 1.0, 0.5]);
+        server.handle_feedback(select("path/0", DELETE));
+        assert_eq!(xs(&editor), [1.0, 0.5]);
+        assert!(server.get("path/2").is_none());
+
+        // Moving the first waypoint earlier changes nothing
+        server.handle_feedback(select("path/0", MOVE_EARLIER));
+        assert_eq!(xs(&editor), [1.0, 0.5]);
+    }
+
+    #[tokio::test]
+    async fn drags_are_streamed() {
+        let server = server(ServerConfig::default());
+        let editor = WaypointEditor::new(&server, "path", "map", [pose_at(0.0)]).unwrap();
+        let mut updates = editor.updates();
+
+        server.handle_feedback(drag("path/0", pose_at(2.0)));
+        let update = updates.next().now_or_never().flatten().unwrap();
+        assert_eq!(update[0].position.x, 2.0);
+        let path = server.get("path/path").unwrap();
+        assert_eq!(path.controls[0].markers[0].points[0].x, 2.0);
+
+        editor.clear().unwrap();
+        assert!(server.get("path/0").is_none());
+        assert!(server.get("path/path").is_none());
+    }
+}
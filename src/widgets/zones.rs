@@ -0,0 +1,268 @@
+use super::polygon::{CommitHook, PolygonEditor};
+use super::{color, identity};
+use crate::{sync, InteractiveMarkerServer};
+use r2r::geometry_msgs::msg::{Point, PolygonStamped};
+use r2r::std_msgs::msg::Header;
+use r2r::visualization_msgs::msg::{Marker, MarkerArray};
+use r2r::{Publisher, QosProfile};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Weak};
+
+const OVERLAY_NS: &str = "keep_out_zones";
+
+type Editors = sync::Mutex<BTreeMap<String, PolygonEditor>>;
+
+/// A keep-out zone, a polygon in the XY plane of a frame.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+    pub frame_id: String,
+    pub vertices: Vec<Point>,
+}
+
+/// Keep-out zones, each edited by a `PolygonEditor` named after the zone, so vertices are
+/// dragged and inserted or deleted through their menus. Whenever a zone is committed, it is
+/// published as a `PolygonStamped`, and all zones are published as a `MarkerArray` overlay.
+/// Zones can be saved to and loaded from YAML files.
+#[derive(Clone)]
+pub struct KeepOutZones {
+    server: InteractiveMarkerServer,
+    editors: Arc<Editors>,
+    polygon_pub: Publisher<PolygonStamped>,
+    overlay_pub: Publisher<MarkerArray>,
+}
+
+impl KeepOutZones {
+    pub fn new(
+        server: &InteractiveMarkerServer,
+        polygon_topic: &str,
+        overlay_topic: &str,
+    ) -> crate::Result<Self> {
+        let mut node = server.node.lock().unwrap();
+        let polygon_pub =
+            node.create_publisher::<PolygonStamped>(polygon_topic, QosProfile::default())?;
+        // Latched, so late RViz displays still get the overlay
+        let overlay_pub = node.create_publisher::<MarkerArray>(
+            overlay_topic,
+            QosProfile::default().transient_local(),
+        )?;
+        Ok(Self {
+            server: server.clone(),
+            editors: Arc::new(sync::Mutex::new(BTreeMap::new())),
+            polygon_pub,
+            overlay_pub,
+        })
+    }
+
+    /// Adds a zone, or replaces the zone with the same name, and publishes it.
+    pub fn add_zone(&self, name: &str, zone: Zone) -> crate::Result<()> {
+        self.remove_zone(name)?;
+        let editor = PolygonEditor::with_publisher(
+            &self.server,
+            name,
+            &zone.frame_id,
+            zone.vertices,
+            self.polygon_pub.clone(),
+            Some(self.overlay_hook()),
+        )?;
+        self.editors.lock().insert(name.to_string(), editor);
+        publish_overlay(&self.server, &self.overlay_pub, &self.editors);
+        Ok(())
+    }
+
+    /// Removes a zone and its markers, publishing it once more without vertices. Returns false
+    /// if there is no such zone.
+    pub fn remove_zone(&self, name: &str) -> crate::Result<bool> {
+        // Not locked while clearing, the overlay hook locks the editors
+        let Some(editor) = self.editors.lock().remove(name) else {
+            return Ok(false);
+        };
+        editor.clear()?;
+        Ok(true)
+    }
+
+    pub fn zones(&self) -> BTreeMap<String, Zone> {
+        zones(&self.editors)
+    }
+
+    /// Writes all zones to a YAML file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let yaml = serde_yaml::to_string(&self.zones())?;
+        std::fs::write(path, yaml)?;
+        Ok(())
+    }
+
+    /// Replaces all zones with the ones in a YAML file written by `save`.
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let zones: BTreeMap<String, Zone> = serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+        let names: Vec<String> = self.editors.lock().keys().cloned().collect();
+        for name in names {
+            self.remove_zone(&name)?;
+        }
+        for (name, zone) in zones {
+            self.add_zone(&name, zone)?;
+        }
+        Ok(())
+    }
+
+    // Republishes the overlay whenever an editor commits. The editors own their hooks, so the
+    // hooks only hold on to the editors weakly.
+    fn overlay_hook(&self) -> CommitHook {
+        let server = self.server.clone();
+        let overlay_pub = self.overlay_pub.clone();
+        let editors: Weak<Editors> = Arc::downgrade(&self.editors);
+        Arc::new(move |_: &[Point]| {
+            if let Some(editors) = editors.upgrade() {
+                publish_overlay(&server, &overlay_pub, &editors);
+            }
+        })
+    }
+}
+
+fn zones(editors: &Editors) -> BTreeMap<String, Zone> {
+    editors
+        .lock()
+        .iter()
+        .map(|(name, editor)| {
+            let zone = Zone {
+                frame_id: editor.frame_id().to_string(),
+                vertices: editor.vertices(),
+            };
+            (name.clone(), zone)
+        })
+        .collect()
+}
+
+// Republishes the whole overlay, clearing what was shown before
+fn publish_overlay(
+    server: &InteractiveMarkerServer,
+    publisher: &Publisher<MarkerArray>,
+    editors: &Editors,
+) {
+    let mut markers = vec![Marker {
+        ns: OVERLAY_NS.to_string(),
+        action: Marker::DELETEALL as i32,
+        ..Default::default()
+    }];
+    for (id, zone) in zones(editors).values().enumerate() {
+        markers.extend(zone_markers(zone, 2 * id as i32));
+    }
+    if let Err(e) = publisher.publish(&MarkerArray { markers }) {
+        server.logger.warn(format_args!(
+            "Failed to publish keep-out zone overlay: {}",
+            e
+        ));
+    }
+}
+
+// An outline and a translucent fill of the zone. The fill is a triangle fan, so it is only
+// exact for convex zones.
+fn zone_markers(zone: &Zone, first_id: i32) -> [Marker; 2] {
+    let header = Header {
+        frame_id: zone.frame_id.clone(),
+        ..Default::default()
+    };
+
+    let mut outline = Marker {
+        header: header.clone(),
+        ns: OVERLAY_NS.to_string(),
+        id: first_id,
+        type_: Marker::LINE_STRIP as i32,
+        color: color(1.0, 0.3, 0.0, 1.0),
+        points: zone.vertices.clone(),
+        ..Default::default()
+    };
+    outline.pose.orientation = identity();
+    outline.scale.x = 0.03;
+    if let Some(first) = zone.vertices.first() {
+        outline.points.push(first.clone());
+    }
+
+    let mut fill = Marker {
+        header,
+        ns: OVERLAY_NS.to_string(),
+        id: first_id + 1,
+        type_: Marker::TRIANGLE_LIST as i32,
+        color: color(1.0, 0.0, 0.0, 0.3),
+        ..Default::default()
+    };
+    fill.pose.orientation = identity();
+    fill.scale.x = 1.0;
+    fill.scale.y = 1.0;
+    fill.scale.z = 1.0;
+    if let Some((first, rest)) = zone.vertices.split_first() {
+        for pair in rest.windows(2) {
+            fill.points.extend([first.clone(), pair[0].clone(), pair[1].clone()]);
+        }
+    }
+
+    [outline, fill]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{feedback, pose_at, server};
+    use crate::ServerConfig;
+    use r2r::visualization_msgs::msg::InteractiveMarkerFeedback;
+
+    fn square() -> Zone {
+        let point = |x, y| Point { x, y, z: 0.0 };
+        Zone {
+            frame_id: "map".to_string(),
+            vertices: vec![
+                point(0.0, 0.0),
+                point(1.0, 0.0),
+                point(1.0, 1.0),
+                point(0.0, 1.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn overlay_closes_the_outline_and_fans_the_fill() {
+        let [outline, fill] = zone_markers(&square(), 4);
+        assert_eq!(outline.points.len(), 5);
+        assert_eq!(outline.points.first(), outline.points.last());
+        assert_eq!((outline.id, fill.id), (4, 5));
+        // Two triangles for four vertices
+        assert_eq!(fill.points.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn released_vertices_move_the_zone() {
+        let server = server(ServerConfig::default());
+        let zones = KeepOutZones::new(&server, "zone", "zones").unwrap();
+        zones.add_zone("z", square()).unwrap();
+        assert!(server.get("z/outline").is_some());
+        // The vertices have the insert and delete menu of the polygon editor
+        assert_eq!(server.get("z/3").unwrap().menu_entries.len(), 2);
+
+        server.handle_feedback(feedback(
+            "z/1",
+            InteractiveMarkerFeedback::MOUSE_UP as u8,
+            pose_at(2.0),
+        ));
+        assert_eq!(zones.zones()["z"].vertices[1].x, 2.0);
+
+        assert!(zones.remove_zone("z").unwrap());
+        assert!(server.get("z/0").is_none());
+        assert!(!zones.remove_zone("z").unwrap());
+    }
+
+    #[tokio::test]
+    async fn zones_survive_a_save_and_load() {
+        let server = server(ServerConfig::default());
+        let zones = KeepOutZones::new(&server, "zone", "zones").unwrap();
+        zones.add_zone("z", square()).unwrap();
+        let path = std::env::temp_dir().join(format!("zones_{}.yaml", std::process::id()));
+        zones.save(&path).unwrap();
+
+        zones.remove_zone("z").unwrap();
+        zones.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(zones.zones()["z"], square());
+        assert!(server.get("z/2").is_some());
+    }
+}